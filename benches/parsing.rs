@@ -0,0 +1,27 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::io::Read;
+use transactions_lib::generator::{GeneratorConfig, InputGenerator};
+use transactions_lib::{parse_input_with, InputParser};
+
+fn parsing(c: &mut Criterion) {
+    let mut input = Vec::new();
+    InputGenerator::new(GeneratorConfig {
+        transactions: 100_000,
+        ..GeneratorConfig::default()
+    })
+    .read_to_end(&mut input)
+    .expect("Generating the input can not fail");
+    let mut group = c.benchmark_group("parsing");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    for parser in [InputParser::StringRecord, InputParser::ByteRecord] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{:?}", parser)),
+            &input,
+            |b, input| b.iter(|| parse_input_with(input.as_slice(), parser).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::io::Read;
+use transactions_lib::generator::{GeneratorConfig, InputGenerator, Mix};
+use transactions_lib::{
+    process_input_then_write_output, process_input_with_config_then_write_output, Config,
+};
+
+const TRANSACTIONS: u32 = 200_000;
+
+fn generate_input(config: GeneratorConfig) -> Vec<u8> {
+    let mut input = Vec::new();
+    InputGenerator::new(config)
+        .read_to_end(&mut input)
+        .expect("Generating the input can not fail");
+    input
+}
+
+fn processing(c: &mut Criterion) {
+    let workloads = [
+        (
+            "deposits",
+            GeneratorConfig {
+                transactions: TRANSACTIONS,
+                mix: Mix {
+                    deposit: 1,
+                    withdrawal: 0,
+                    dispute: 0,
+                },
+                error_rate: 0.0,
+                ..GeneratorConfig::default()
+            },
+        ),
+        (
+            "mixed",
+            GeneratorConfig {
+                transactions: TRANSACTIONS,
+                ..GeneratorConfig::default()
+            },
+        ),
+        (
+            "errors",
+            GeneratorConfig {
+                transactions: TRANSACTIONS,
+                error_rate: 0.3,
+                ..GeneratorConfig::default()
+            },
+        ),
+    ];
+    let mut group = c.benchmark_group("processing");
+    for (name, generator_config) in workloads {
+        let input = generate_input(generator_config);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter(|| process_input_then_write_output(input.as_slice(), std::io::sink()))
+        });
+    }
+    group.finish();
+}
+
+fn parallel_processing(c: &mut Criterion) {
+    let input = generate_input(GeneratorConfig {
+        transactions: TRANSACTIONS,
+        ..GeneratorConfig::default()
+    });
+    let mut group = c.benchmark_group("parallel_processing");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    for workers in [1, 2, 4] {
+        let config = Config {
+            workers,
+            ..Config::default()
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(workers), &input, |b, input| {
+            b.iter(|| {
+                process_input_with_config_then_write_output(
+                    input.as_slice(),
+                    std::io::sink(),
+                    &config,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, processing, parallel_processing);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+//! Measures the peak heap usage of the processing on a generated input.
+//!
+//! The input is generated on the fly, so it does not take up any memory or disk space itself:
+//! ```
+//! cargo run --release --example memory_usage -- <number of transactions>
+//! ```
+//! There are 3 deposits for every withdrawal, without disputes and ignored lines.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use transactions_lib::generator::{GeneratorConfig, InputGenerator, Mix};
+use transactions_lib::process_input_then_write_output;
+
+struct CountingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(current, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let transaction_count: u32 = std::env::args()
+        .nth(1)
+        .map(|it| it.parse().expect("Invalid number of transactions"))
+        .unwrap_or(10_000_000);
+    let input = InputGenerator::new(GeneratorConfig {
+        transactions: transaction_count,
+        clients: u16::MAX,
+        mix: Mix {
+            deposit: 3,
+            withdrawal: 1,
+            dispute: 0,
+        },
+        error_rate: 0.0,
+        ..GeneratorConfig::default()
+    });
+    process_input_then_write_output(input, std::io::sink());
+    let peak = PEAK.load(Ordering::Relaxed);
+    println!(
+        "transactions: {}, peak heap usage: {:.1} MB, {:.1} bytes per transaction",
+        transaction_count,
+        peak as f64 / 1024.0 / 1024.0,
+        peak as f64 / transaction_count.max(1) as f64
+    );
+}
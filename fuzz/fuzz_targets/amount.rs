@@ -0,0 +1,12 @@
+//! Every parsed `Amount` is written in a format which is parsed back to the same value.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactions_lib::amount::Amount;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(amount) = Amount::parse_bytes(data) {
+        let formatted = amount.to_string();
+        assert_eq!(Amount::parse(&formatted), Some(amount), "{}", formatted);
+    }
+});
@@ -0,0 +1,10 @@
+//! Parses arbitrary input with both parsers, neither of them may panic on malformed lines.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactions_lib::{parse_input_with, InputParser};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_input_with(data, InputParser::ByteRecord);
+    let _ = parse_input_with(data, InputParser::StringRecord);
+});
@@ -0,0 +1,9 @@
+//! Processes arbitrary input end to end, the errors have to be reported in the output instead of panicking.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactions_lib::process_input_then_write_output;
+
+fuzz_target!(|data: &[u8]| {
+    process_input_then_write_output(data, std::io::sink());
+});
@@ -1,3 +1,4 @@
+use serde::{Serialize, Serializer};
 use std::fmt::{Display, Formatter};
 use std::ops::{AddAssign, SubAssign};
 
@@ -5,7 +6,7 @@ use std::ops::{AddAssign, SubAssign};
 ///
 /// This type stores floating point numbers as integers, multiplied by 10 000,
 /// to keep 4 decimal places. Any additional precision is lost after the conversion to Amount.
-#[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, PartialOrd, PartialEq)]
 pub struct Amount(u64);
 
 impl Amount {
@@ -25,6 +26,18 @@ impl Amount {
         Amount(0)
     }
 
+    /// Returns `None` if the sum does not fit into an `Amount`
+    /// ```
+    /// use transactions_lib::amount::Amount;
+    ///
+    /// let one = Amount::parse("1").unwrap();
+    /// assert_eq!(one.checked_add(one).unwrap().to_string(), "2");
+    /// assert_eq!(Amount::MAX.checked_add(one), None);
+    /// ```
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
     /// Converts an f64 to Amount. Any additional precision after the four places past the decimal will be truncated
     /// ```
     /// use transactions_lib::amount::Amount;
@@ -50,10 +63,92 @@ impl Amount {
     /// assert_eq!(Amount::parse("0.0000001").unwrap().to_string(), "0");
     /// ```
     pub fn parse(str: &str) -> Option<Amount> {
-        Some(Amount::from_f64(str.parse::<f64>().ok()?))
+        Amount::parse_bytes(str.as_bytes())
+    }
+
+    /// Same as `parse`, but a negative number is an error instead of zero,
+    /// e.g. for a limit or an expected balance, where a negative value is a mistake.
+    /// ```
+    /// use transactions_lib::amount::Amount;
+    ///
+    /// assert_eq!(Amount::parse_non_negative("12.5").unwrap().to_string(), "12.5");
+    /// assert_eq!(Amount::parse_non_negative("-12.5"), None);
+    /// ```
+    pub fn parse_non_negative(str: &str) -> Option<Amount> {
+        if str.starts_with('-') {
+            return None;
+        }
+        Amount::parse(str)
+    }
+
+    /// Same as `parse`, but it works on the raw bytes of the input, without UTF-8 validation.
+    ///
+    /// Simple decimal numbers are parsed digit by digit, so there is no precision loss caused by
+    /// an intermediate `f64`. Numbers out of range are truncated, and negative numbers are zero, the same way
+    /// as in `from_f64`. Other formats (e.g. `1e3`) are still parsed as `f64`.
+    /// ```
+    /// use transactions_lib::amount::Amount;
+    ///
+    /// assert_eq!(Amount::parse_bytes(b"2.9999").unwrap().to_string(), "2.9999");
+    /// assert_eq!(Amount::parse_bytes(b"1.23456").unwrap().to_string(), "1.2345");
+    /// assert_eq!(Amount::parse_bytes(b".5").unwrap().to_string(), "0.5");
+    /// assert_eq!(Amount::parse_bytes(b"-100.0").unwrap().to_string(), "0");
+    /// assert_eq!(Amount::parse_bytes(b"1e3").unwrap().to_string(), "1000");
+    /// assert_eq!(Amount::parse_bytes(b"1.2.3"), None);
+    /// assert_eq!(Amount::parse_bytes(b""), None);
+    /// ```
+    pub fn parse_bytes(bytes: &[u8]) -> Option<Amount> {
+        match parse_decimal(bytes) {
+            Some(amount) => Some(amount),
+            None => Some(Amount::from_f64(
+                std::str::from_utf8(bytes).ok()?.parse::<f64>().ok()?,
+            )),
+        }
+    }
+
+    /// The underlying integer value, for storing the amount in a compact binary format
+    pub(crate) fn to_raw(self) -> u64 {
+        self.0
+    }
+
+    /// The inverse of `to_raw`
+    pub(crate) fn from_raw(raw: u64) -> Amount {
+        Amount(raw)
     }
 }
 
+/// Parses `[+-]digits[.digits]`, returns `None` for anything else.
+fn parse_decimal(bytes: &[u8]) -> Option<Amount> {
+    let (negative, digits) = match bytes.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        Some((b'+', rest)) => (false, rest),
+        _ => (false, bytes),
+    };
+    let (integer, fraction) = match digits.iter().position(|&it| it == b'.') {
+        Some(dot_index) => (&digits[..dot_index], &digits[dot_index + 1..]),
+        None => (digits, &[][..]),
+    };
+    if (integer.is_empty() && fraction.is_empty())
+        || !integer.iter().chain(fraction).all(u8::is_ascii_digit)
+    {
+        return None;
+    }
+    if negative {
+        return Some(Amount::zero());
+    }
+    // the missing decimal places are zeros, and the excessive ones are truncated
+    let decimal_places = fraction.iter().chain(std::iter::repeat(&b'0')).take(4);
+    let value = integer
+        .iter()
+        .chain(decimal_places)
+        .fold(0u64, |value, digit| {
+            value
+                .saturating_mul(10)
+                .saturating_add((digit - b'0') as u64)
+        });
+    Some(Amount(value))
+}
+
 /// Makes it possible to use `+=` operator for `Amount`s
 /// ```
 /// use transactions_lib::amount::Amount;
@@ -62,9 +157,17 @@ impl Amount {
 /// amount += Amount::parse("123").unwrap();
 /// assert_eq!(amount.to_string(), "123.456");
 /// ```
+/// The result is truncated to `Amount::MAX` instead of overflowing, the same way as in `parse`:
+/// ```
+/// use transactions_lib::amount::Amount;
+///
+/// let mut amount = Amount::MAX;
+/// amount += Amount::parse("1").unwrap();
+/// assert_eq!(amount, Amount::MAX);
+/// ```
 impl AddAssign for Amount {
     fn add_assign(&mut self, rhs: Self) {
-        self.0 += rhs.0;
+        self.0 = self.0.saturating_add(rhs.0);
     }
 }
 
@@ -76,9 +179,18 @@ impl AddAssign for Amount {
 /// amount -= Amount::parse("123").unwrap();
 /// assert_eq!(amount.to_string(), "0.456");
 /// ```
+/// The result is truncated to zero instead of underflowing (the balances are checked before every subtraction,
+/// so it is only a safety net against panics and wrapped values):
+/// ```
+/// use transactions_lib::amount::Amount;
+///
+/// let mut amount = Amount::parse("1").unwrap();
+/// amount -= Amount::parse("2").unwrap();
+/// assert_eq!(amount, Amount::zero());
+/// ```
 impl SubAssign for Amount {
     fn sub_assign(&mut self, rhs: Self) {
-        self.0 -= rhs.0;
+        self.0 = self.0.saturating_sub(rhs.0);
     }
 }
 
@@ -114,3 +226,16 @@ impl Display for Amount {
         Ok(())
     }
 }
+
+/// Serializes the type as a string in the same format as `Display`, so no precision is lost
+/// by the consumer parsing it as a floating point number.
+/// ```
+/// use transactions_lib::amount::Amount;
+///
+/// assert_eq!(serde_json::to_string(&Amount::parse("0.3").unwrap()).unwrap(), "\"0.3\"");
+/// ```
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,227 @@
+use crate::client::{Client, ClientId};
+use crate::ledger::LedgerEventType;
+use crate::{Amount, HashMap};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Checks the balance invariants after every applied event, see `Config::verify`.
+///
+/// The sum of the client totals is tracked from the totals of the changed clients, while the expected sum
+/// is calculated only from the amounts of the events, so a bug in the balance arithmetic of `Client`
+/// makes them diverge.
+pub(crate) struct Audit {
+    /// The latest total of every client
+    totals: HashMap<ClientId, Amount>,
+    /// The sum of `totals`, in ten-thousandths
+    sum_of_totals: i128,
+    /// The sum of the deposits minus the withdrawals and the chargebacks (plus the totals of the restored clients)
+    expected_sum_of_totals: i128,
+    /// The first violation, it is returned by `take_violation`
+    violation: Option<InvariantViolation>,
+}
+
+/// The state of an `Audit` before an event of a client, see `Audit::snapshot`.
+pub(crate) struct AuditSnapshot {
+    client_id: ClientId,
+    total: Option<Amount>,
+    sum_of_totals: i128,
+    expected_sum_of_totals: i128,
+}
+
+/// A broken balance invariant, it stops the processing.
+#[derive(Debug)]
+pub(crate) struct InvariantViolation {
+    line_index: usize,
+    client_id: ClientId,
+    details: String,
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Balance invariant violated at line {} (client {}): {}",
+            self.line_index, self.client_id.0, self.details
+        )
+    }
+}
+
+impl Error for InvariantViolation {}
+
+impl Audit {
+    /// The clients restored from a checkpoint or a persistent store are trusted
+    pub fn new(clients: &HashMap<ClientId, Client>) -> Audit {
+        let totals: HashMap<ClientId, Amount> = clients
+            .iter()
+            .map(|(id, client)| (*id, client.total))
+            .collect();
+        let sum_of_totals = totals.values().map(|it| raw(*it)).sum();
+        Audit {
+            totals,
+            sum_of_totals,
+            expected_sum_of_totals: sum_of_totals,
+            violation: None,
+        }
+    }
+
+    /// Must be called after every applied event with the new state of its client
+    pub fn event_applied(
+        &mut self,
+        line_index: usize,
+        client_id: ClientId,
+        client: &Client,
+        typ: LedgerEventType,
+        amount: Amount,
+    ) {
+        if self.violation.is_some() {
+            return;
+        }
+        let previous_total = self.totals.insert(client_id, client.total);
+        self.sum_of_totals += raw(client.total) - previous_total.map(raw).unwrap_or(0);
+        self.expected_sum_of_totals += match typ {
+            LedgerEventType::Deposit => raw(amount),
+            LedgerEventType::Withdrawal | LedgerEventType::Chargeback => -raw(amount),
+            LedgerEventType::Dispute | LedgerEventType::Resolve => 0,
+        };
+
+        let details = if raw(client.available) + raw(client.held) != raw(client.total) {
+            format!(
+                "available ({}) + held ({}) != total ({}) after the {}",
+                client.available, client.held, client.total, typ
+            )
+        } else if self.sum_of_totals != self.expected_sum_of_totals {
+            format!(
+                "the sum of the client totals ({}) != deposits - withdrawals - chargebacks ({}) after the {}",
+                format_raw(self.sum_of_totals),
+                format_raw(self.expected_sum_of_totals),
+                typ
+            )
+        } else {
+            return;
+        };
+        self.violation = Some(InvariantViolation {
+            line_index,
+            client_id,
+            details,
+        });
+    }
+
+    pub fn take_violation(&mut self) -> Option<InvariantViolation> {
+        self.violation.take()
+    }
+
+    /// The sums and the latest total of the client, see `restore`
+    pub fn snapshot(&self, client_id: ClientId) -> AuditSnapshot {
+        AuditSnapshot {
+            client_id,
+            total: self.totals.get(&client_id).copied(),
+            sum_of_totals: self.sum_of_totals,
+            expected_sum_of_totals: self.expected_sum_of_totals,
+        }
+    }
+
+    /// Undoes the events of the client since `snapshot`
+    pub fn restore(&mut self, snapshot: AuditSnapshot) {
+        match snapshot.total {
+            Some(total) => self.totals.insert(snapshot.client_id, total),
+            None => self.totals.remove(&snapshot.client_id),
+        };
+        self.sum_of_totals = snapshot.sum_of_totals;
+        self.expected_sum_of_totals = snapshot.expected_sum_of_totals;
+    }
+}
+
+/// The sums can be out of the range of `Amount` (e.g. negative after a bug), so they are calculated on `i128`s
+fn raw(amount: Amount) -> i128 {
+    amount.to_raw() as i128
+}
+
+fn format_raw(value: i128) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let value = value.unsigned_abs();
+    match u64::try_from(value).map(Amount::from_raw) {
+        Ok(amount) => format!("{}{}", sign, amount),
+        Err(_) => format!("{}{}.{:04}", sign, value / 10_000, value % 10_000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(available: &str, held: &str, total: &str) -> Client {
+        Client {
+            available: Amount::parse(available).unwrap(),
+            held: Amount::parse(held).unwrap(),
+            total: Amount::parse(total).unwrap(),
+            locked: false,
+        }
+    }
+
+    #[test]
+    fn reports_the_first_inconsistent_client() {
+        let mut audit = Audit::new(&HashMap::default());
+        let one = Amount::parse("1").unwrap();
+        audit.event_applied(
+            1,
+            ClientId(1),
+            &client("1", "0", "1"),
+            LedgerEventType::Deposit,
+            one,
+        );
+        assert!(audit.take_violation().is_none());
+        audit.event_applied(
+            2,
+            ClientId(2),
+            &client("1", "1", "1"),
+            LedgerEventType::Deposit,
+            one,
+        );
+        audit.event_applied(
+            3,
+            ClientId(3),
+            &client("2", "2", "1"),
+            LedgerEventType::Deposit,
+            one,
+        );
+        assert_eq!(
+            "Balance invariant violated at line 2 (client 2): available (1) + held (1) != total (1) after the deposit",
+            audit.take_violation().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn reports_the_first_inconsistent_sum_of_totals() {
+        let mut clients = HashMap::default();
+        clients.insert(ClientId(1), client("5", "0", "5"));
+        let mut audit = Audit::new(&clients);
+        let two = Amount::parse("2").unwrap();
+        audit.event_applied(
+            4,
+            ClientId(1),
+            &client("3", "0", "3"),
+            LedgerEventType::Withdrawal,
+            two,
+        );
+        assert!(audit.take_violation().is_none());
+        // the total of the client did not change by the deposit
+        audit.event_applied(
+            7,
+            ClientId(1),
+            &client("3", "0", "3"),
+            LedgerEventType::Deposit,
+            two,
+        );
+        audit.event_applied(
+            8,
+            ClientId(2),
+            &client("1", "0", "1"),
+            LedgerEventType::Withdrawal,
+            two,
+        );
+        assert_eq!(
+            "Balance invariant violated at line 7 (client 1): the sum of the client totals (3) != deposits - withdrawals - chargebacks (5) after the deposit",
+            audit.take_violation().unwrap().to_string()
+        );
+    }
+}
@@ -1,13 +1,411 @@
 use std::fs::File;
-use std::io::BufReader;
-use transactions_lib::process_input_then_write_output;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::slice::Iter;
+use transactions_lib::generator::{GeneratorConfig, InputGenerator};
+use transactions_lib::{
+    process_input_then_write_outputs, query_input_as_of_then_write_output,
+    reconcile_input_then_write_report, resume_input_then_write_outputs,
+    run_scenarios_then_write_report, AsOf, ChangesOutput, CheckpointConfig, Config, LedgerFormat,
+    LedgerOutput, Outputs, Server, SummaryFormat, SummaryOutput,
+};
+
+const USAGE: &str = "Usage:
+    transactions [OPTIONS] [--ledger <output file> [--ledger-format csv|json] [--ledger-client <client id>]]
+                 [--rejections <output file>] [--changes <output file> [--changes-interval <lines>]]
+                 [--checkpoint <file> [--checkpoint-interval <lines>] [--resume]]
+                 [--summary | --summary-json <output file>] [--metrics <output file>] [--alerts <output file>]
+                 <input.csv>
+    transactions query [OPTIONS] (--as-of <line> | --as-of-timestamp <timestamp>) [--client <client id>] <input.csv>
+    transactions reconcile [OPTIONS] --expected <balances.csv> <input.csv>
+    transactions serve [OPTIONS] [--port <port>]
+    transactions test <scenario directory>
+    transactions gen [--transactions <count>] [--clients <count>] [--mix <deposit>,<withdrawal>,<dispute>]
+                     [--error-rate <ratio>] [--seed <seed>]
+
+OPTIONS:
+    --store-file <file>             Store the deposits in <file> instead of the memory
+    --store-cache <count>           The number of deposits cached in memory when --store-file is used (default: 1000000)
+    --sqlite <file>                 Persist the clients and the deposits in the SQLite database <file>, and continue
+                                    from its state if it exists (requires the sqlite feature)
+    --dispute-window-days <days>    Deposits older than this can not be disputed (requires the timestamp column)
+    --workers <count>               Distribute the clients between <count> threads (default: 1)
+    --unsorted                      Do not sort the client states by their Client IDs
+    --verify                        Check the balance invariants after every event, and stop at the first violation
+    --max-withdrawal <amount>       Reject the withdrawals larger than <amount>
+    --max-withdrawal-volume <amount>
+                                    Reject the withdrawals which would increase the withdrawals of the client
+                                    within the window above <amount>
+    --withdrawal-volume-window-hours <hours>
+                                    The length of the rolling window of --max-withdrawal-volume (default: 24)
+    --max-balance <amount>          Reject the deposits which would increase the total funds of the client above <amount>
+    --limits <limits.csv>           Override the limits of the listed clients, the columns:
+                                    client, max_withdrawal, max_withdrawal_volume, max_balance
+    --fraud-rule <rule>             Evaluate a fraud rule after every line, it can be repeated. The rules:
+                                    frequent_disputes:max=<count>,events=<count>
+                                    deposit_then_full_withdrawal
+                                    repeated_failed_withdrawals:max=<count>,events=<count>
+                                    with an optional action=flag|lock option (default: flag)
+    --log-level <level>             Log the rejected lines and the errors to stderr at or above the level:
+                                    off, error, warn, info, debug or trace (default: off)";
+
+const DEFAULT_PORT: u16 = 8080;
+
+const DEFAULT_CHECKPOINT_INTERVAL: usize = 1_000_000;
 
 fn main() {
-    if let Some(input_filename) = std::env::args().skip(1).next() {
-        let file_reader =
-            BufReader::new(File::open(input_filename).expect("Could not open the input file"));
-        process_input_then_write_output(file_reader, std::io::stdout());
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = init_logger(&mut args) {
+        println!("{}\n{}", err, USAGE);
+        return;
+    }
+    let result = match args.first().map(String::as_str) {
+        None => Err("Input file path is missing.".to_owned()),
+        Some("query") => run_query(&args[1..]),
+        Some("reconcile") => run_reconcile(&args[1..]),
+        Some("serve") => run_serve(&args[1..]),
+        Some("test") => run_test(&args[1..]),
+        Some("gen") => run_gen(&args[1..]),
+        Some(_) => run_process(&args),
+    };
+    if let Err(err) = result {
+        println!("{}\n{}", err, USAGE);
+    }
+}
+
+/// Handles the default command, which prints the final client states.
+fn run_process(args: &[String]) -> Result<(), String> {
+    let mut config = Config::default();
+    let mut ledger_filename: Option<&str> = None;
+    let mut ledger_format = LedgerFormat::Csv;
+    let mut ledger_client_id: Option<u16> = None;
+    let mut rejections_filename: Option<&str> = None;
+    let mut changes_filename: Option<&str> = None;
+    let mut changes_interval: Option<usize> = None;
+    let mut checkpoint_filename: Option<&str> = None;
+    let mut checkpoint_interval = DEFAULT_CHECKPOINT_INTERVAL;
+    let mut resume = false;
+    let mut summary = false;
+    let mut summary_json_filename: Option<&str> = None;
+    let mut metrics_filename: Option<&str> = None;
+    let mut alerts_filename: Option<&str> = None;
+    let mut input_filename: Option<&str> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ledger" => {
+                ledger_filename = Some(args.next().ok_or("Missing value for --ledger")?);
+            }
+            "--ledger-format" => {
+                ledger_format = parse_arg_value(args.next(), "--ledger-format")?;
+            }
+            "--ledger-client" => {
+                ledger_client_id = Some(parse_arg_value(args.next(), "--ledger-client")?);
+            }
+            "--rejections" => {
+                rejections_filename = Some(args.next().ok_or("Missing value for --rejections")?);
+            }
+            "--changes" => {
+                changes_filename = Some(args.next().ok_or("Missing value for --changes")?);
+            }
+            "--changes-interval" => {
+                changes_interval = Some(parse_arg_value(args.next(), "--changes-interval")?);
+            }
+            "--checkpoint" => {
+                checkpoint_filename = Some(args.next().ok_or("Missing value for --checkpoint")?);
+            }
+            "--checkpoint-interval" => {
+                checkpoint_interval = parse_arg_value(args.next(), "--checkpoint-interval")?;
+            }
+            "--resume" => resume = true,
+            "--summary" => summary = true,
+            "--alerts" => {
+                alerts_filename = Some(args.next().ok_or("Missing value for --alerts")?);
+            }
+            "--metrics" => {
+                metrics_filename = Some(args.next().ok_or("Missing value for --metrics")?);
+            }
+            "--summary-json" => {
+                summary_json_filename =
+                    Some(args.next().ok_or("Missing value for --summary-json")?);
+            }
+            _ if parse_config_option(arg, &mut args, &mut config)? => {}
+            _ => input_filename = Some(arg),
+        }
+    }
+    if summary && summary_json_filename.is_some() {
+        return Err("--summary can not be used together with --summary-json".into());
+    }
+    if changes_filename.is_some() && config.workers > 1 {
+        return Err("--changes can not be used together with --workers".into());
+    }
+    if let Some(path) = checkpoint_filename {
+        if config.workers > 1 {
+            return Err("--checkpoint can not be used together with --workers".into());
+        }
+        config.checkpoint = Some(CheckpointConfig {
+            path: path.into(),
+            interval: checkpoint_interval.max(1),
+        });
+    } else if resume {
+        return Err("--resume requires --checkpoint".into());
+    }
+    let input = open_input(input_filename.ok_or("Input file path is missing.")?);
+    let mut ledger_writer = ledger_filename.map(|it| create_output(it, "ledger"));
+    let mut rejections_writer = rejections_filename.map(|it| create_output(it, "rejections"));
+    let mut changes_writer = changes_filename.map(|it| create_output(it, "changes"));
+    let mut metrics_writer = metrics_filename.map(|it| create_output(it, "metrics"));
+    let mut alerts_writer = alerts_filename.map(|it| create_output(it, "alerts"));
+    let mut summary_writer: Option<Box<dyn Write>> = match summary_json_filename {
+        Some(filename) => Some(Box::new(create_output(filename, "summary"))),
+        None => summary.then(|| Box::new(std::io::stderr()) as Box<dyn Write>),
+    };
+    let outputs = Outputs {
+        ledger: ledger_writer.as_mut().map(|writer| LedgerOutput {
+            writer: writer as &mut dyn Write,
+            format: ledger_format,
+            client_id: ledger_client_id,
+        }),
+        rejections: rejections_writer
+            .as_mut()
+            .map(|writer| writer as &mut dyn Write),
+        changes: changes_writer.as_mut().map(|writer| ChangesOutput {
+            writer: writer as &mut dyn Write,
+            interval: changes_interval,
+        }),
+        summary: summary_writer.as_mut().map(|writer| SummaryOutput {
+            writer: writer.as_mut(),
+            format: if summary_json_filename.is_some() {
+                SummaryFormat::Json
+            } else {
+                SummaryFormat::Text
+            },
+        }),
+        metrics: metrics_writer
+            .as_mut()
+            .map(|writer| writer as &mut dyn Write),
+        alerts: alerts_writer
+            .as_mut()
+            .map(|writer| writer as &mut dyn Write),
+    };
+    if resume {
+        resume_input_then_write_outputs(input, std::io::stdout(), outputs, &config);
     } else {
-        println!("Input file path is missing.");
+        process_input_then_write_outputs(input, std::io::stdout(), outputs, &config);
     }
+    Ok(())
+}
+
+/// Handles the `query` command, which prints the client states as of a given input line.
+fn run_query(args: &[String]) -> Result<(), String> {
+    let mut config = Config::default();
+    let mut as_of: Option<AsOf> = None;
+    let mut client_id: Option<u16> = None;
+    let mut input_filename: Option<&str> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--as-of" => {
+                as_of = Some(AsOf::Line(parse_arg_value(args.next(), "--as-of")?));
+            }
+            "--as-of-timestamp" => {
+                as_of = Some(AsOf::Timestamp(parse_arg_value(
+                    args.next(),
+                    "--as-of-timestamp",
+                )?));
+            }
+            "--client" => {
+                client_id = Some(parse_arg_value(args.next(), "--client")?);
+            }
+            _ if parse_config_option(arg, &mut args, &mut config)? => {}
+            _ => input_filename = Some(arg),
+        }
+    }
+    let as_of = as_of.ok_or("--as-of is missing.")?;
+    let input_filename = input_filename.ok_or("Input file path is missing.")?;
+    query_input_as_of_then_write_output(
+        open_input(input_filename),
+        as_of,
+        client_id,
+        std::io::stdout(),
+        &config,
+    );
+    Ok(())
+}
+
+/// Handles the `reconcile` command, which prints the differences between the processed client states
+/// and an expected balances file. The process exits with 1 if there is any difference.
+fn run_reconcile(args: &[String]) -> Result<(), String> {
+    let mut config = Config::default();
+    let mut expected_filename: Option<&str> = None;
+    let mut input_filename: Option<&str> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--expected" => {
+                expected_filename = Some(args.next().ok_or("Missing value for --expected")?);
+            }
+            _ if parse_config_option(arg, &mut args, &mut config)? => {}
+            _ => input_filename = Some(arg),
+        }
+    }
+    let expected_filename = expected_filename.ok_or("--expected is missing.")?;
+    let input_filename = input_filename.ok_or("Input file path is missing.")?;
+    let expected = File::open(expected_filename)
+        .map_err(|err| format!("Could not open the expected balances file: {}", err))?;
+    let reconciled = reconcile_input_then_write_report(
+        open_input(input_filename),
+        BufReader::new(expected),
+        std::io::stdout(),
+        &config,
+    );
+    if !reconciled {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Handles the `serve` command, which accepts the transactions over HTTP on the loopback interface.
+fn run_serve(args: &[String]) -> Result<(), String> {
+    let mut config = Config::default();
+    let mut port = DEFAULT_PORT;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => port = parse_arg_value(args.next(), "--port")?,
+            _ if parse_config_option(arg, &mut args, &mut config)? => {}
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    let server = Server::bind(port, &config)
+        .map_err(|err| format!("Could not start the server: {}", err))?;
+    if let Ok(addr) = server.local_addr() {
+        println!("Listening on http://{}", addr);
+    }
+    server
+        .run()
+        .map_err(|err| format!("The server has stopped: {}", err))
+}
+
+/// Handles the `test` command, which runs the scenarios in the subdirectories of the given directory.
+/// The process exits with 1 if any of them failed.
+fn run_test(args: &[String]) -> Result<(), String> {
+    let dir = match args {
+        [dir] => dir,
+        _ => return Err("Scenario directory is missing.".into()),
+    };
+    if !run_scenarios_then_write_report(Path::new(dir), std::io::stdout()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Handles the `gen` command, which writes a generated input csv to the standard output.
+fn run_gen(args: &[String]) -> Result<(), String> {
+    let mut config = GeneratorConfig::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--transactions" => config.transactions = parse_arg_value(args.next(), arg)?,
+            "--clients" => config.clients = parse_arg_value(args.next(), arg)?,
+            "--mix" => config.mix = parse_arg_value(args.next(), arg)?,
+            "--error-rate" => config.error_rate = parse_arg_value(args.next(), arg)?,
+            "--seed" => config.seed = parse_arg_value(args.next(), arg)?,
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    let mut output = BufWriter::new(std::io::stdout().lock());
+    std::io::copy(&mut InputGenerator::new(config), &mut output)
+        .and_then(|_| output.flush())
+        .map_err(|err| format!("Could not write the output: {}", err))?;
+    Ok(())
+}
+
+/// Handles the options of the `Config` which are common for every command, see `Config::set_option`.
+/// Returns `false` if `arg` is not such an option.
+fn parse_config_option(
+    arg: &str,
+    args: &mut Iter<String>,
+    config: &mut Config,
+) -> Result<bool, String> {
+    if !arg.starts_with("--") {
+        return Ok(false);
+    }
+    if Config::is_flag_option(arg) {
+        return config.set_option(arg, None);
+    }
+    // the next argument is consumed only if it is the value of an option
+    let is_option = config.set_option(arg, args.as_slice().first().map(String::as_str))?;
+    if is_option {
+        args.next();
+    }
+    Ok(is_option)
+}
+
+/// Removes the `--log-level` option from the arguments (it is accepted by every command),
+/// and installs the stderr logger with the given level.
+fn init_logger(args: &mut Vec<String>) -> Result<(), String> {
+    let Some(index) = args.iter().position(|arg| arg == "--log-level") else {
+        return Ok(());
+    };
+    let level: log::LevelFilter = parse_arg_value(args.get(index + 1), "--log-level")?;
+    args.drain(index..index + 2);
+    log::set_logger(&StderrLogger).map_err(|err| err.to_string())?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Writes every record as a single line to stderr: the level, the message, then the `key=value` pairs.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = format!("{} {}", record.level(), record.args());
+        let _ = record.key_values().visit(&mut KeyValueWriter(&mut line));
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+struct KeyValueWriter<'a>(&'a mut String);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for KeyValueWriter<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = value.to_string();
+        // the values with spaces are quoted, so every pair can be split at the spaces
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            self.0.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            self.0.push_str(&format!(" {}={}", key, value));
+        }
+        Ok(())
+    }
+}
+
+fn parse_arg_value<T: std::str::FromStr>(value: Option<&String>, name: &str) -> Result<T, String> {
+    value
+        .and_then(|it| it.parse().ok())
+        .ok_or_else(|| format!("Invalid or missing value for {}", name))
+}
+
+fn open_input(input_filename: &str) -> BufReader<File> {
+    BufReader::new(File::open(input_filename).expect("Could not open the input file"))
+}
+
+fn create_output(filename: &str, name: &str) -> BufWriter<File> {
+    BufWriter::new(
+        File::create(filename).unwrap_or_else(|_| panic!("Could not create the {} file", name)),
+    )
 }
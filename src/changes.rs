@@ -0,0 +1,104 @@
+use crate::client::{Client, ClientId};
+use crate::HashMap;
+use std::io::Write;
+
+/// Where and how often the changed client states are written during the processing.
+pub struct ChangesOutput<'a> {
+    pub writer: &'a mut dyn Write,
+    /// If present, the changed clients are collected and written only after every `interval` input lines
+    /// (and at the end of the input), each client once with its latest state.
+    /// Otherwise a row is written right after every change.
+    pub interval: Option<usize>,
+}
+
+/// Writes the state of a client every time it is changed by an input line,
+/// so the changes can be consumed before the whole input is processed.
+///
+/// Every applied line changes the balances or the lock state of its client. A rejected line does not change
+/// the balances, but a fraud rule can still lock its client, so such a line is written as a change as well.
+pub(crate) struct ChangeStream<'a> {
+    writer: &'a mut dyn Write,
+    interval: Option<usize>,
+    /// The clients changed since the last write, with the line of their latest change
+    changed_clients: HashMap<ClientId, usize>,
+    lines_since_last_write: usize,
+}
+
+impl<'a> ChangeStream<'a> {
+    /// Writes the header into the output
+    pub fn new(output: ChangesOutput<'a>) -> std::io::Result<ChangeStream<'a>> {
+        writeln!(
+            output.writer,
+            "line, client, available, held, total, locked"
+        )?;
+        Ok(ChangeStream {
+            writer: output.writer,
+            interval: output.interval,
+            changed_clients: HashMap::default(),
+            lines_since_last_write: 0,
+        })
+    }
+
+    /// Must be called after every processed input line, `changed_client_id` is present if the line was applied
+    /// or it locked its client.
+    pub fn line_processed(
+        &mut self,
+        line_index: usize,
+        changed_client_id: Option<ClientId>,
+        clients: &HashMap<ClientId, Client>,
+    ) -> std::io::Result<()> {
+        match self.interval {
+            None => {
+                if let Some(client_id) = changed_client_id {
+                    write_row(self.writer, line_index, client_id, &clients[&client_id])?;
+                    self.writer.flush()?;
+                }
+            }
+            Some(interval) => {
+                if let Some(client_id) = changed_client_id {
+                    self.changed_clients.insert(client_id, line_index);
+                }
+                self.lines_since_last_write += 1;
+                if self.lines_since_last_write >= interval {
+                    self.write_changed_clients(clients)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining changes at the end of the input
+    pub fn finish(&mut self, clients: &HashMap<ClientId, Client>) -> std::io::Result<()> {
+        self.write_changed_clients(clients)
+    }
+
+    /// Writes the changed clients ordered by their Client IDs
+    fn write_changed_clients(
+        &mut self,
+        clients: &HashMap<ClientId, Client>,
+    ) -> std::io::Result<()> {
+        self.lines_since_last_write = 0;
+        if self.changed_clients.is_empty() {
+            return Ok(());
+        }
+        let mut changed_clients: Vec<(ClientId, usize)> = self.changed_clients.drain().collect();
+        changed_clients.sort();
+        for (client_id, line_index) in changed_clients {
+            write_row(self.writer, line_index, client_id, &clients[&client_id])?;
+        }
+        self.writer.flush()
+    }
+}
+
+fn write_row(
+    writer: &mut dyn Write,
+    line_index: usize,
+    client_id: ClientId,
+    client: &Client,
+) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "{},{},{},{},{},{}",
+        line_index, client_id.0, client.available, client.held, client.total, client.locked
+    )
+}
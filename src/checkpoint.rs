@@ -0,0 +1,313 @@
+use crate::client::{Client, ClientId};
+use crate::engine::Engine;
+use crate::fraud::{Alert, FraudAction};
+use crate::store::SavedState;
+use crate::transaction::{StoredDeposit, TransactionId};
+use crate::{Amount, Config};
+use roaring::RoaringBitmap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The first bytes of a checkpoint file, with the version of the format
+const MAGIC: &[u8; 8] = b"TXCKPT04";
+
+/// The counts in the file are trusted only up to this many preallocated elements,
+/// so a corrupted count can not allocate a huge vector, the vectors grow beyond it
+const MAX_PREALLOCATED: u64 = 65536;
+
+const DEPOSIT_MARKER: u8 = 1;
+const END_MARKER: u8 = 0;
+
+/// Where and how often the state of the processing is saved, so a stopped run can be resumed
+/// by `resume_input_then_write_outputs`.
+#[derive(Debug, Clone)]
+pub struct CheckpointConfig {
+    pub path: PathBuf,
+    /// A checkpoint is written after every `interval` input lines, and at the end of the input.
+    /// Every checkpoint contains every stored deposit, so a short interval is expensive with a lot of deposits.
+    pub interval: usize,
+}
+
+/// A point of the input between two lines
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct InputPosition {
+    /// The offset of the next line from the start of the input
+    pub byte_offset: u64,
+    /// The index of the last line before this position, 0 means the start of the input (before the header)
+    pub line_index: usize,
+}
+
+/// Writes the checkpoints during the processing.
+pub(crate) struct Checkpointer<'a> {
+    config: &'a CheckpointConfig,
+    lines_since_last_checkpoint: usize,
+}
+
+impl<'a> Checkpointer<'a> {
+    pub fn new(config: &'a CheckpointConfig) -> Checkpointer<'a> {
+        Checkpointer {
+            config,
+            lines_since_last_checkpoint: 0,
+        }
+    }
+
+    /// Must be called after every applied input line, with the position after the line
+    pub fn line_processed(
+        &mut self,
+        position: InputPosition,
+        engine: &mut Engine,
+    ) -> std::io::Result<()> {
+        self.lines_since_last_checkpoint += 1;
+        if self.lines_since_last_checkpoint >= self.config.interval {
+            self.write(position, engine)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the final checkpoint at the end of the input, so resuming a finished run does not apply anything
+    pub fn finish(&mut self, position: InputPosition, engine: &mut Engine) -> std::io::Result<()> {
+        self.write(position, engine)
+    }
+
+    /// The checkpoint is written into a temporary file first, and it replaces the previous one only when
+    /// it is complete, so a crash during the writing leaves the previous checkpoint intact.
+    fn write(&mut self, position: InputPosition, engine: &mut Engine) -> std::io::Result<()> {
+        self.lines_since_last_checkpoint = 0;
+        let mut temp_path = self.config.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        write_checkpoint(&mut writer, position, engine)?;
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&temp_path, &self.config.path)
+    }
+}
+
+/// Reads the checkpoint at `path` and restores the engine from it.
+/// Returns `None` if there is no checkpoint yet.
+pub(crate) fn read_checkpoint(
+    path: &Path,
+    config: &Config,
+) -> std::io::Result<Option<(InputPosition, Engine)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut reader = BufReader::new(file);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "The file is not a checkpoint of this version",
+        ));
+    }
+    let position = InputPosition {
+        byte_offset: read_u64(&mut reader)?,
+        line_index: read_u64(&mut reader)? as usize,
+    };
+    let current_time = match read_u8(&mut reader)? {
+        0 => None,
+        _ => Some(read_u64(&mut reader)?),
+    };
+    let client_count = read_u64(&mut reader)?;
+    let mut clients = Vec::with_capacity(capacity(client_count));
+    for _ in 0..client_count {
+        let id = ClientId(read_u16(&mut reader)?);
+        clients.push((
+            id,
+            Client {
+                available: Amount::from_raw(read_u64(&mut reader)?),
+                held: Amount::from_raw(read_u64(&mut reader)?),
+                total: Amount::from_raw(read_u64(&mut reader)?),
+                locked: read_u8(&mut reader)? != 0,
+            },
+        ));
+    }
+    let transaction_ids = RoaringBitmap::deserialize_from(&mut reader)?;
+    let evicted_deposits = RoaringBitmap::deserialize_from(&mut reader)?;
+    let deposits_by_time_count = read_u64(&mut reader)?;
+    let mut deposits_by_time = Vec::with_capacity(capacity(deposits_by_time_count));
+    for _ in 0..deposits_by_time_count {
+        deposits_by_time.push((
+            read_u64(&mut reader)?,
+            TransactionId(read_u32(&mut reader)?),
+        ));
+    }
+    let recent_withdrawal_count = read_u64(&mut reader)?;
+    let mut recent_withdrawals = Vec::with_capacity(capacity(recent_withdrawal_count));
+    for _ in 0..recent_withdrawal_count {
+        recent_withdrawals.push((
+            ClientId(read_u16(&mut reader)?),
+            read_u64(&mut reader)?,
+            Amount::from_raw(read_u64(&mut reader)?),
+        ));
+    }
+    let fraud_rule_state_count = read_u64(&mut reader)?;
+    let mut fraud_rule_states = Vec::with_capacity(capacity(fraud_rule_state_count));
+    for _ in 0..fraud_rule_state_count {
+        fraud_rule_states.push((
+            ClientId(read_u16(&mut reader)?),
+            read_u64(&mut reader)? as usize,
+            read_bytes(&mut reader)?,
+        ));
+    }
+    let alert_count = read_u64(&mut reader)?;
+    let mut alerts = Vec::with_capacity(capacity(alert_count));
+    for _ in 0..alert_count {
+        alerts.push(Alert {
+            line: read_u64(&mut reader)? as usize,
+            client_id: read_u16(&mut reader)?,
+            action: match read_u8(&mut reader)? {
+                0 => FraudAction::Flag,
+                _ => FraudAction::Lock,
+            },
+            rule: read_string(&mut reader)?,
+            reason: read_string(&mut reader)?,
+        });
+    }
+    let mut engine = Engine::with_state(
+        config,
+        SavedState {
+            clients,
+            transaction_ids,
+            evicted_deposits,
+            deposits_by_time,
+            recent_withdrawals,
+            fraud_rule_states,
+            alerts,
+            current_time,
+            last_line: position.line_index,
+        },
+    )?;
+    while read_u8(&mut reader)? == DEPOSIT_MARKER {
+        let id = TransactionId(read_u32(&mut reader)?);
+        let mut buf = [0u8; StoredDeposit::SIZE];
+        reader.read_exact(&mut buf)?;
+        engine
+            .deposits_mut()
+            .insert(id, StoredDeposit::from_bytes(&buf))?;
+    }
+    Ok(Some((position, engine)))
+}
+
+/// The format is a little-endian binary: the position, the current time, the clients, the Transaction IDs,
+/// the evicted deposits, the deposits with timestamp in the order of their arrival,
+/// the withdrawals within the volume limit's window, the states of the fraud rules, the alerts,
+/// then the deposits and the withdrawals of the deposit store.
+/// The ledger is not saved.
+///
+/// Every checkpoint writes the whole state, including every stored deposit (the file based store reads them back
+/// from its file), so its cost grows with the stored deposits, and it is paid after every `CheckpointConfig::interval`
+/// lines.
+fn write_checkpoint(
+    writer: &mut impl Write,
+    position: InputPosition,
+    engine: &mut Engine,
+) -> std::io::Result<()> {
+    let state = engine.saved_state();
+    writer.write_all(MAGIC)?;
+    writer.write_all(&position.byte_offset.to_le_bytes())?;
+    writer.write_all(&(position.line_index as u64).to_le_bytes())?;
+    match state.current_time {
+        Some(current_time) => {
+            writer.write_all(&[1])?;
+            writer.write_all(&current_time.to_le_bytes())?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    writer.write_all(&(state.clients.len() as u64).to_le_bytes())?;
+    for (id, client) in state.clients.iter() {
+        writer.write_all(&id.0.to_le_bytes())?;
+        writer.write_all(&client.available.to_raw().to_le_bytes())?;
+        writer.write_all(&client.held.to_raw().to_le_bytes())?;
+        writer.write_all(&client.total.to_raw().to_le_bytes())?;
+        writer.write_all(&[client.locked as u8])?;
+    }
+    state.transaction_ids.serialize_into(&mut *writer)?;
+    state.evicted_deposits.serialize_into(&mut *writer)?;
+    writer.write_all(&(state.deposits_by_time.len() as u64).to_le_bytes())?;
+    for (timestamp, id) in state.deposits_by_time.iter() {
+        writer.write_all(&timestamp.to_le_bytes())?;
+        writer.write_all(&id.0.to_le_bytes())?;
+    }
+    writer.write_all(&(state.recent_withdrawals.len() as u64).to_le_bytes())?;
+    for (client_id, time, amount) in state.recent_withdrawals.iter() {
+        writer.write_all(&client_id.0.to_le_bytes())?;
+        writer.write_all(&time.to_le_bytes())?;
+        writer.write_all(&amount.to_raw().to_le_bytes())?;
+    }
+    writer.write_all(&(state.fraud_rule_states.len() as u64).to_le_bytes())?;
+    for (client_id, rule_index, rule_state) in state.fraud_rule_states.iter() {
+        writer.write_all(&client_id.0.to_le_bytes())?;
+        writer.write_all(&(*rule_index as u64).to_le_bytes())?;
+        write_bytes(writer, rule_state)?;
+    }
+    writer.write_all(&(state.alerts.len() as u64).to_le_bytes())?;
+    for alert in state.alerts.iter() {
+        writer.write_all(&(alert.line as u64).to_le_bytes())?;
+        writer.write_all(&alert.client_id.to_le_bytes())?;
+        writer.write_all(&[(alert.action == FraudAction::Lock) as u8])?;
+        write_bytes(writer, alert.rule.as_bytes())?;
+        write_bytes(writer, alert.reason.as_bytes())?;
+    }
+    // the number of the deposits is not known in advance, so each one is prefixed by a marker
+    // instead, and the list is closed by an end marker
+    engine.deposits_mut().for_each_deposit(&mut |id, deposit| {
+        writer.write_all(&[DEPOSIT_MARKER])?;
+        writer.write_all(&id.0.to_le_bytes())?;
+        writer.write_all(&deposit.to_bytes())
+    })?;
+    writer.write_all(&[END_MARKER])?;
+    writer.flush()
+}
+
+fn capacity(count: u64) -> usize {
+    count.min(MAX_PREALLOCATED) as usize
+}
+
+/// The bytes are prefixed by their length
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_bytes(reader: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let len = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_string(reader: &mut impl Read) -> std::io::Result<String> {
+    String::from_utf8(read_bytes(reader)?)
+        .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+}
+
+fn read_u8(reader: &mut impl Read) -> std::io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> std::io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
@@ -3,6 +3,7 @@ use crate::Amount;
 #[derive(Debug, Clone, Copy, Ord, Eq, PartialOrd, PartialEq, Hash)]
 pub struct ClientId(pub u16);
 
+#[derive(Clone)]
 pub struct Client {
     pub available: Amount,
     pub held: Amount,
@@ -20,17 +21,25 @@ impl Client {
         }
     }
 
-    pub fn deposit(&mut self, amount: Amount) {
+    /// Returns `false` if the total would not fit into an `Amount`, in this case nothing happens.
+    /// The available and held funds are never more than the total, so they can not overflow either.
+    pub fn deposit(&mut self, amount: Amount) -> bool {
+        let Some(total) = self.total.checked_add(amount) else {
+            return false;
+        };
         self.available += amount;
-        self.total += amount;
+        self.total = total;
+        true
     }
 
-    pub fn withdrawal(&mut self, amount: Amount) {
+    /// Returns `false` if the client does not have enough available funds, in this case nothing happens.
+    pub fn withdrawal(&mut self, amount: Amount) -> bool {
         if amount > self.available {
-            return;
+            return false;
         }
         self.available -= amount;
         self.total -= amount;
+        true
     }
 
     pub fn dispute(&mut self, amount: Amount) {
@@ -0,0 +1,847 @@
+use crate::audit::{Audit, AuditSnapshot};
+use crate::client::{Client, ClientId};
+use crate::fraud::{Alert, FraudDetector, FraudEvent, FraudSnapshot};
+use crate::ledger::{Ledger, LedgerEventType};
+use crate::limits::{LimitChecker, LimitsSnapshot};
+use crate::statistics::{Statistics, StatisticsSnapshot};
+use crate::store::{DepositStore, ProcessedLine, SavedState};
+use crate::transaction::{
+    Dispute, DisputeState, StoredDeposit, TransactionId, Transfer, TransferType,
+};
+use crate::{Amount, Config, HashMap, InputCsvLine};
+use roaring::RoaringBitmap;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+
+/// The state of all the clients and the deposits which might be referenced later by a dispute.
+///
+/// Input lines are applied one by one with `apply`, so the state can be inspected at any point
+/// of the processing, not only after the whole input was consumed.
+pub struct Engine {
+    clients: HashMap<ClientId, Client>,
+    /// The IDs of every deposit and withdrawal so far, to detect the duplicates.
+    /// A compressed bitmap takes only a fraction of the memory a `HashSet` would.
+    transaction_ids: RoaringBitmap,
+    deposits: Box<dyn DepositStore>,
+    recorders: Recorders,
+    /// Deposits older than this (in seconds) can not be disputed, and they are evicted from `deposits`
+    dispute_window: Option<u64>,
+    /// The deposits with timestamp in the order of their arrival, so the expired ones can be evicted.
+    /// Used only if `dispute_window` is present.
+    deposits_by_time: VecDeque<(u64, TransactionId)>,
+    /// The IDs of the evicted deposits, so disputes referencing them can be rejected with the right reason
+    evicted_deposits: RoaringBitmap,
+    duplicates: DuplicateCounts,
+    /// Present only if `Config::limits` limits anything
+    limits: Option<LimitChecker>,
+    /// Present only if `Config::fraud_rules` is not empty
+    fraud: Option<FraudDetector>,
+    /// The latest timestamp in the input so far
+    current_time: Option<u64>,
+    /// The index of the last applied input line
+    last_line: usize,
+    /// The changes of the current line, see `rollback_line`
+    changes: LineChanges,
+}
+
+/// The result of applying an input line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Applied,
+    Rejected(RejectionReason),
+}
+
+/// The number of the deposits and withdrawals rejected because of their already used Transaction IDs.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DuplicateCounts {
+    /// The same transfer again (same type, client and amount), it is safe to ignore
+    pub replays: usize,
+    /// A different transfer with an already used ID, it might be a fraud or an integrity problem
+    pub conflicts: usize,
+}
+
+/// The reason why an input line was ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectionReason {
+    /// The same deposit or withdrawal (with the same type, client and amount) was already processed
+    DuplicateTransactionId,
+    /// A different deposit or withdrawal with the same Transaction ID was already processed
+    ConflictingTransactionId,
+    ClientLocked,
+    InsufficientFunds,
+    /// The deposit would increase the total funds of the client above `Amount::MAX`
+    BalanceOverflow,
+    /// The referenced transaction does not exist, or it is a rejected deposit
+    UnknownTransaction,
+    /// The referenced transaction is a withdrawal
+    NotADeposit,
+    /// The referenced transaction belongs to a different client
+    ClientMismatch,
+    AlreadyDisputed,
+    NotDisputed,
+    /// The referenced deposit is older than the configured dispute window
+    DisputeWindowExpired,
+    /// The withdrawal is larger than `Limits::max_withdrawal` of the client
+    WithdrawalLimitExceeded,
+    /// The withdrawal would increase the withdrawals of the client within the window above
+    /// `Limits::max_withdrawal_volume`
+    WithdrawalVolumeLimitExceeded,
+    /// The deposit would increase the total funds of the client above `Limits::max_balance`
+    BalanceLimitExceeded,
+}
+
+impl Display for RejectionReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RejectionReason::DuplicateTransactionId => "duplicate transaction id",
+            RejectionReason::ConflictingTransactionId => "conflicting transaction id",
+            RejectionReason::ClientLocked => "client locked",
+            RejectionReason::InsufficientFunds => "insufficient funds",
+            RejectionReason::BalanceOverflow => "balance overflow",
+            RejectionReason::UnknownTransaction => "unknown transaction",
+            RejectionReason::NotADeposit => "not a deposit",
+            RejectionReason::ClientMismatch => "client mismatch",
+            RejectionReason::AlreadyDisputed => "already disputed",
+            RejectionReason::NotDisputed => "not disputed",
+            RejectionReason::DisputeWindowExpired => "dispute window expired",
+            RejectionReason::WithdrawalLimitExceeded => "withdrawal limit exceeded",
+            RejectionReason::WithdrawalVolumeLimitExceeded => "withdrawal volume limit exceeded",
+            RejectionReason::BalanceLimitExceeded => "balance limit exceeded",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Engine {
+    /// It can fail only if the configured deposit store can not be created.
+    /// If the store is persistent, the state saved by the previous run is loaded.
+    pub fn new(config: &Config) -> std::io::Result<Engine> {
+        let mut deposits = config.store.create_store()?;
+        let state = deposits.load_state()?.unwrap_or_default();
+        Ok(Engine::from_parts(config, deposits, state))
+    }
+
+    /// Creates an engine with a state saved by `saved_state`, e.g. from a checkpoint.
+    /// Its deposits have to be restored one by one by `restore_deposit`.
+    pub(crate) fn with_state(config: &Config, state: SavedState) -> std::io::Result<Engine> {
+        Ok(Engine::from_parts(
+            config,
+            config.store.create_store()?,
+            state,
+        ))
+    }
+
+    fn from_parts(config: &Config, deposits: Box<dyn DepositStore>, state: SavedState) -> Engine {
+        let dispute_window = config.dispute_window.map(|it| it.as_secs());
+        let clients = state.clients.into_iter().collect();
+        Engine {
+            recorders: Recorders {
+                ledger: None,
+                audit: config.verify.then(|| Audit::new(&clients)),
+                statistics: Statistics::default(),
+            },
+            clients,
+            transaction_ids: state.transaction_ids,
+            deposits,
+            dispute_window,
+            deposits_by_time: match dispute_window {
+                Some(_) => state.deposits_by_time.into(),
+                None => VecDeque::new(),
+            },
+            evicted_deposits: state.evicted_deposits,
+            duplicates: DuplicateCounts::default(),
+            limits: (!config.limits.is_unlimited())
+                .then(|| LimitChecker::new(&config.limits, state.recent_withdrawals)),
+            fraud: (!config.fraud_rules.is_empty()).then(|| {
+                FraudDetector::new(&config.fraud_rules, state.fraud_rule_states, state.alerts)
+            }),
+            current_time: state.current_time,
+            last_line: state.last_line,
+            changes: LineChanges::default(),
+        }
+    }
+
+    /// Creates an engine which records every balance-affecting event into a `Ledger`.
+    pub fn with_ledger(config: &Config) -> std::io::Result<Engine> {
+        let mut engine = Engine::new(config)?;
+        engine.record_ledger();
+        Ok(engine)
+    }
+
+    /// Starts recording the balance-affecting events from now on, see `with_ledger`.
+    pub(crate) fn record_ledger(&mut self) {
+        self.recorders.ledger.get_or_insert_with(Ledger::new);
+    }
+
+    pub fn clients(&self) -> &HashMap<ClientId, Client> {
+        &self.clients
+    }
+
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.recorders.ledger.as_ref()
+    }
+
+    pub(crate) fn statistics(&self) -> &Statistics {
+        &self.recorders.statistics
+    }
+
+    /// The duplicated Transaction IDs rejected by this engine
+    pub fn duplicates(&self) -> DuplicateCounts {
+        self.duplicates
+    }
+
+    /// The index of the last applied input line. Before applying any line, it is non-zero only if
+    /// the state was loaded from a persistent store, and the lines up to it must not be applied again.
+    pub fn last_line(&self) -> usize {
+        self.last_line
+    }
+
+    /// Applies a single input line to the state according to the documentation.
+    /// `line_index` is only used to refer back to the input line, e.g. in the ledger.
+    ///
+    /// It can return an `Err` only when the deposit store fails, or a violation is found with `Config::verify`.
+    /// The line is rolled back then, so it leaves no trace in the state, and it can be applied again later.
+    /// If even the rollback fails, its error is returned, and the state is undefined.
+    pub fn apply(&mut self, line_index: usize, csv_line: InputCsvLine) -> std::io::Result<Outcome> {
+        let timestamp = csv_line.timestamp();
+        self.apply_dispatched(line_index, csv_line, timestamp, false)
+    }
+
+    /// Same as `apply`, for a line dispatched by the parallel processing: the time is moved to `current_time`
+    /// (the latest timestamp of the whole input), and a transfer is rejected as a duplicate if `duplicate` is set,
+    /// since the duplicates are detected outside of the engine.
+    pub(crate) fn apply_dispatched(
+        &mut self,
+        line_index: usize,
+        csv_line: InputCsvLine,
+        current_time: Option<u64>,
+        duplicate: bool,
+    ) -> std::io::Result<Outcome> {
+        let snapshot = self.snapshot(&csv_line);
+        self.changes.clear();
+        let result = self.apply_line(line_index, csv_line, current_time, duplicate);
+        if result.is_err() {
+            self.rollback_line(snapshot)?;
+        }
+        result
+    }
+
+    fn apply_line(
+        &mut self,
+        line_index: usize,
+        csv_line: InputCsvLine,
+        current_time: Option<u64>,
+        duplicate: bool,
+    ) -> std::io::Result<Outcome> {
+        if let Some(timestamp) = current_time {
+            self.advance_time(timestamp)?;
+        }
+        let client_id = csv_line.client_id();
+        let line_type = line_type(&csv_line);
+        let tx_id = csv_line.tx_id();
+        let (transfer_id, transfer_amount) = match &csv_line {
+            InputCsvLine::Transfer(tx) => match tx.typ {
+                TransferType::Deposit { amount } | TransferType::Withdrawal { amount } => {
+                    (Some(tx.id), Some(amount))
+                }
+            },
+            InputCsvLine::Dispute(_) => (None, None),
+        };
+        let withdrawal_amount =
+            transfer_amount.filter(|_| matches!(line_type, LedgerEventType::Withdrawal));
+        let result = match csv_line {
+            InputCsvLine::Transfer(tx) => self.apply_transfer(line_index, tx, duplicate)?,
+            InputCsvLine::Dispute(dispute) => self.apply_dispute(line_index, dispute)?,
+        };
+        let is_duplicate = matches!(
+            result,
+            Err(RejectionReason::DuplicateTransactionId | RejectionReason::ConflictingTransactionId)
+        );
+        if let Some(violation) = self
+            .recorders
+            .audit
+            .as_mut()
+            .and_then(Audit::take_violation)
+        {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, violation));
+        }
+        let alert_count = self.alerts().len();
+        self.detect_fraud(FraudEvent {
+            line: line_index,
+            client_id: client_id.0,
+            tx_id: tx_id.0,
+            typ: line_type,
+            amount: transfer_amount,
+            outcome: to_outcome(result),
+            available: Amount::zero(),
+        });
+        self.deposits.line_processed(ProcessedLine {
+            line_index,
+            client_id,
+            client: self.clients.get(&client_id),
+            new_transaction_id: transfer_id.filter(|_| !is_duplicate),
+            new_withdrawal_amount: withdrawal_amount.filter(|_| !is_duplicate),
+            recent_withdrawals: self
+                .limits
+                .as_ref()
+                .filter(|_| withdrawal_amount.is_some())
+                .and_then(|limits| limits.recent_withdrawals(client_id)),
+            fraud: self.fraud.as_ref(),
+            new_alerts: self
+                .fraud
+                .as_ref()
+                .map_or(&[], |fraud| &fraud.alerts()[alert_count..]),
+            current_time: self.current_time,
+        })?;
+        // the failed lines are neither counted nor logged
+        self.last_line = line_index;
+        self.recorders.statistics.line_processed(line_type, result);
+        if let Err(reason) = result {
+            log_rejection(line_index, line_type, client_id, tx_id, reason);
+        }
+        Ok(to_outcome(result))
+    }
+
+    /// The state which might be changed by the line besides `changes`, see `rollback_line`.
+    /// A line changes the state of its own client only.
+    fn snapshot(&self, csv_line: &InputCsvLine) -> LineSnapshot {
+        let client_id = csv_line.client_id();
+        let is_withdrawal = matches!(line_type(csv_line), LedgerEventType::Withdrawal);
+        LineSnapshot {
+            client_id,
+            client: self.clients.get(&client_id).cloned(),
+            current_time: self.current_time,
+            duplicates: self.duplicates,
+            statistics: self.recorders.statistics.snapshot(),
+            limits: self
+                .limits
+                .as_ref()
+                .filter(|_| is_withdrawal)
+                .map(|limits| limits.snapshot(client_id)),
+            fraud: self.fraud.as_ref().map(|fraud| fraud.snapshot(client_id)),
+            audit: self
+                .recorders
+                .audit
+                .as_ref()
+                .map(|audit| audit.snapshot(client_id)),
+            ledger_entries: self
+                .recorders
+                .ledger
+                .as_ref()
+                .map_or(0, |ledger| ledger.entry_count(client_id)),
+        }
+    }
+
+    /// Restores the state before the failed line from `snapshot` and `changes`.
+    /// The rest of the state is restored even if the deposit store fails to roll back.
+    fn rollback_line(&mut self, snapshot: LineSnapshot) -> std::io::Result<()> {
+        let changes = &mut self.changes;
+        let result = self.deposits.rollback_line(&changes.deposits);
+        match snapshot.client {
+            Some(client) => self.clients.insert(snapshot.client_id, client),
+            None => self.clients.remove(&snapshot.client_id),
+        };
+        if let Some(id) = changes.new_transaction_id {
+            self.transaction_ids.remove(id.0);
+        }
+        if changes.pushed_by_time {
+            self.deposits_by_time.pop_back();
+        }
+        for (timestamp, id) in changes.popped_by_time.drain(..).rev() {
+            self.deposits_by_time.push_front((timestamp, id));
+        }
+        for id in changes.evicted_deposits.drain(..) {
+            self.evicted_deposits.remove(id.0);
+        }
+        self.current_time = snapshot.current_time;
+        self.duplicates = snapshot.duplicates;
+        self.recorders.statistics.restore(snapshot.statistics);
+        if let (Some(limits), Some(limits_snapshot)) = (self.limits.as_mut(), snapshot.limits) {
+            limits.restore(limits_snapshot);
+        }
+        if let (Some(fraud), Some(fraud_snapshot)) = (self.fraud.as_mut(), snapshot.fraud) {
+            fraud.restore(fraud_snapshot);
+        }
+        if let (Some(audit), Some(audit_snapshot)) = (self.recorders.audit.as_mut(), snapshot.audit)
+        {
+            audit.restore(audit_snapshot);
+        }
+        if let Some(ledger) = self.recorders.ledger.as_mut() {
+            ledger.truncate(snapshot.client_id, snapshot.ledger_entries);
+        }
+        result
+    }
+
+    /// Evaluates the fraud rules on the event (its `available` is filled in here), and locks the client
+    /// if a rule requires it.
+    fn detect_fraud(&mut self, mut event: FraudEvent) {
+        let Some(fraud) = self.fraud.as_mut() else {
+            return;
+        };
+        let client_id = ClientId(event.client_id);
+        event.available = self
+            .clients
+            .get(&client_id)
+            .map_or(Amount::zero(), |client| client.available);
+        if fraud.evaluate(&event) {
+            get_or_create_client(&mut self.clients, client_id).locked = true;
+        }
+    }
+
+    /// The alerts of the fraud rules so far, in the order of their lines.
+    pub(crate) fn alerts(&self) -> &[Alert] {
+        self.fraud.as_ref().map_or(&[], |fraud| fraud.alerts())
+    }
+
+    /// Moves the current time forward to `timestamp` (it is never moved backwards),
+    /// and evicts the deposits which became expired.
+    fn advance_time(&mut self, timestamp: u64) -> std::io::Result<()> {
+        if self.current_time.is_none_or(|current| timestamp > current) {
+            self.current_time = Some(timestamp);
+            self.evict_expired_deposits()?;
+        }
+        Ok(())
+    }
+
+    /// A copy of the state besides the deposits and the ledger, see `with_state`.
+    pub(crate) fn saved_state(&self) -> SavedState {
+        SavedState {
+            clients: self
+                .clients
+                .iter()
+                .map(|(id, client)| (*id, client.clone()))
+                .collect(),
+            transaction_ids: self.transaction_ids.clone(),
+            evicted_deposits: self.evicted_deposits.clone(),
+            deposits_by_time: self.deposits_by_time.iter().copied().collect(),
+            recent_withdrawals: self
+                .limits
+                .as_ref()
+                .map_or_else(Vec::new, LimitChecker::saved_state),
+            fraud_rule_states: self
+                .fraud
+                .as_ref()
+                .map_or_else(Vec::new, FraudDetector::saved_state),
+            alerts: self.alerts().to_vec(),
+            current_time: self.current_time,
+            last_line: self.last_line,
+        }
+    }
+
+    pub(crate) fn deposits_mut(&mut self) -> &mut dyn DepositStore {
+        self.deposits.as_mut()
+    }
+
+    /// Moves the clients (and their ledger entries) of `other` into this engine.
+    /// The two engines must not share any clients, e.g. they were sharded by Client ID.
+    pub fn merge(&mut self, other: Engine) {
+        self.clients.extend(other.clients);
+        self.duplicates.replays += other.duplicates.replays;
+        self.duplicates.conflicts += other.duplicates.conflicts;
+        self.recorders.statistics.merge(other.recorders.statistics);
+        if let (Some(limits), Some(other_limits)) = (self.limits.as_mut(), other.limits) {
+            limits.merge(other_limits);
+        }
+        if let (Some(fraud), Some(other_fraud)) = (self.fraud.as_mut(), other.fraud) {
+            fraud.merge(other_fraud);
+        }
+        if let (Some(ledger), Some(other_ledger)) =
+            (self.recorders.ledger.as_mut(), other.recorders.ledger)
+        {
+            ledger.merge(other_ledger);
+        }
+    }
+
+    /// `duplicate` is set if the Transaction ID is known to be used already, see `apply_dispatched`
+    fn apply_transfer(
+        &mut self,
+        line_index: usize,
+        tx: Transfer,
+        duplicate: bool,
+    ) -> std::io::Result<Result<(), RejectionReason>> {
+        if duplicate || !self.transaction_ids.insert(tx.id.0) {
+            return Ok(Err(self.reject_duplicate(&tx)?));
+        }
+        self.changes.new_transaction_id = Some(tx.id);
+        let client = get_or_create_client(&mut self.clients, tx.client_id);
+        let mut limits = self.limits.as_mut();
+        let result = match &tx.typ {
+            TransferType::Deposit { amount } => {
+                if client.locked {
+                    Err(RejectionReason::ClientLocked)
+                } else if let Some(Err(reason)) = limits
+                    .as_ref()
+                    .map(|limits| limits.check_deposit(tx.client_id, client, *amount))
+                {
+                    Err(reason)
+                } else if !client.deposit(*amount) {
+                    Err(RejectionReason::BalanceOverflow)
+                } else {
+                    self.recorders.record(
+                        line_index,
+                        tx.client_id,
+                        client,
+                        tx.id,
+                        LedgerEventType::Deposit,
+                        *amount,
+                    );
+                    Ok(())
+                }
+            }
+            TransferType::Withdrawal { amount } => {
+                if client.locked {
+                    Err(RejectionReason::ClientLocked)
+                } else if let Some(Err(reason)) = limits
+                    .as_mut()
+                    .map(|limits| limits.check_withdrawal(tx.client_id, *amount, self.current_time))
+                {
+                    Err(reason)
+                } else if !client.withdrawal(*amount) {
+                    Err(RejectionReason::InsufficientFunds)
+                } else {
+                    if let Some(limits) = limits {
+                        limits.withdrawal_applied(tx.client_id, *amount, self.current_time);
+                    }
+                    self.recorders.record(
+                        line_index,
+                        tx.client_id,
+                        client,
+                        tx.id,
+                        LedgerEventType::Withdrawal,
+                        *amount,
+                    );
+                    Ok(())
+                }
+            }
+        };
+        // the rejected transfers are stored as well, to classify the later reuses of their IDs
+        match tx.typ {
+            TransferType::Deposit { amount } => {
+                if let (Some(_), Some(timestamp)) = (self.dispute_window, tx.timestamp) {
+                    self.deposits_by_time.push_back((timestamp, tx.id));
+                    self.changes.pushed_by_time = true;
+                }
+                let mut deposit = StoredDeposit::new(tx.client_id, amount, tx.timestamp);
+                deposit.set_rejected(result.is_err());
+                self.changes.deposits.push((tx.id, None));
+                self.deposits.insert(tx.id, deposit)?;
+                self.recorders.statistics.deposit_stored();
+            }
+            TransferType::Withdrawal { amount } => {
+                let withdrawal = StoredDeposit::withdrawal(tx.client_id, amount);
+                self.changes.deposits.push((tx.id, None));
+                self.deposits.insert(tx.id, withdrawal)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Classifies and counts a transfer with an already used Transaction ID.
+    ///
+    /// In parallel mode the duplicates are detected globally, and the ones whose original transfer was processed
+    /// by another worker (so it belongs to another client) are conflicts.
+    /// The evicted deposits can not be compared, so a reuse of their IDs is always considered a conflict.
+    fn reject_duplicate(&mut self, tx: &Transfer) -> std::io::Result<RejectionReason> {
+        let (TransferType::Deposit { amount } | TransferType::Withdrawal { amount }) = tx.typ;
+        let is_withdrawal = matches!(tx.typ, TransferType::Withdrawal { .. });
+        let is_replay = self.deposits.get_mut(tx.id)?.is_some_and(|stored| {
+            let (client_id, stored_amount) = (stored.client_id, stored.amount);
+            stored.is_withdrawal() == is_withdrawal
+                && client_id == tx.client_id
+                && stored_amount == amount
+        });
+        Ok(if is_replay {
+            self.duplicates.replays += 1;
+            RejectionReason::DuplicateTransactionId
+        } else {
+            self.duplicates.conflicts += 1;
+            RejectionReason::ConflictingTransactionId
+        })
+    }
+
+    fn apply_dispute(
+        &mut self,
+        line_index: usize,
+        dispute: Dispute,
+    ) -> std::io::Result<Result<(), RejectionReason>> {
+        let deposit = match get_deposit_transaction(
+            self.deposits.as_mut(),
+            &self.evicted_deposits,
+            &dispute,
+        )? {
+            Ok(it) => it,
+            // according to the business requirements, non existing referenced transactions are expected
+            Err(reason) => return Ok(Err(reason)),
+        };
+        self.changes
+            .deposits
+            .push((dispute.disputed_tx_id, Some(*deposit)));
+        let amount = deposit.amount;
+        let client = get_or_create_client(&mut self.clients, dispute.client_id);
+        let (result, typ) = match dispute.state {
+            DisputeState::Dispute => {
+                let result = if deposit.disputed() {
+                    Err(RejectionReason::AlreadyDisputed)
+                } else if is_expired(self.dispute_window, self.current_time, deposit.timestamp()) {
+                    Err(RejectionReason::DisputeWindowExpired)
+                } else if client.locked {
+                    Err(RejectionReason::ClientLocked)
+                } else if deposit.rejected() {
+                    // the rejected deposits are only stored to classify the reuses of their IDs
+                    Err(RejectionReason::UnknownTransaction)
+                } else if client.available < amount {
+                    // TODO @clarify What to do when client does not have the available amount?
+                    Err(RejectionReason::InsufficientFunds)
+                } else {
+                    client.dispute(amount);
+                    deposit.set_disputed(true);
+                    Ok(())
+                };
+                (result, LedgerEventType::Dispute)
+            }
+            DisputeState::Resolve => {
+                let result = if !deposit.disputed() {
+                    // according to the business requirements, it is an error on our partner's side
+                    Err(RejectionReason::NotDisputed)
+                } else {
+                    client.resolve(amount);
+                    deposit.set_disputed(false);
+                    Ok(())
+                };
+                (result, LedgerEventType::Resolve)
+            }
+            DisputeState::Chargeback => {
+                let result = if !deposit.disputed() {
+                    // according to the business requirements, it is an error on our partner's side
+                    Err(RejectionReason::NotDisputed)
+                } else if client.locked {
+                    Err(RejectionReason::ClientLocked)
+                } else {
+                    client.chargeback(amount);
+                    deposit.set_disputed(false);
+                    Ok(())
+                };
+                (result, LedgerEventType::Chargeback)
+            }
+        };
+        // the deposit was not evicted when it expired, since it was disputed
+        let expired_deposit = (result.is_ok()
+            && !matches!(dispute.state, DisputeState::Dispute)
+            && is_expired(self.dispute_window, self.current_time, deposit.timestamp()))
+        .then_some(*deposit);
+        if result.is_ok() {
+            self.recorders.record(
+                line_index,
+                dispute.client_id,
+                client,
+                dispute.disputed_tx_id,
+                typ,
+                amount,
+            );
+        }
+        if expired_deposit.is_some() {
+            self.evict_deposit(dispute.disputed_tx_id, expired_deposit)?;
+        }
+        Ok(result)
+    }
+
+    /// Removes the deposits from the store which can not be disputed anymore.
+    /// The disputed deposits are kept, since they can still be resolved or chargebacked,
+    /// they are evicted by `apply_dispute` after that.
+    fn evict_expired_deposits(&mut self) -> std::io::Result<()> {
+        while let Some((timestamp, id)) = self.deposits_by_time.front().copied() {
+            if !is_expired(self.dispute_window, self.current_time, Some(timestamp)) {
+                break;
+            }
+            self.deposits_by_time.pop_front();
+            self.changes.popped_by_time.push((timestamp, id));
+            match self.deposits.get_mut(id)?.map(|deposit| *deposit) {
+                // it was disputed when it expired, and evicted by its resolve or chargeback
+                None if self.evicted_deposits.contains(id.0) => {}
+                Some(deposit) if deposit.disputed() => {}
+                deposit => self.evict_deposit(id, deposit)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the deposit from the store, and remembers its ID to reject the later disputes as expired.
+    fn evict_deposit(
+        &mut self,
+        id: TransactionId,
+        deposit: Option<StoredDeposit>,
+    ) -> std::io::Result<()> {
+        self.changes.deposits.push((id, deposit));
+        self.deposits.remove(id)?;
+        self.evicted_deposits.insert(id.0);
+        self.changes.evicted_deposits.push(id);
+        self.recorders.statistics.deposit_evicted();
+        Ok(())
+    }
+}
+
+/// Returns true if a deposit with the given `timestamp` can not be disputed anymore at `current_time`.
+/// Without timestamps or dispute window, nothing expires.
+fn is_expired(
+    dispute_window: Option<u64>,
+    current_time: Option<u64>,
+    timestamp: Option<u64>,
+) -> bool {
+    match (dispute_window, current_time, timestamp) {
+        (Some(window), Some(current_time), Some(timestamp)) => {
+            current_time.saturating_sub(timestamp) > window
+        }
+        _ => false,
+    }
+}
+
+/// The type of the input line, the same as the type of the event it results in if it is applied
+fn line_type(csv_line: &InputCsvLine) -> LedgerEventType {
+    match csv_line {
+        InputCsvLine::Transfer(tx) => match tx.typ {
+            TransferType::Deposit { .. } => LedgerEventType::Deposit,
+            TransferType::Withdrawal { .. } => LedgerEventType::Withdrawal,
+        },
+        InputCsvLine::Dispute(dispute) => match dispute.state {
+            DisputeState::Dispute => LedgerEventType::Dispute,
+            DisputeState::Resolve => LedgerEventType::Resolve,
+            DisputeState::Chargeback => LedgerEventType::Chargeback,
+        },
+    }
+}
+
+fn to_outcome(result: Result<(), RejectionReason>) -> Outcome {
+    match result {
+        Ok(()) => Outcome::Applied,
+        Err(reason) => Outcome::Rejected(reason),
+    }
+}
+
+/// Emits a log record about a rejected line, with its fields as structured key-values.
+///
+/// The integrity problems (a conflicting Transaction ID or a balance overflow) are warnings,
+/// the other rejections are expected in the input, so they are only informational.
+fn log_rejection(
+    line_index: usize,
+    line_type: LedgerEventType,
+    client_id: ClientId,
+    tx_id: TransactionId,
+    reason: RejectionReason,
+) {
+    let level = match reason {
+        RejectionReason::ConflictingTransactionId | RejectionReason::BalanceOverflow => {
+            log::Level::Warn
+        }
+        _ => log::Level::Info,
+    };
+    log::log!(
+        level,
+        line = line_index,
+        type:% = line_type,
+        tx = tx_id.0,
+        client = client_id.0,
+        reason:% = reason;
+        "{} rejected",
+        line_type
+    );
+}
+
+/// The state before an input line, which might be changed by the line, see `Engine::snapshot`
+struct LineSnapshot {
+    client_id: ClientId,
+    client: Option<Client>,
+    current_time: Option<u64>,
+    duplicates: DuplicateCounts,
+    statistics: StatisticsSnapshot,
+    /// Present only if the line is a withdrawal and there are limits
+    limits: Option<LimitsSnapshot>,
+    fraud: Option<FraudSnapshot>,
+    audit: Option<AuditSnapshot>,
+    ledger_entries: usize,
+}
+
+/// The changes of the current line which can not be restored from a `LineSnapshot`.
+/// It is reused by every line, so its buffers are allocated only once.
+#[derive(Default)]
+struct LineChanges {
+    /// The Transaction ID inserted into `Engine::transaction_ids`
+    new_transaction_id: Option<TransactionId>,
+    /// A deposit was pushed to the back of `Engine::deposits_by_time`
+    pushed_by_time: bool,
+    /// The deposits popped from the front of `Engine::deposits_by_time`, in their order
+    popped_by_time: Vec<(u64, TransactionId)>,
+    /// The Transaction IDs inserted into `Engine::evicted_deposits`
+    evicted_deposits: Vec<TransactionId>,
+    /// The changed deposits with their previous values, see `DepositStore::rollback_line`
+    deposits: Vec<(TransactionId, Option<StoredDeposit>)>,
+}
+
+impl LineChanges {
+    fn clear(&mut self) {
+        self.new_transaction_id = None;
+        self.pushed_by_time = false;
+        self.popped_by_time.clear();
+        self.evicted_deposits.clear();
+        self.deposits.clear();
+    }
+}
+
+/// The consumers of the applied events
+struct Recorders {
+    ledger: Option<Ledger>,
+    /// Present only if `Config::verify` is set
+    audit: Option<Audit>,
+    statistics: Statistics,
+}
+
+impl Recorders {
+    /// Records the already applied event into the statistics, and the ledger and the audit if they are turned on.
+    fn record(
+        &mut self,
+        line_index: usize,
+        client_id: ClientId,
+        client: &Client,
+        tx_id: TransactionId,
+        typ: LedgerEventType,
+        amount: Amount,
+    ) {
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.record(line_index, client_id, client, tx_id, typ, amount);
+        }
+        if let Some(audit) = self.audit.as_mut() {
+            audit.event_applied(line_index, client_id, client, typ, amount);
+        }
+        self.statistics.event_applied(typ, amount);
+    }
+}
+
+/// A utility function which returns a deposit referenced by the dispute, if the
+/// transaction is a deposit and has the same Client ID as the dispute. Otherwise it returns
+/// the reason why it can not be disputed.
+///
+/// This function is needed to hide the lookups and so make the caller code more readable.
+fn get_deposit_transaction<'a>(
+    deposits: &'a mut dyn DepositStore,
+    evicted_deposits: &RoaringBitmap,
+    dispute: &Dispute,
+) -> std::io::Result<Result<&'a mut StoredDeposit, RejectionReason>> {
+    let id = dispute.disputed_tx_id;
+    Ok(match deposits.get_mut(id)? {
+        Some(deposit) if deposit.is_withdrawal() => Err(RejectionReason::NotADeposit),
+        Some(deposit) if { deposit.client_id } != dispute.client_id => {
+            Err(RejectionReason::ClientMismatch)
+        }
+        Some(deposit) => Ok(deposit),
+        None if evicted_deposits.contains(id.0) => Err(RejectionReason::DisputeWindowExpired),
+        None => Err(RejectionReason::UnknownTransaction),
+    })
+}
+
+/// Returns the referenced client, or if it does not exists, it creates one with the default values.
+fn get_or_create_client(
+    clients: &mut HashMap<ClientId, Client>,
+    client_id: ClientId,
+) -> &mut Client {
+    clients.entry(client_id).or_insert_with(Client::new)
+}
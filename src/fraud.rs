@@ -0,0 +1,464 @@
+use crate::client::ClientId;
+use crate::engine::Outcome;
+use crate::ledger::LedgerEventType;
+use crate::{Amount, HashMap};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// A heuristic which watches the events of the clients, and triggers on the suspicious ones.
+///
+/// A rule is created for every engine (e.g. for every worker), and it sees the events of its clients
+/// in their order, so it can keep a per-client state.
+pub trait FraudRule: Send {
+    /// The name of the rule in the alerts
+    fn name(&self) -> &str;
+
+    /// Called after every input line, returns the reason of the alert if the rule triggers on it.
+    fn evaluate(&mut self, event: &FraudEvent) -> Option<String>;
+
+    /// The state of the rule for a client, it is saved with the state of the engine (into a checkpoint
+    /// or a persistent store), so the rule continues where it stopped after resuming.
+    /// An empty state means the rule keeps nothing about the client, e.g. the rule is stateless by default.
+    fn client_state(&self, _client_id: u16) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores a state returned by `client_state`. It is called before any event is evaluated with the saved
+    /// non-empty states, and with any state (even an empty one) to undo the event of a line which failed to be stored.
+    fn restore_client_state(&mut self, _client_id: u16, _state: &[u8]) {}
+}
+
+/// An input line after it was applied or rejected, as it is seen by the `FraudRule`s.
+#[derive(Debug, Clone, Copy)]
+pub struct FraudEvent {
+    pub line: usize,
+    pub client_id: u16,
+    pub tx_id: u32,
+    pub typ: LedgerEventType,
+    /// The amount of a deposit or a withdrawal
+    pub amount: Option<Amount>,
+    pub outcome: Outcome,
+    /// The available funds of the client after the line
+    pub available: Amount,
+}
+
+/// What happens with the client when a rule triggers, an alert is recorded in both cases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FraudAction {
+    /// Only an alert is recorded
+    Flag,
+    /// The client is locked, the same way as by a chargeback
+    Lock,
+}
+
+impl Display for FraudAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FraudAction::Flag => write!(f, "flag"),
+            FraudAction::Lock => write!(f, "lock"),
+        }
+    }
+}
+
+impl FromStr for FraudAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(FraudAction::Flag),
+            "lock" => Ok(FraudAction::Lock),
+            _ => Err(format!("Invalid fraud action: {}", s)),
+        }
+    }
+}
+
+/// A rule and the action taken when it triggers.
+///
+/// It can be parsed from `<rule>[:<key>=<value>,...]`, e.g. `frequent_disputes:max=3,events=10,action=lock`,
+/// the action is `flag` by default.
+#[derive(Debug, Clone)]
+pub struct FraudRuleConfig {
+    pub kind: FraudRuleKind,
+    pub action: FraudAction,
+}
+
+#[derive(Debug, Clone)]
+pub enum FraudRuleKind {
+    /// More than `max_disputes` disputes (applied or rejected) within the last `events` lines of a client
+    FrequentDisputes { max_disputes: usize, events: usize },
+    /// A withdrawal right after a deposit of the client, which takes out at least the deposited amount
+    /// and leaves no available funds
+    DepositThenFullWithdrawal,
+    /// More than `max_failures` rejected withdrawals within the last `events` lines of a client
+    RepeatedFailedWithdrawals { max_failures: usize, events: usize },
+    /// A rule implemented outside of the crate
+    Custom(CustomFraudRule),
+}
+
+/// Creates a custom rule for every engine.
+#[derive(Clone)]
+pub struct CustomFraudRule(pub Arc<dyn Fn() -> Box<dyn FraudRule> + Send + Sync>);
+
+impl Debug for CustomFraudRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CustomFraudRule")
+    }
+}
+
+impl FraudRuleConfig {
+    fn create_rule(&self) -> Box<dyn FraudRule> {
+        match &self.kind {
+            FraudRuleKind::FrequentDisputes {
+                max_disputes,
+                events,
+            } => Box::new(CountingRule {
+                name: "frequent_disputes",
+                description: "disputes",
+                is_counted: |event| matches!(event.typ, LedgerEventType::Dispute),
+                max: *max_disputes,
+                windows: EventWindows::new(*events),
+            }),
+            FraudRuleKind::DepositThenFullWithdrawal => {
+                Box::new(DepositThenFullWithdrawal::default())
+            }
+            FraudRuleKind::RepeatedFailedWithdrawals {
+                max_failures,
+                events,
+            } => Box::new(CountingRule {
+                name: "repeated_failed_withdrawals",
+                description: "rejected withdrawals",
+                is_counted: |event| {
+                    matches!(event.typ, LedgerEventType::Withdrawal)
+                        && matches!(event.outcome, Outcome::Rejected(_))
+                },
+                max: *max_failures,
+                windows: EventWindows::new(*events),
+            }),
+            FraudRuleKind::Custom(CustomFraudRule(create)) => create(),
+        }
+    }
+}
+
+impl FromStr for FraudRuleConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid fraud rule: {}", s);
+        let (name, options) = s.trim().split_once(':').unwrap_or((s.trim(), ""));
+        let mut action = FraudAction::Flag;
+        let mut max = None;
+        let mut events = None;
+        for option in options.split(',').filter(|it| !it.trim().is_empty()) {
+            let (key, value) = option.split_once('=').ok_or_else(invalid)?;
+            let value = value.trim();
+            match key.trim() {
+                "action" => action = value.parse().map_err(|_| invalid())?,
+                "max" => max = Some(value.parse().map_err(|_| invalid())?),
+                "events" => events = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            }
+        }
+        let kind = match (name, max, events) {
+            ("frequent_disputes", Some(max_disputes), Some(events)) => {
+                FraudRuleKind::FrequentDisputes {
+                    max_disputes,
+                    events,
+                }
+            }
+            ("deposit_then_full_withdrawal", None, None) => {
+                FraudRuleKind::DepositThenFullWithdrawal
+            }
+            ("repeated_failed_withdrawals", Some(max_failures), Some(events)) => {
+                FraudRuleKind::RepeatedFailedWithdrawals {
+                    max_failures,
+                    events,
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(FraudRuleConfig { kind, action })
+    }
+}
+
+/// A triggered rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// The input line which triggered the rule
+    pub line: usize,
+    pub client_id: u16,
+    pub rule: String,
+    pub action: FraudAction,
+    pub reason: String,
+}
+
+/// Evaluates the rules on every event, and collects the alerts.
+pub(crate) struct FraudDetector {
+    rules: Vec<(Box<dyn FraudRule>, FraudAction)>,
+    alerts: Vec<Alert>,
+}
+
+impl FraudDetector {
+    /// `rule_states` and `alerts` are the ones saved by `saved_state` and `alerts`,
+    /// the rules are identified by their index in `rules`.
+    pub fn new(
+        rules: &[FraudRuleConfig],
+        rule_states: Vec<(ClientId, usize, Vec<u8>)>,
+        alerts: Vec<Alert>,
+    ) -> FraudDetector {
+        let mut rules: Vec<_> = rules
+            .iter()
+            .map(|rule| (rule.create_rule(), rule.action))
+            .collect();
+        for (client_id, rule_index, state) in rule_states {
+            if let Some((rule, _)) = rules.get_mut(rule_index) {
+                rule.restore_client_state(client_id.0, &state);
+            }
+        }
+        FraudDetector { rules, alerts }
+    }
+
+    /// Returns true if the client has to be locked.
+    pub fn evaluate(&mut self, event: &FraudEvent) -> bool {
+        let mut lock = false;
+        for (rule, action) in self.rules.iter_mut() {
+            if let Some(reason) = rule.evaluate(event) {
+                log::warn!(
+                    line = event.line,
+                    client = event.client_id,
+                    rule = rule.name(),
+                    action:% = action,
+                    reason = reason.as_str();
+                    "fraud alert"
+                );
+                lock |= *action == FraudAction::Lock;
+                self.alerts.push(Alert {
+                    line: event.line,
+                    client_id: event.client_id,
+                    rule: rule.name().to_owned(),
+                    action: *action,
+                    reason,
+                });
+            }
+        }
+        lock
+    }
+
+    pub fn alerts(&self) -> &[Alert] {
+        &self.alerts
+    }
+
+    /// The state of every rule for the client (even the empty ones), by the index of the rule
+    pub fn client_states(
+        &self,
+        client_id: ClientId,
+    ) -> impl Iterator<Item = (usize, Vec<u8>)> + '_ {
+        self.rules
+            .iter()
+            .enumerate()
+            .map(move |(index, (rule, _))| (index, rule.client_state(client_id.0)))
+    }
+
+    /// The non-empty states of the rules for every client.
+    /// A rule can keep a state of a client which is not in the engine (e.g. a rejected dispute of a new client),
+    /// so every Client ID is checked, there are at most 65536 of them.
+    pub fn saved_state(&self) -> Vec<(ClientId, usize, Vec<u8>)> {
+        (0..=u16::MAX)
+            .flat_map(|client_id| {
+                self.client_states(ClientId(client_id))
+                    .filter(|(_, state)| !state.is_empty())
+                    .map(move |(index, state)| (ClientId(client_id), index, state))
+            })
+            .collect()
+    }
+
+    /// The state of the rules for the client and the number of the alerts, see `restore`
+    pub fn snapshot(&self, client_id: ClientId) -> FraudSnapshot {
+        FraudSnapshot {
+            client_id,
+            rule_states: self.client_states(client_id).collect(),
+            alert_count: self.alerts.len(),
+        }
+    }
+
+    /// Undoes the events of the client since `snapshot`, and forgets their alerts.
+    pub fn restore(&mut self, snapshot: FraudSnapshot) {
+        for (rule_index, state) in snapshot.rule_states {
+            self.rules[rule_index]
+                .0
+                .restore_client_state(snapshot.client_id.0, &state);
+        }
+        self.alerts.truncate(snapshot.alert_count);
+    }
+
+    /// Moves the alerts of `other` into this detector, they are kept in the order of their lines.
+    pub fn merge(&mut self, other: FraudDetector) {
+        self.alerts.extend(other.alerts);
+        self.alerts.sort_by_key(|alert| alert.line);
+    }
+}
+
+/// The state of a `FraudDetector` before an event of a client, see `FraudDetector::snapshot`.
+pub(crate) struct FraudSnapshot {
+    client_id: ClientId,
+    rule_states: Vec<(usize, Vec<u8>)>,
+    alert_count: usize,
+}
+
+/// Writes the alerts into `writer` as a csv.
+pub(crate) fn write_alerts(alerts: &[Alert], writer: &mut dyn Write) -> std::io::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(["line", "client", "rule", "action", "reason"])?;
+    for alert in alerts {
+        wtr.write_record([
+            alert.line.to_string(),
+            alert.client_id.to_string(),
+            alert.rule.clone(),
+            alert.action.to_string(),
+            alert.reason.clone(),
+        ])?;
+    }
+    wtr.flush()
+}
+
+/// Whether the last `size` events of the clients were counted by a rule.
+struct EventWindows {
+    size: usize,
+    windows: HashMap<ClientId, VecDeque<bool>>,
+}
+
+impl EventWindows {
+    fn new(size: usize) -> EventWindows {
+        EventWindows {
+            size: size.max(1),
+            windows: HashMap::default(),
+        }
+    }
+
+    /// Returns the number of the counted events in the window of the client.
+    fn push(&mut self, client_id: ClientId, counted: bool) -> usize {
+        let window = self.windows.entry(client_id).or_default();
+        if window.len() == self.size {
+            window.pop_front();
+        }
+        window.push_back(counted);
+        window.iter().filter(|it| **it).count()
+    }
+
+    fn clear(&mut self, client_id: ClientId) {
+        self.windows.remove(&client_id);
+    }
+
+    /// The window of the client, a byte for every event
+    fn state(&self, client_id: ClientId) -> Vec<u8> {
+        self.windows
+            .get(&client_id)
+            .map_or_else(Vec::new, |window| {
+                window.iter().map(|counted| *counted as u8).collect()
+            })
+    }
+
+    /// The inverse of `state`, only the last `size` events are kept if the window got smaller
+    fn restore(&mut self, client_id: ClientId, state: &[u8]) {
+        if state.is_empty() {
+            self.windows.remove(&client_id);
+            return;
+        }
+        let skipped = state.len().saturating_sub(self.size);
+        let window = state[skipped..]
+            .iter()
+            .map(|counted| *counted != 0)
+            .collect();
+        self.windows.insert(client_id, window);
+    }
+}
+
+/// Triggers when more than `max` events of a client are counted within its window.
+/// The window of the client is cleared then, so a single burst triggers only once.
+struct CountingRule {
+    name: &'static str,
+    /// The counted events in the reason of the alert
+    description: &'static str,
+    is_counted: fn(&FraudEvent) -> bool,
+    max: usize,
+    windows: EventWindows,
+}
+
+impl FraudRule for CountingRule {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn evaluate(&mut self, event: &FraudEvent) -> Option<String> {
+        let client_id = ClientId(event.client_id);
+        let count = self.windows.push(client_id, (self.is_counted)(event));
+        if count <= self.max {
+            return None;
+        }
+        self.windows.clear(client_id);
+        Some(format!(
+            "{} {} in the last {} events",
+            count, self.description, self.windows.size
+        ))
+    }
+
+    fn client_state(&self, client_id: u16) -> Vec<u8> {
+        self.windows.state(ClientId(client_id))
+    }
+
+    fn restore_client_state(&mut self, client_id: u16, state: &[u8]) {
+        self.windows.restore(ClientId(client_id), state);
+    }
+}
+
+#[derive(Default)]
+struct DepositThenFullWithdrawal {
+    /// The amount of the last event of the clients, if it was an applied deposit
+    last_deposits: HashMap<ClientId, Amount>,
+}
+
+impl FraudRule for DepositThenFullWithdrawal {
+    fn name(&self) -> &str {
+        "deposit_then_full_withdrawal"
+    }
+
+    fn evaluate(&mut self, event: &FraudEvent) -> Option<String> {
+        let client_id = ClientId(event.client_id);
+        let last_deposit = self.last_deposits.remove(&client_id);
+        match (event.typ, event.outcome, event.amount) {
+            (LedgerEventType::Deposit, Outcome::Applied, Some(amount)) => {
+                self.last_deposits.insert(client_id, amount);
+                None
+            }
+            (LedgerEventType::Withdrawal, Outcome::Applied, Some(amount)) => {
+                let deposit = last_deposit?;
+                (amount >= deposit && event.available == Amount::zero()).then(|| {
+                    format!(
+                        "withdrawal of {} emptied the account right after a deposit of {}",
+                        amount, deposit
+                    )
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The raw amount of the last deposit
+    fn client_state(&self, client_id: u16) -> Vec<u8> {
+        self.last_deposits
+            .get(&ClientId(client_id))
+            .map_or_else(Vec::new, |amount| amount.to_raw().to_le_bytes().to_vec())
+    }
+
+    fn restore_client_state(&mut self, client_id: u16, state: &[u8]) {
+        match state.try_into() {
+            Ok(raw) => self.last_deposits.insert(
+                ClientId(client_id),
+                Amount::from_raw(u64::from_le_bytes(raw)),
+            ),
+            Err(_) => self.last_deposits.remove(&ClientId(client_id)),
+        };
+    }
+}
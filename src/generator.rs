@@ -0,0 +1,345 @@
+//! Generates synthetic input csv, so the benchmarks and measurements can be reproduced.
+//!
+//! The same `GeneratorConfig` (including the seed) always results in the same input.
+//! ```
+//! use std::io::Read;
+//! use transactions_lib::generator::{GeneratorConfig, InputGenerator};
+//!
+//! let mut input = String::new();
+//! InputGenerator::new(GeneratorConfig {
+//!     transactions: 3,
+//!     ..GeneratorConfig::default()
+//! })
+//! .read_to_string(&mut input)
+//! .unwrap();
+//! assert_eq!(input.lines().count(), 4);
+//! assert_eq!(input.lines().next(), Some("type,client,tx,amount"));
+//! ```
+use crate::amount::Amount;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// The number of the most recent deposits which can be disputed by the generated lines,
+/// so the memory usage of the generator does not grow with the input.
+const DISPUTABLE_DEPOSIT_COUNT: usize = 10_000;
+
+/// At most this ratio of the clients are locked by chargebacks, since every line of a locked client is ignored.
+const MAX_LOCKED_CLIENT_RATIO: f64 = 0.1;
+
+/// The largest generated deposit is 1000 (in the raw representation of `Amount`)
+const MAX_DEPOSIT: u64 = 1000 * 10_000;
+
+/// The relative frequency of the generated line types.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mix {
+    pub deposit: u32,
+    pub withdrawal: u32,
+    /// Disputes, and the resolves and chargebacks which close them
+    pub dispute: u32,
+}
+
+impl Default for Mix {
+    fn default() -> Self {
+        Mix {
+            deposit: 70,
+            withdrawal: 20,
+            dispute: 10,
+        }
+    }
+}
+
+/// Parses the `<deposit>,<withdrawal>,<dispute>` format, e.g. `70,20,10`
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "Invalid mix: {}, expected <deposit>,<withdrawal>,<dispute>",
+                s
+            )
+        };
+        let weights = s
+            .split(',')
+            .map(|it| it.trim().parse::<u32>().map_err(|_| error()))
+            .collect::<Result<Vec<u32>, String>>()?;
+        match weights[..] {
+            [deposit, withdrawal, dispute] if weights.iter().any(|it| *it > 0) => Ok(Mix {
+                deposit,
+                withdrawal,
+                dispute,
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// The parameters of the generated input.
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    /// The number of lines (without the header)
+    pub transactions: u32,
+    /// The Client IDs are between 1 and `clients`
+    pub clients: u16,
+    pub mix: Mix,
+    /// The ratio (between 0 and 1) of the lines which are ignored by the processing, e.g. duplicated
+    /// Transaction IDs, withdrawals without enough funds or disputes referencing unknown transactions.
+    /// The rest of the lines are always applied.
+    pub error_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            transactions: 1_000_000,
+            clients: 1000,
+            mix: Mix::default(),
+            error_rate: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// A deposit which can be disputed by a later line
+#[derive(Clone, Copy)]
+struct Deposit {
+    tx_id: u32,
+    client_id: u16,
+    amount: u64,
+}
+
+/// The balances of a client as the processing will see them, so only valid lines are generated for it.
+#[derive(Clone, Copy, Default)]
+struct Balance {
+    available: u64,
+    held: u64,
+    locked: bool,
+}
+
+/// Generates the input csv line by line as it is read, so even huge inputs do not take up memory or disk space.
+pub struct InputGenerator {
+    config: GeneratorConfig,
+    random: Random,
+    generated_lines: u32,
+    next_tx_id: u32,
+    balances: Vec<Balance>,
+    locked_clients: u16,
+    recent_deposits: VecDeque<Deposit>,
+    open_disputes: Vec<Deposit>,
+    line: Vec<u8>,
+    position: usize,
+}
+
+impl InputGenerator {
+    pub fn new(mut config: GeneratorConfig) -> InputGenerator {
+        config.clients = config.clients.max(1);
+        let mut line = Vec::new();
+        line.extend_from_slice(b"type,client,tx,amount\n");
+        InputGenerator {
+            random: Random(config.seed),
+            balances: vec![Balance::default(); config.clients as usize + 1],
+            config,
+            generated_lines: 0,
+            locked_clients: 0,
+            next_tx_id: 1,
+            recent_deposits: VecDeque::new(),
+            open_disputes: Vec::new(),
+            line,
+            position: 0,
+        }
+    }
+
+    /// Writes the next line into `self.line`
+    fn generate_line(&mut self) {
+        self.line.clear();
+        if self.random.chance(self.config.error_rate) {
+            self.generate_ignored_line();
+            return;
+        }
+        let Mix {
+            deposit,
+            withdrawal,
+            dispute,
+        } = self.config.mix;
+        let (deposit, withdrawal, dispute) = (deposit as u64, withdrawal as u64, dispute as u64);
+        let roll = self.random.below(deposit + withdrawal + dispute);
+        let generated = if roll < deposit {
+            false
+        } else if roll < deposit + withdrawal {
+            self.generate_withdrawal()
+        } else {
+            self.generate_dispute()
+        };
+        // falls back to a deposit if no valid line of the chosen type is possible
+        if !generated {
+            self.generate_deposit();
+        }
+    }
+
+    fn generate_deposit(&mut self) {
+        let client_id = self.unlocked_client();
+        let amount = 1 + self.random.below(MAX_DEPOSIT);
+        let tx_id = self.next_tx_id();
+        self.balances[client_id as usize].available += amount;
+        if self.recent_deposits.len() == DISPUTABLE_DEPOSIT_COUNT {
+            self.recent_deposits.pop_front();
+        }
+        self.recent_deposits.push_back(Deposit {
+            tx_id,
+            client_id,
+            amount,
+        });
+        self.write_line("deposit", client_id, tx_id, Some(amount));
+    }
+
+    fn generate_withdrawal(&mut self) -> bool {
+        let client_id = self.unlocked_client();
+        let available = self.balances[client_id as usize].available;
+        if available == 0 {
+            return false;
+        }
+        let amount = 1 + self.random.below(available);
+        self.balances[client_id as usize].available -= amount;
+        let tx_id = self.next_tx_id();
+        self.write_line("withdrawal", client_id, tx_id, Some(amount));
+        true
+    }
+
+    /// Disputes a recent deposit, or closes an open dispute by a resolve or (rarely) by a chargeback.
+    fn generate_dispute(&mut self) -> bool {
+        if !self.open_disputes.is_empty() && self.random.chance(0.5) {
+            let index = self.random.below(self.open_disputes.len() as u64) as usize;
+            let deposit = self.open_disputes.swap_remove(index);
+            let balance = &mut self.balances[deposit.client_id as usize];
+            balance.held -= deposit.amount;
+            let can_be_locked =
+                (self.locked_clients as f64) < self.config.clients as f64 * MAX_LOCKED_CLIENT_RATIO;
+            // a chargeback on a locked client would be ignored
+            let typ = if !balance.locked && can_be_locked && self.random.chance(0.1) {
+                balance.locked = true;
+                self.locked_clients += 1;
+                "chargeback"
+            } else {
+                balance.available += deposit.amount;
+                "resolve"
+            };
+            self.write_line(typ, deposit.client_id, deposit.tx_id, None);
+            return true;
+        }
+        if self.recent_deposits.is_empty() {
+            return false;
+        }
+        let index = self.random.below(self.recent_deposits.len() as u64) as usize;
+        let deposit = self.recent_deposits[index];
+        let balance = &mut self.balances[deposit.client_id as usize];
+        if balance.locked || balance.available < deposit.amount {
+            return false;
+        }
+        self.recent_deposits.swap_remove_back(index);
+        balance.available -= deposit.amount;
+        balance.held += deposit.amount;
+        self.open_disputes.push(deposit);
+        self.write_line("dispute", deposit.client_id, deposit.tx_id, None);
+        true
+    }
+
+    /// Generates a line which does not affect the balances, since it is ignored by the processing.
+    fn generate_ignored_line(&mut self) {
+        let client_id = self.unlocked_client();
+        match self.random.below(4) {
+            0 if self.next_tx_id > 1 => {
+                let duplicated_tx_id = 1 + self.random.below(self.next_tx_id as u64 - 1) as u32;
+                self.write_line("deposit", client_id, duplicated_tx_id, Some(1));
+            }
+            1 => {
+                let amount = self.balances[client_id as usize].available + 1;
+                let tx_id = self.next_tx_id();
+                self.write_line("withdrawal", client_id, tx_id, Some(amount));
+            }
+            2 if !self.recent_deposits.is_empty() => {
+                let index = self.random.below(self.recent_deposits.len() as u64) as usize;
+                let deposit = self.recent_deposits[index];
+                self.write_line("resolve", deposit.client_id, deposit.tx_id, None);
+            }
+            _ => {
+                // the Transaction IDs of the transfers are sequential, so it is never used
+                let unknown_tx_id = u32::MAX - self.random.below(1000) as u32;
+                self.write_line("dispute", client_id, unknown_tx_id, None);
+            }
+        }
+    }
+
+    /// Returns a random client which is not locked, or any client if all of them are locked.
+    fn unlocked_client(&mut self) -> u16 {
+        let clients = self.config.clients as u64;
+        let first = self.random.below(clients);
+        (0..clients)
+            .map(|offset| 1 + ((first + offset) % clients) as u16)
+            .find(|client_id| !self.balances[*client_id as usize].locked)
+            .unwrap_or(1 + first as u16)
+    }
+
+    fn next_tx_id(&mut self) -> u32 {
+        let tx_id = self.next_tx_id;
+        self.next_tx_id += 1;
+        tx_id
+    }
+
+    fn write_line(&mut self, typ: &str, client_id: u16, tx_id: u32, amount: Option<u64>) {
+        match amount {
+            Some(amount) => writeln!(
+                self.line,
+                "{},{},{},{}",
+                typ,
+                client_id,
+                tx_id,
+                Amount::from_raw(amount)
+            ),
+            None => writeln!(self.line, "{},{},{},", typ, client_id, tx_id),
+        }
+        .expect("Writing into a Vec can not fail");
+    }
+}
+
+impl Read for InputGenerator {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.line.len() {
+            if self.generated_lines == self.config.transactions {
+                return Ok(0);
+            }
+            self.generate_line();
+            self.generated_lines += 1;
+            self.position = 0;
+        }
+        let len = buf.len().min(self.line.len() - self.position);
+        buf[..len].copy_from_slice(&self.line[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// A small and fast pseudorandom number generator (SplitMix64), so no dependency is needed
+/// and the generated input is the same on every platform.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..max`, `max` must not be zero
+    fn below(&mut self, max: u64) -> u64 {
+        self.next() % max
+    }
+
+    /// Returns true with the given probability
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
@@ -0,0 +1,187 @@
+use crate::client::{Client, ClientId};
+use crate::transaction::TransactionId;
+use crate::{Amount, HashMap};
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The format of the exported client statements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LedgerFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for LedgerFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "csv" => Ok(LedgerFormat::Csv),
+            "json" => Ok(LedgerFormat::Json),
+            _ => Err(format!("Invalid ledger format: {}", s)),
+        }
+    }
+}
+
+/// The types of the input lines. In the ledger only the balance-affecting events are recorded,
+/// ignored input lines never.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LedgerEventType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+impl Display for LedgerEventType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LedgerEventType::Deposit => "deposit",
+            LedgerEventType::Withdrawal => "withdrawal",
+            LedgerEventType::Dispute => "dispute",
+            LedgerEventType::Resolve => "resolve",
+            LedgerEventType::Chargeback => "chargeback",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A single line of a client statement: the event and the balances of the client right after it.
+#[derive(Debug, Serialize)]
+struct LedgerEntry {
+    line: usize,
+    tx: u32,
+    #[serde(rename = "type")]
+    typ: LedgerEventType,
+    amount: Amount,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+#[derive(Serialize)]
+struct ClientStatement<'a> {
+    client: u16,
+    entries: &'a [LedgerEntry],
+}
+
+/// Records every balance-affecting event per client, with the running balances
+/// and the transaction they originate from.
+pub struct Ledger {
+    entries: HashMap<ClientId, Vec<LedgerEntry>>,
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        Ledger {
+            entries: HashMap::default(),
+        }
+    }
+
+    /// Records an event which has already been applied to `client`.
+    pub fn record(
+        &mut self,
+        line_index: usize,
+        client_id: ClientId,
+        client: &Client,
+        tx_id: TransactionId,
+        typ: LedgerEventType,
+        amount: Amount,
+    ) {
+        self.entries
+            .entry(client_id)
+            .or_default()
+            .push(LedgerEntry {
+                line: line_index,
+                tx: tx_id.0,
+                typ,
+                amount,
+                available: client.available,
+                held: client.held,
+                total: client.total,
+                locked: client.locked,
+            });
+    }
+
+    /// The number of the entries of the client, see `truncate`
+    pub(crate) fn entry_count(&self, client_id: ClientId) -> usize {
+        self.entries.get(&client_id).map_or(0, Vec::len)
+    }
+
+    /// Removes the entries of the client after the first `len`, e.g. the ones of a failed line.
+    pub(crate) fn truncate(&mut self, client_id: ClientId, len: usize) {
+        if len == 0 {
+            self.entries.remove(&client_id);
+        } else if let Some(entries) = self.entries.get_mut(&client_id) {
+            entries.truncate(len);
+        }
+    }
+
+    /// Moves the statements of `other` into this ledger.
+    /// The two ledgers must not share any clients.
+    pub fn merge(&mut self, other: Ledger) {
+        self.entries.extend(other.entries);
+    }
+
+    /// Writes the statements ordered by the Client IDs into `writer`.
+    /// If `only_client_id` is present, only the statement of that client is written.
+    ///
+    /// It can return an `Err` only when there is an error writing to `writer`.
+    pub fn write_to(
+        &self,
+        format: LedgerFormat,
+        only_client_id: Option<ClientId>,
+        writer: &mut dyn std::io::Write,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut keys: Vec<ClientId> = self
+            .entries
+            .keys()
+            .copied()
+            .filter(|id| only_client_id.is_none_or(|only| only == *id))
+            .collect();
+        keys.sort();
+
+        match format {
+            LedgerFormat::Csv => {
+                writeln!(
+                    writer,
+                    "client, line, tx, type, amount, available, held, total, locked"
+                )?;
+                for client_id in keys.iter() {
+                    for entry in self.entries[client_id].iter() {
+                        writeln!(
+                            writer,
+                            "{},{},{},{},{},{},{},{},{}",
+                            client_id.0,
+                            entry.line,
+                            entry.tx,
+                            entry.typ,
+                            entry.amount,
+                            entry.available,
+                            entry.held,
+                            entry.total,
+                            entry.locked
+                        )?;
+                    }
+                }
+            }
+            LedgerFormat::Json => {
+                let statements: Vec<ClientStatement> = keys
+                    .iter()
+                    .map(|client_id| ClientStatement {
+                        client: client_id.0,
+                        entries: &self.entries[client_id],
+                    })
+                    .collect();
+                serde_json::to_writer_pretty(&mut *writer, &statements)?;
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
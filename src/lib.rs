@@ -1,15 +1,52 @@
 pub mod amount;
+mod audit;
+mod changes;
+mod checkpoint;
 mod client;
+mod engine;
+mod fraud;
+pub mod generator;
+mod ledger;
+mod limits;
+mod metrics;
+mod options;
+mod parallel;
+mod reconcile;
+mod scenario;
+mod server;
+mod statistics;
+mod store;
+#[cfg(feature = "async")]
+mod stream;
 mod transaction;
 
 use crate::amount::Amount;
+use crate::changes::ChangeStream;
+pub use crate::changes::ChangesOutput;
+pub use crate::checkpoint::CheckpointConfig;
+use crate::checkpoint::{read_checkpoint, Checkpointer, InputPosition};
 use crate::client::{Client, ClientId};
-use crate::transaction::{
-    DepositedTransaction, Dispute, DisputeState, TransactionId, Transfer, TransferType,
+use crate::engine::Engine;
+pub use crate::engine::{DuplicateCounts, Outcome, RejectionReason};
+use crate::fraud::write_alerts;
+pub use crate::fraud::{
+    Alert, CustomFraudRule, FraudAction, FraudEvent, FraudRule, FraudRuleConfig, FraudRuleKind,
 };
-use csv::{Reader, StringRecord};
+pub use crate::ledger::{LedgerEventType, LedgerFormat};
+pub use crate::limits::{read_limit_overrides, Limits, LimitsConfig};
+use crate::metrics::write_metrics;
+use crate::parallel::run_transactions_in_parallel;
+pub use crate::server::Server;
+use crate::statistics::Summary;
+pub use crate::statistics::SummaryFormat;
+pub use crate::store::StoreConfig;
+#[cfg(feature = "async")]
+pub use crate::stream::{AsyncEngine, AsyncError};
+use crate::transaction::{Dispute, DisputeState, TransactionId, Transfer, TransferType};
+use csv::{ByteRecord, Reader, StringRecord};
 use std::error::Error;
 use std::io::Read;
+use std::time::{Duration, Instant};
 
 /// A type definition for HashMap, so it is easy to replace the implementation if needed.
 /// FxHashMap is 10 times faster on my computer
@@ -18,12 +55,246 @@ type HashMap<K, V> = rustc_hash::FxHashMap<K, V>;
 // uncomment this if you want the implementation from the standard lib
 //type HashMap<K, V> = std::collections::HashMap<K, V>;
 
+/// The durations of the options are given in hours or days, the timestamps of the input are in seconds
+pub(crate) const SECONDS_PER_HOUR: u64 = 60 * 60;
+
+pub(crate) const SECONDS_PER_DAY: u64 = 24 * SECONDS_PER_HOUR;
+
+/// The options of the processing, the `Default` values result in the behaviour described in the task.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Where the deposits are stored, which might be referenced later by a dispute
+    pub store: StoreConfig,
+    /// If present, deposits older than this can not be disputed, and they are evicted from the store.
+    /// It requires the optional `timestamp` column in the input, deposits without timestamp never expire.
+    pub dispute_window: Option<Duration>,
+    /// The number of worker threads the clients are distributed between.
+    /// Values below 2 mean the lines are processed on the calling thread, and so does a persistent `store`.
+    pub workers: usize,
+    /// If true, the client states are written in an arbitrary order instead of being sorted by their Client IDs.
+    pub unsorted: bool,
+    /// If present, the state of the processing is saved periodically, so it can be continued by
+    /// `resume_input_then_write_outputs` after a crash. The lines are always processed on the calling thread
+    /// in this case, and it can not be used with a persistent `store`. The queries do not write checkpoints.
+    pub checkpoint: Option<CheckpointConfig>,
+    /// If true, the balance invariants are checked after every applied event: `available + held == total`
+    /// for the changed client, and the sum of the client totals equals the deposits minus the withdrawals
+    /// and the chargebacks. The processing stops with an error at the first violating line.
+    pub verify: bool,
+    /// The withdrawal and balance limits of the clients, a line violating them is rejected.
+    /// Nothing is limited by default.
+    pub limits: LimitsConfig,
+    /// The fraud heuristics evaluated after every input line, they can flag or lock the clients.
+    /// None of them is used by default.
+    pub fraud_rules: Vec<FraudRuleConfig>,
+}
+
+/// The optional outputs of the processing besides the client states.
+#[derive(Default)]
+pub struct Outputs<'a> {
+    /// If present, every balance-affecting event is recorded, and the per-client statements are written into it
+    pub ledger: Option<LedgerOutput<'a>>,
+    /// If present, every ignored input line is written into it as a csv, together with the reason
+    pub rejections: Option<&'a mut dyn std::io::Write>,
+    /// If present, the client states are written into it as they change during the processing.
+    /// The lines are always processed on the calling thread in this case, `Config::workers` is ignored.
+    pub changes: Option<ChangesOutput<'a>>,
+    /// If present, a summary of the processing is written into it at the end, e.g. the number of the lines
+    /// per type and per rejection reason, the volumes and the throughput
+    pub summary: Option<SummaryOutput<'a>>,
+    /// If present, the metrics of the engine are written into it at the end in the Prometheus text format,
+    /// e.g. the number of the lines per type and per rejection reason, the locked clients and the held funds
+    pub metrics: Option<&'a mut dyn std::io::Write>,
+    /// If present, the alerts of the `Config::fraud_rules` are written into it at the end as a csv
+    pub alerts: Option<&'a mut dyn std::io::Write>,
+}
+
+pub struct SummaryOutput<'a> {
+    pub writer: &'a mut dyn std::io::Write,
+    pub format: SummaryFormat,
+}
+
+pub struct LedgerOutput<'a> {
+    pub writer: &'a mut dyn std::io::Write,
+    pub format: LedgerFormat,
+    /// If present, only the statement of this client is written
+    pub client_id: Option<u16>,
+}
+
+/// The point of the input until the lines are processed in `query_input_as_of_then_write_output`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOf {
+    /// The lines up to and including this line are processed.
+    /// Line numbers are counted the same way as in the error messages, the header is not counted,
+    /// so the first transaction is line 1.
+    Line(usize),
+    /// The lines are processed until the first line with a later timestamp.
+    /// Lines without timestamp are processed until then.
+    Timestamp(u64),
+}
+
 /// It reads the csv in the expected format from the `input` and write the result client states into
 /// the `output`
-pub fn process_input_then_write_output(input: impl std::io::Read, mut output: impl std::io::Write) {
-    match run_transactions(input) {
-        Ok(result) => {
-            if let Err(err) = write_client_states_to(result, &mut output) {
+pub fn process_input_then_write_output(input: impl std::io::Read, output: impl std::io::Write) {
+    process_input_with_config_then_write_output(input, output, &Config::default());
+}
+
+/// Same as `process_input_then_write_output`, but the processing can be customized by `config`.
+pub fn process_input_with_config_then_write_output(
+    input: impl std::io::Read,
+    output: impl std::io::Write,
+    config: &Config,
+) {
+    process_input_then_write_outputs(input, output, Outputs::default(), config);
+}
+
+/// Same as `process_input_with_config_then_write_output`, but the optional `outputs` are written as well.
+pub fn process_input_then_write_outputs(
+    input: impl std::io::Read,
+    output: impl std::io::Write,
+    mut outputs: Outputs,
+    config: &Config,
+) {
+    let started = Instant::now();
+    let result = run_transactions_with_config(
+        input,
+        None,
+        config,
+        outputs.ledger.is_some(),
+        outputs.rejections.take(),
+        outputs.changes.take(),
+        None,
+    );
+    write_outputs(result, output, outputs, started, config);
+}
+
+/// Same as `process_input_then_write_outputs`, but if the checkpoint of `config.checkpoint` exists,
+/// the state is restored from it, and the processing continues from the input line after it,
+/// so every line is applied exactly once.
+/// If there is no checkpoint yet, the whole input is processed.
+///
+/// The optional outputs contain only the lines processed by this run,
+/// e.g. the ledger does not contain the events before the checkpoint.
+pub fn resume_input_then_write_outputs(
+    mut input: impl std::io::Read + std::io::Seek,
+    mut output: impl std::io::Write,
+    mut outputs: Outputs,
+    config: &Config,
+) {
+    let started = Instant::now();
+    let resumed = match config.checkpoint.as_ref() {
+        Some(checkpoint) => read_checkpoint(&checkpoint.path, config),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Resuming requires a checkpoint configuration",
+        )),
+    };
+    let resumed = resumed.and_then(|resumed| {
+        if let Some((position, _)) = resumed.as_ref() {
+            input.seek(std::io::SeekFrom::Start(position.byte_offset))?;
+        }
+        Ok(resumed)
+    });
+    let result = match resumed {
+        Ok(resumed) => run_transactions_with_config(
+            input,
+            None,
+            config,
+            outputs.ledger.is_some(),
+            outputs.rejections.take(),
+            outputs.changes.take(),
+            resumed,
+        ),
+        Err(err) => {
+            writeln!(
+                output,
+                "Error: Could not resume from the checkpoint: {}",
+                err
+            )
+            .unwrap();
+            return;
+        }
+    };
+    write_outputs(result, output, outputs, started, config);
+}
+
+/// Writes the client states and the outputs written at the end (the ledger, the summary, the metrics and the alerts)
+/// of a finished processing, or the error which stopped it.
+fn write_outputs(
+    result: Result<Engine, Box<dyn Error>>,
+    mut output: impl std::io::Write,
+    outputs: Outputs,
+    started: Instant,
+    config: &Config,
+) {
+    let Outputs {
+        ledger,
+        summary,
+        metrics,
+        alerts,
+        ..
+    } = outputs;
+    match result {
+        Ok(engine) => {
+            if let Err(err) =
+                write_client_states_to(engine.clients(), None, !config.unsorted, &mut output)
+            {
+                writeln!(output, "Error: {}", err).unwrap();
+            }
+            if let (Some(ledger), Some(ledger_output)) = (engine.ledger(), ledger) {
+                if let Err(err) = ledger.write_to(
+                    ledger_output.format,
+                    ledger_output.client_id.map(ClientId),
+                    ledger_output.writer,
+                ) {
+                    writeln!(output, "Error: {}", err).unwrap();
+                }
+            }
+            if let Some(summary) = summary {
+                let elapsed = started.elapsed();
+                if let Err(err) =
+                    Summary::new(&engine, elapsed).write_to(summary.format, summary.writer)
+                {
+                    writeln!(output, "Error: {}", err).unwrap();
+                }
+            }
+            if let Some(metrics) = metrics {
+                if let Err(err) = write_metrics(&engine, metrics) {
+                    writeln!(output, "Error: {}", err).unwrap();
+                }
+            }
+            if let Some(alerts) = alerts {
+                if let Err(err) = write_alerts(engine.alerts(), alerts) {
+                    writeln!(output, "Error: {}", err).unwrap();
+                }
+            }
+        }
+        Err(err) => {
+            log::error!(error:% = err; "processing stopped");
+            writeln!(output, "Error: {}", err).unwrap();
+        }
+    }
+}
+
+/// Reads the csv in the expected format from the `input`, applies only the lines until `as_of`,
+/// then writes the client states at that point into the `output`.
+///
+/// If `client_id` is present, only the state of that client is written.
+pub fn query_input_as_of_then_write_output(
+    input: impl std::io::Read,
+    as_of: AsOf,
+    client_id: Option<u16>,
+    mut output: impl std::io::Write,
+    config: &Config,
+) {
+    match run_transactions_with_config(input, Some(as_of), config, false, None, None, None) {
+        Ok(engine) => {
+            if let Err(err) = write_client_states_to(
+                engine.clients(),
+                client_id.map(ClientId),
+                !config.unsorted,
+                &mut output,
+            ) {
                 writeln!(output, "Error: {}", err).unwrap();
             }
         }
@@ -33,23 +304,76 @@ pub fn process_input_then_write_output(input: impl std::io::Read, mut output: im
     }
 }
 
+/// Reads the csv in the expected format from the `input`, processes it, then compares the client states
+/// with the `expected` balances (a csv in the same format as the output), and writes the differences into `report`.
+///
+/// Returns `true` only if every client matches, an invalid input or balances file is written into `report`
+/// as an error.
+pub fn reconcile_input_then_write_report(
+    input: impl std::io::Read,
+    expected: impl std::io::Read,
+    mut report: impl std::io::Write,
+    config: &Config,
+) -> bool {
+    let result = reconcile::read_expected_balances(expected).and_then(|expected| {
+        let engine = run_transactions_with_config(input, None, config, false, None, None, None)?;
+        Ok(reconcile::write_differences(
+            engine.clients(),
+            &expected,
+            &mut report,
+        )?)
+    });
+    match result {
+        Ok(difference_count) => difference_count == 0,
+        Err(err) => {
+            writeln!(report, "Error: {}", err).unwrap();
+            false
+        }
+    }
+}
+
+/// Runs the scenarios in the subdirectories of `dir` (an input csv with the expected outputs in each of them,
+/// see the README), and writes which of them passed into `report`, with the differences of the failed ones.
+///
+/// Returns `true` only if every scenario passed.
+pub fn run_scenarios_then_write_report(
+    dir: &std::path::Path,
+    mut report: impl std::io::Write,
+) -> bool {
+    match scenario::run_scenarios(dir, &mut report) {
+        Ok((_, failed)) => failed == 0,
+        Err(err) => {
+            writeln!(report, "Error: {}", err).unwrap();
+            false
+        }
+    }
+}
+
 /// Writes the clients state passed in the `result` argument into `writer`.
 /// The output format is a csv defined in the task description.
+/// If `only_client_id` is present, only that client is written.
+/// If `sorted` is true, the clients are ordered by their Client IDs.
 ///
 /// It can return an `Err` only when there is an error writing to `writer`.
 fn write_client_states_to(
-    result: HashMap<ClientId, Client>,
+    result: &HashMap<ClientId, Client>,
+    only_client_id: Option<ClientId>,
+    sorted: bool,
     writer: &mut impl std::io::Write,
 ) -> Result<(), Box<dyn Error>> {
     writeln!(writer, "client, available, held, total, locked")?;
     // @doc
-    // In order to be able to verify the output easily in the integration tests,
-    // the output is ordered.
-    // It has some unnecessary performance penalty since it is not a requirement.
-    // In a real world scenario with more time I would implement a more sophisticated test
-    // utility which does not have assumption about output ordering.
-    let mut keys: Vec<ClientId> = result.keys().map(|it| *it).collect();
-    keys.sort();
+    // The output is ordered by default, so it is easier to read and compare for humans.
+    // It has some unnecessary performance penalty since it is not a requirement,
+    // so it can be turned off by `Config::unsorted`. The integration tests do not depend on the ordering.
+    let mut keys: Vec<ClientId> = result
+        .keys()
+        .copied()
+        .filter(|id| only_client_id.is_none_or(|only| only == *id))
+        .collect();
+    if sorted {
+        keys.sort();
+    }
 
     for client_id in keys.iter() {
         let client = &result[client_id];
@@ -63,112 +387,226 @@ fn write_client_states_to(
     Ok(())
 }
 
+/// Creates the engine(s) according to `config`, then process the lines with `run_transactions_until`,
+/// or with `run_transactions_in_parallel` if multiple workers are configured.
+///
+/// If `resumed` is present, its engine is used, and `reader` has to be at its position.
+fn run_transactions_with_config(
+    reader: impl std::io::Read,
+    as_of: Option<AsOf>,
+    config: &Config,
+    with_ledger: bool,
+    rejections: Option<&mut dyn std::io::Write>,
+    changes: Option<ChangesOutput>,
+    resumed: Option<(InputPosition, Engine)>,
+) -> Result<Engine, Box<dyn Error>> {
+    let checkpoint = config.checkpoint.as_ref().filter(|_| as_of.is_none());
+    if checkpoint.is_some() && config.store.is_persistent() {
+        return Err(
+            "Checkpoints can not be used with a persistent store, it saves the progress itself"
+                .into(),
+        );
+    }
+    // the changes have to be written in the order of the input lines,
+    // a persistent store holds the state of every client, so it can not be sharded,
+    // and a checkpoint needs the state of every client at the same line
+    if config.workers > 1
+        && changes.is_none()
+        && !config.store.is_persistent()
+        && checkpoint.is_none()
+    {
+        return run_transactions_in_parallel(reader, as_of, config, with_ledger, rejections);
+    }
+    let (start, engine) = match resumed {
+        Some((position, mut engine)) => {
+            if with_ledger {
+                engine.record_ledger();
+            }
+            (position, engine)
+        }
+        None if with_ledger => (InputPosition::default(), Engine::with_ledger(config)?),
+        None => (InputPosition::default(), Engine::new(config)?),
+    };
+    run_transactions_until(
+        reader,
+        as_of,
+        start,
+        engine,
+        rejections,
+        changes,
+        checkpoint.map(Checkpointer::new),
+    )
+}
+
 /// Reads the csv from `reader` and process them according to the documentation.
-/// The output is a `HashMap<ClientId, Client>`, the state of the clients after the transactions have affected them.
-fn run_transactions(
+/// The output is the `engine`, containing the state of the clients after the transactions have affected them.
+///
+/// If `as_of` is present, the lines after it are not processed (and not even applied),
+/// so the result is the state of the clients as of that point.
+/// If `rejections` is present, the ignored lines are written into it.
+/// If `changes` is present, the changed client states are written into it during the processing.
+/// If `checkpointer` is present, the checkpoints are written during the processing and at the end.
+///
+/// The reading starts at `start` (the header is expected only at the start of the input),
+/// and the lines already applied by a previous run (see `Engine::last_line`) are skipped.
+fn run_transactions_until(
+    reader: impl std::io::Read,
+    as_of: Option<AsOf>,
+    start: InputPosition,
+    mut engine: Engine,
+    mut rejections: Option<&mut dyn std::io::Write>,
+    changes: Option<ChangesOutput>,
+    mut checkpointer: Option<Checkpointer>,
+) -> Result<Engine, Box<dyn Error>> {
+    if let Some(rejections) = rejections.as_mut() {
+        Rejection::write_header_to(rejections)?;
+    }
+    let mut changes = changes.map(ChangeStream::new).transpose()?;
+    let resumed_line = engine.last_line();
+    let mut position = start;
+    read_input_lines(reader, as_of, start, |line_index, csv_line, next_offset| {
+        position = InputPosition {
+            byte_offset: next_offset,
+            line_index,
+        };
+        if line_index <= resumed_line {
+            return Ok(());
+        }
+        let client_id = csv_line.client_id();
+        let rejection = Rejection::prepare(line_index, &csv_line);
+        let is_locked =
+            |engine: &Engine| engine.clients().get(&client_id).is_some_and(|it| it.locked);
+        // a fraud rule can lock the client even if the line is rejected
+        let was_locked = changes.is_some() && is_locked(&engine);
+        let outcome = engine.apply(line_index, csv_line)?;
+        if let Outcome::Rejected(reason) = outcome {
+            if let Some(rejections) = rejections.as_mut() {
+                rejection.with_reason(reason).write_to(rejections)?;
+            }
+        }
+        if let Some(changes) = changes.as_mut() {
+            let changed = outcome == Outcome::Applied || was_locked != is_locked(&engine);
+            let changed_client_id = changed.then_some(client_id);
+            changes.line_processed(line_index, changed_client_id, engine.clients())?;
+        }
+        if let Some(checkpointer) = checkpointer.as_mut() {
+            checkpointer.line_processed(position, &mut engine)?;
+        }
+        Ok(())
+    })?;
+    if let Some(changes) = changes.as_mut() {
+        changes.finish(engine.clients())?;
+    }
+    if let Some(checkpointer) = checkpointer.as_mut() {
+        checkpointer.finish(position, &mut engine)?;
+    }
+    Ok(engine)
+}
+
+/// Reads and parses the csv from `reader`, and calls `apply_line` for every line until `as_of`,
+/// with the line index and the offset of the next line.
+///
+/// `reader` has to be at the `start` position of the input, the header is read and validated only at the start.
+fn read_input_lines(
     reader: impl std::io::Read,
-) -> Result<HashMap<ClientId, Client>, Box<dyn Error>> {
+    as_of: Option<AsOf>,
+    start: InputPosition,
+    mut apply_line: impl FnMut(usize, InputCsvLine, u64) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let is_at_start = start == InputPosition::default();
     let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
+        .has_headers(is_at_start)
         .flexible(true)
         .from_reader(reader);
 
-    validate_header(&mut rdr)?;
-
-    let mut transactions: HashMap<TransactionId, Transfer> = HashMap::default();
-    let mut clients: HashMap<ClientId, Client> = HashMap::default();
-    for (record_index, result) in rdr.records().enumerate() {
-        let line_index = record_index + 1;
-        let csv_line: InputCsvLine = parse_transaction(line_index, result?)?;
-        match csv_line {
-            InputCsvLine::Transfer(tx) => {
-                if transactions.contains_key(&tx.id) {
-                    continue;
-                }
-                let client = get_or_create_client(&mut clients, tx.client_id);
-                match &tx.typ {
-                    TransferType::Deposit(DepositedTransaction { amount, .. }) => {
-                        if !client.locked {
-                            client.deposit(*amount);
-                        }
-                    }
-                    TransferType::Withdrawal { amount } => {
-                        if !client.locked {
-                            client.withdrawal(*amount);
-                        }
-                    }
-                }
-                transactions.insert(tx.id, tx);
-            }
-            InputCsvLine::Dispute(dispute) => {
-                match dispute.state {
-                    DisputeState::Dispute => {
-                        if let Some(deposit) = get_deposit_transaction(&mut transactions, &dispute)
-                        {
-                            if deposit.disputed == false {
-                                let client = get_or_create_client(&mut clients, dispute.client_id);
-                                // TODO @clarify What to do when client does not have the available amount?
-                                if client.locked == false && client.available >= deposit.amount {
-                                    client.dispute(deposit.amount);
-                                    deposit.disputed = true;
-                                }
-                            }
-                        } else {
-                            // according to the business requirements, non existing referenced transactions are expected
-                        }
-                    }
-                    DisputeState::Resolve => {
-                        if let Some(deposit) = get_deposit_transaction(&mut transactions, &dispute)
-                        {
-                            if deposit.disputed {
-                                let client = get_or_create_client(&mut clients, dispute.client_id);
-                                client.resolve(deposit.amount);
-                                deposit.disputed = false;
-                            } else {
-                                // according to the business requirements, it is an error on our partner's side
-                            }
-                        } else {
-                            // according to the business requirements, non existing referenced transactions are expected
-                        }
-                    }
-                    DisputeState::Chargeback => {
-                        if let Some(deposit) = get_deposit_transaction(&mut transactions, &dispute)
-                        {
-                            if deposit.disputed {
-                                let client = get_or_create_client(&mut clients, dispute.client_id);
-                                if !client.locked {
-                                    client.chargeback(deposit.amount);
-                                    deposit.disputed = false;
-                                }
-                            } else {
-                                // according to the business requirements, it is an error on our partner's side
-                            }
-                        } else {
-                            // according to the business requirements, non existing referenced transactions are expected
-                        }
-                    }
-                }
+    if is_at_start {
+        validate_header(&mut rdr)?;
+    }
+
+    // the same record is reused for every line, so there is no allocation per line
+    let mut record = ByteRecord::new();
+    let mut line_index = start.line_index;
+    while rdr.read_byte_record(&mut record)? {
+        line_index += 1;
+        if matches!(as_of, Some(AsOf::Line(last_line)) if line_index > last_line) {
+            break;
+        }
+        let csv_line: InputCsvLine = parse_transaction(line_index, &record)?;
+        if let (Some(AsOf::Timestamp(as_of)), Some(timestamp)) = (as_of, csv_line.timestamp()) {
+            if timestamp > as_of {
+                break;
             }
         }
+        apply_line(
+            line_index,
+            csv_line,
+            start.byte_offset + rdr.position().byte(),
+        )?;
+    }
+    Ok(())
+}
+
+/// An ignored input line, as it is written into the rejections output.
+struct Rejection {
+    line_index: usize,
+    typ: &'static str,
+    client_id: ClientId,
+    tx_id: TransactionId,
+    reason: Option<RejectionReason>,
+}
+
+impl Rejection {
+    /// Saves the identifying fields of the line before it is consumed by `Engine::apply`
+    fn prepare(line_index: usize, csv_line: &InputCsvLine) -> Rejection {
+        Rejection {
+            line_index,
+            typ: csv_line.type_name(),
+            client_id: csv_line.client_id(),
+            tx_id: csv_line.tx_id(),
+            reason: None,
+        }
+    }
+
+    fn with_reason(self, reason: RejectionReason) -> Rejection {
+        Rejection {
+            reason: Some(reason),
+            ..self
+        }
+    }
+
+    fn write_header_to(writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        writeln!(writer, "line, type, client, tx, reason")
+    }
+
+    fn write_to(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let reason = self.reason.map(|it| it.to_string()).unwrap_or_default();
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            self.line_index, self.typ, self.client_id.0, self.tx_id.0, reason
+        )
     }
-    return Ok(clients);
 }
 
 /// Validate the header of the input csv.
 fn validate_header(rdr: &mut Reader<impl Read>) -> Result<(), Box<dyn Error>> {
-    let headers = rdr.headers()?;
+    validate_header_record(rdr.headers()?.as_byte_record())
+}
 
+fn validate_header_record(headers: &ByteRecord) -> Result<(), Box<dyn Error>> {
     let error_msg = "Expected columns: type, client, tx, amount";
-    if headers.len() != 4 {
-        return Err(Box::new(CsvParsingError::new(error_msg)));
-    }
-    if headers[0].trim().to_ascii_lowercase() != "type" {
-        return Err(Box::new(CsvParsingError::new(error_msg)));
-    } else if headers[1].trim().to_ascii_lowercase() != "client" {
-        return Err(Box::new(CsvParsingError::new(error_msg)));
-    } else if headers[2].trim().to_ascii_lowercase() != "tx" {
-        return Err(Box::new(CsvParsingError::new(error_msg)));
-    } else if headers[3].trim().to_ascii_lowercase() != "amount" {
+    let expected_columns = ["type", "client", "tx", "amount", "timestamp"];
+    // the `timestamp` column is optional
+    if !(4..=5).contains(&headers.len())
+        || !headers
+            .iter()
+            .zip(expected_columns)
+            .all(|(column, expected)| {
+                column
+                    .trim_ascii()
+                    .eq_ignore_ascii_case(expected.as_bytes())
+            })
+    {
         return Err(Box::new(CsvParsingError::new(error_msg)));
     }
 
@@ -206,85 +644,290 @@ enum InputCsvLine {
     Dispute(Dispute),
 }
 
-/// Parses a single input csv line
+impl InputCsvLine {
+    fn timestamp(&self) -> Option<u64> {
+        match self {
+            InputCsvLine::Transfer(tx) => tx.timestamp,
+            InputCsvLine::Dispute(dispute) => dispute.timestamp,
+        }
+    }
+
+    fn client_id(&self) -> ClientId {
+        match self {
+            InputCsvLine::Transfer(tx) => tx.client_id,
+            InputCsvLine::Dispute(dispute) => dispute.client_id,
+        }
+    }
+
+    fn tx_id(&self) -> TransactionId {
+        match self {
+            InputCsvLine::Transfer(tx) => tx.id,
+            InputCsvLine::Dispute(dispute) => dispute.disputed_tx_id,
+        }
+    }
+
+    /// The type as it is in the input csv
+    fn type_name(&self) -> &'static str {
+        match self {
+            InputCsvLine::Transfer(Transfer {
+                typ: TransferType::Deposit { .. },
+                ..
+            }) => "deposit",
+            InputCsvLine::Transfer(Transfer {
+                typ: TransferType::Withdrawal { .. },
+                ..
+            }) => "withdrawal",
+            InputCsvLine::Dispute(Dispute { state, .. }) => match state {
+                DisputeState::Dispute => "dispute",
+                DisputeState::Resolve => "resolve",
+                DisputeState::Chargeback => "chargeback",
+            },
+        }
+    }
+}
+
+/// A parsed input line, so the transactions can be passed to the engine without csv, e.g. to `AsyncEngine::process`.
+pub struct Transaction(InputCsvLine);
+
+impl Transaction {
+    pub fn deposit(client_id: u16, tx_id: u32, amount: Amount) -> Transaction {
+        Transaction::transfer(client_id, tx_id, TransferType::Deposit { amount })
+    }
+
+    pub fn withdrawal(client_id: u16, tx_id: u32, amount: Amount) -> Transaction {
+        Transaction::transfer(client_id, tx_id, TransferType::Withdrawal { amount })
+    }
+
+    /// `tx_id` is the ID of the disputed deposit
+    pub fn dispute(client_id: u16, tx_id: u32) -> Transaction {
+        Transaction::dispute_state(client_id, tx_id, DisputeState::Dispute)
+    }
+
+    /// `tx_id` is the ID of the disputed deposit
+    pub fn resolve(client_id: u16, tx_id: u32) -> Transaction {
+        Transaction::dispute_state(client_id, tx_id, DisputeState::Resolve)
+    }
+
+    /// `tx_id` is the ID of the disputed deposit
+    pub fn chargeback(client_id: u16, tx_id: u32) -> Transaction {
+        Transaction::dispute_state(client_id, tx_id, DisputeState::Chargeback)
+    }
+
+    /// The same as the optional `timestamp` column of the input
+    pub fn with_timestamp(self, timestamp: u64) -> Transaction {
+        Transaction(match self.0 {
+            InputCsvLine::Transfer(tx) => InputCsvLine::Transfer(Transfer {
+                timestamp: Some(timestamp),
+                ..tx
+            }),
+            InputCsvLine::Dispute(dispute) => InputCsvLine::Dispute(Dispute {
+                timestamp: Some(timestamp),
+                ..dispute
+            }),
+        })
+    }
+
+    fn transfer(client_id: u16, tx_id: u32, typ: TransferType) -> Transaction {
+        Transaction(InputCsvLine::Transfer(Transfer {
+            id: TransactionId(tx_id),
+            client_id: ClientId(client_id),
+            typ,
+            timestamp: None,
+        }))
+    }
+
+    fn dispute_state(client_id: u16, tx_id: u32, state: DisputeState) -> Transaction {
+        Transaction(InputCsvLine::Dispute(Dispute {
+            disputed_tx_id: TransactionId(tx_id),
+            client_id: ClientId(client_id),
+            state,
+            timestamp: None,
+        }))
+    }
+}
+
+/// Parses a single input csv line.
+/// It works on the raw bytes of the line, so there is no UTF-8 validation and allocation.
 fn parse_transaction(
+    line_index: usize,
+    columns: &ByteRecord,
+) -> Result<InputCsvLine, CsvParsingError> {
+    let column = |index: usize| columns.get(index).unwrap_or_default().trim_ascii();
+    let typ = column(0);
+    let client_id = ClientId(parse_integer(column(1)).ok_or_else(|| {
+        CsvParsingError::new(format!("Invalid Client ID at line {}", line_index))
+    })?);
+    let tx_id = TransactionId(parse_integer(column(2)).ok_or_else(|| {
+        CsvParsingError::new(format!("Invalid Transaction ID at line {}", line_index))
+    })?);
+    let timestamp = match column(4) {
+        b"" => None,
+        timestamp => Some(parse_integer(timestamp).ok_or_else(|| {
+            CsvParsingError::new(format!("Invalid timestamp at line {}", line_index))
+        })?),
+    };
+    let amount = || {
+        Amount::parse_bytes(column(3))
+            .ok_or_else(|| CsvParsingError::new(format!("Invalid amount at line {}", line_index)))
+    };
+    match typ {
+        b"withdrawal" => Ok(InputCsvLine::Transfer(Transfer {
+            id: tx_id,
+            client_id,
+            typ: TransferType::Withdrawal { amount: amount()? },
+            timestamp,
+        })),
+        b"deposit" => Ok(InputCsvLine::Transfer(Transfer {
+            id: tx_id,
+            client_id,
+            typ: TransferType::Deposit { amount: amount()? },
+            timestamp,
+        })),
+        b"dispute" => Ok(InputCsvLine::Dispute(Dispute {
+            disputed_tx_id: tx_id,
+            client_id,
+            state: DisputeState::Dispute,
+            timestamp,
+        })),
+        b"resolve" => Ok(InputCsvLine::Dispute(Dispute {
+            disputed_tx_id: tx_id,
+            client_id,
+            state: DisputeState::Resolve,
+            timestamp,
+        })),
+        b"chargeback" => Ok(InputCsvLine::Dispute(Dispute {
+            disputed_tx_id: tx_id,
+            client_id,
+            state: DisputeState::Chargeback,
+            timestamp,
+        })),
+        _ => Err(CsvParsingError::new(format!(
+            "Invalid transaction type: {}",
+            String::from_utf8_lossy(typ)
+        ))),
+    }
+}
+
+/// Parses an unsigned integer from its ASCII digits, the same way as `str::parse`, but without UTF-8 validation.
+/// Returns `None` if it is not a valid number or it does not fit into `T`.
+fn parse_integer<T: TryFrom<u64>>(bytes: &[u8]) -> Option<T> {
+    let digits = bytes.strip_prefix(b"+").unwrap_or(bytes);
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for digit in digits {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        value = value.checked_mul(10)?.checked_add((digit - b'0') as u64)?;
+    }
+    T::try_from(value).ok()
+}
+
+/// The ways of parsing the input which are compared by the benchmarks in `benches/parsing.rs`.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub enum InputParser {
+    /// A new `StringRecord` is allocated for every line, and the columns are parsed as `str`s.
+    /// It was the only parser before the `ByteRecord` one, and it is kept only for the comparison.
+    StringRecord,
+    /// The parser used by the processing.
+    ByteRecord,
+}
+
+/// Parses every line of the input with the given parser without processing them, and returns the number of lines.
+/// It is public only for the benchmarks.
+#[doc(hidden)]
+pub fn parse_input_with(
+    input: impl std::io::Read,
+    parser: InputParser,
+) -> Result<usize, Box<dyn Error>> {
+    let mut line_count = 0;
+    match parser {
+        InputParser::StringRecord => {
+            let mut rdr = csv::ReaderBuilder::new()
+                .has_headers(true)
+                .flexible(true)
+                .from_reader(input);
+            validate_header(&mut rdr)?;
+            for (record_index, result) in rdr.records().enumerate() {
+                parse_string_record(record_index + 1, result?)?;
+                line_count += 1;
+            }
+        }
+        InputParser::ByteRecord => {
+            read_input_lines(input, None, InputPosition::default(), |_, _, _| {
+                line_count += 1;
+                Ok(())
+            })?;
+        }
+    }
+    Ok(line_count)
+}
+
+/// The original `StringRecord` based version of `parse_transaction`, see `InputParser::StringRecord`.
+fn parse_string_record(
     line_index: usize,
     columns: StringRecord,
 ) -> Result<InputCsvLine, CsvParsingError> {
-    let typ = columns[0].trim();
-    let client_id = ClientId(columns[1].trim().parse().map_err(|_err| {
+    // the reader is flexible, so a line can have less columns than the header
+    let column = |index: usize| columns.get(index).unwrap_or_default().trim();
+    let typ = column(0);
+    let client_id = ClientId(column(1).parse().map_err(|_err| {
         CsvParsingError::new(format!("Invalid Client ID at line {}", line_index))
     })?);
-    let tx_id = TransactionId(columns[2].trim().parse().map_err(|_err| {
+    let tx_id = TransactionId(column(2).parse().map_err(|_err| {
         CsvParsingError::new(format!("Invalid Transaction ID at line {}", line_index))
     })?);
+    let timestamp = match column(4) {
+        "" => None,
+        timestamp => Some(timestamp.parse().map_err(|_err| {
+            CsvParsingError::new(format!("Invalid timestamp at line {}", line_index))
+        })?),
+    };
     match typ {
         "withdrawal" => Ok(InputCsvLine::Transfer(Transfer {
             id: tx_id,
             client_id,
             typ: TransferType::Withdrawal {
-                amount: Amount::parse(columns[3].trim()).ok_or_else(|| {
+                amount: Amount::parse(column(3)).ok_or_else(|| {
                     CsvParsingError::new(format!("Invalid amount at line {}", line_index))
                 })?,
             },
+            timestamp,
         })),
         "deposit" => Ok(InputCsvLine::Transfer(Transfer {
             id: tx_id,
             client_id,
-            typ: TransferType::Deposit(DepositedTransaction {
-                amount: Amount::parse(columns[3].trim()).ok_or_else(|| {
+            typ: TransferType::Deposit {
+                amount: Amount::parse(column(3)).ok_or_else(|| {
                     CsvParsingError::new(format!("Invalid amount at line {}", line_index))
                 })?,
-                disputed: false,
-            }),
+            },
+            timestamp,
         })),
         "dispute" => Ok(InputCsvLine::Dispute(Dispute {
             disputed_tx_id: tx_id,
             client_id,
             state: DisputeState::Dispute,
+            timestamp,
         })),
         "resolve" => Ok(InputCsvLine::Dispute(Dispute {
             disputed_tx_id: tx_id,
             client_id,
             state: DisputeState::Resolve,
+            timestamp,
         })),
         "chargeback" => Ok(InputCsvLine::Dispute(Dispute {
             disputed_tx_id: tx_id,
             client_id,
             state: DisputeState::Chargeback,
+            timestamp,
         })),
-        _ => {
-            return Err(CsvParsingError::new(format!(
-                "Invalid transaction type: {}",
-                typ
-            )))
-        }
+        _ => Err(CsvParsingError::new(format!(
+            "Invalid transaction type: {}",
+            typ
+        ))),
     }
 }
-
-/// A utility function which returns a transaction referenced by the dispute, if the
-/// transaction is a dispute and has the same Client ID as the dispute. Otherwise it returns `None`.
-///
-/// This function is needed to hide the pattern matching and so make the caller code more readable.
-fn get_deposit_transaction<'a>(
-    transactions: &'a mut HashMap<TransactionId, Transfer>,
-    dispute: &Dispute,
-) -> Option<&'a mut DepositedTransaction> {
-    transactions
-        .get_mut(&dispute.disputed_tx_id)
-        .and_then(|tx| match tx {
-            Transfer {
-                typ: TransferType::Deposit(deposit),
-                ..
-            } if tx.client_id == dispute.client_id => Some(deposit),
-            _ => None,
-        })
-}
-
-/// Returns the referenced client, or if it does not exists, it creates one with the default values.
-fn get_or_create_client(
-    clients: &mut HashMap<ClientId, Client>,
-    client_id: ClientId,
-) -> &mut Client {
-    clients.entry(client_id).or_insert_with(|| Client::new())
-}
@@ -0,0 +1,279 @@
+use crate::client::{Client, ClientId};
+use crate::engine::RejectionReason;
+use crate::{Amount, CsvParsingError, HashMap, SECONDS_PER_DAY};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::Read;
+use std::time::Duration;
+
+/// The compliance limits of a client, `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// The largest amount of a single withdrawal
+    pub max_withdrawal: Option<Amount>,
+    /// The largest sum of the withdrawals of the client within `LimitsConfig::withdrawal_volume_window`
+    pub max_withdrawal_volume: Option<Amount>,
+    /// The largest total funds of the client, a deposit can not increase the total above it
+    pub max_balance: Option<Amount>,
+}
+
+/// The limits of every client, the `Default` value does not limit anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitsConfig {
+    /// The limits of the clients without an override
+    pub default: Limits,
+    /// The limits of specific clients by their Client IDs, they replace the `default` limits as a whole
+    pub overrides: std::collections::HashMap<u16, Limits>,
+    /// The length of the rolling window of `Limits::max_withdrawal_volume`, one day by default.
+    /// It is measured by the latest `timestamp` of the input, a withdrawal is in the window from the latest
+    /// timestamp before it (its own included), or from 0 if there was none yet.
+    /// Without timestamps nothing leaves the window, so the volume limit applies to the whole input.
+    pub withdrawal_volume_window: Duration,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            default: Limits::default(),
+            overrides: Default::default(),
+            withdrawal_volume_window: Duration::from_secs(SECONDS_PER_DAY),
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub(crate) fn is_unlimited(&self) -> bool {
+        self.default == Limits::default()
+            && self.overrides.values().all(|it| *it == Limits::default())
+    }
+
+    fn for_client(&self, client_id: ClientId) -> &Limits {
+        self.overrides.get(&client_id.0).unwrap_or(&self.default)
+    }
+}
+
+/// Reads the limit overrides from a csv with the columns `client, max_withdrawal, max_withdrawal_volume, max_balance`.
+/// An empty cell means unlimited.
+pub fn read_limit_overrides(
+    reader: impl Read,
+) -> Result<std::collections::HashMap<u16, Limits>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let expected_columns = [
+        "client",
+        "max_withdrawal",
+        "max_withdrawal_volume",
+        "max_balance",
+    ];
+    let headers = rdr.headers()?;
+    if headers.len() != expected_columns.len()
+        || !headers
+            .iter()
+            .zip(expected_columns)
+            .all(|(column, expected)| column.eq_ignore_ascii_case(expected))
+    {
+        return Err(Box::new(CsvParsingError::new(
+            "Expected columns in the limits file: client, max_withdrawal, max_withdrawal_volume, max_balance",
+        )));
+    }
+
+    let mut overrides = std::collections::HashMap::new();
+    for (record_index, record) in rdr.records().enumerate() {
+        let record = record?;
+        let line_index = record_index + 1;
+        let invalid = |column: &str| {
+            CsvParsingError::new(format!(
+                "Invalid {} in the limits file at line {}",
+                column, line_index
+            ))
+        };
+        let limit = |index: usize, column: &str| match &record[index] {
+            "" => Ok(None),
+            cell => Amount::parse_non_negative(cell)
+                .map(Some)
+                .ok_or_else(|| invalid(column)),
+        };
+        let client_id: u16 = record[0].parse().map_err(|_| invalid("Client ID"))?;
+        let limits = Limits {
+            max_withdrawal: limit(1, "max_withdrawal")?,
+            max_withdrawal_volume: limit(2, "max_withdrawal_volume")?,
+            max_balance: limit(3, "max_balance")?,
+        };
+        if overrides.insert(client_id, limits).is_some() {
+            return Err(Box::new(CsvParsingError::new(format!(
+                "Duplicated client {} in the limits file at line {}",
+                client_id, line_index
+            ))));
+        }
+    }
+    Ok(overrides)
+}
+
+/// Checks the limits of the clients, and keeps their recent withdrawals for the rolling volume limit.
+///
+/// The recent withdrawals are saved with the rest of the engine's state, so the window continues
+/// after a checkpoint or with a persistent store.
+pub(crate) struct LimitChecker {
+    config: LimitsConfig,
+    /// The recent withdrawals of the clients, only the clients with a volume limit are tracked.
+    recent_withdrawals: HashMap<ClientId, RecentWithdrawals>,
+}
+
+/// The withdrawals of a client within the window
+#[derive(Clone, Default)]
+struct RecentWithdrawals {
+    /// The time and the amount of the withdrawals, in their order
+    withdrawals: VecDeque<(u64, Amount)>,
+    /// The sum of the amounts of `withdrawals`, so it is not summed by every withdrawal
+    volume: Amount,
+}
+
+impl RecentWithdrawals {
+    fn push(&mut self, time: u64, amount: Amount) {
+        self.withdrawals.push_back((time, amount));
+        self.volume += amount;
+    }
+}
+
+/// The state of a `LimitChecker` before a withdrawal of a client, see `LimitChecker::snapshot`.
+pub(crate) struct LimitsSnapshot {
+    client_id: ClientId,
+    recent_withdrawals: Option<RecentWithdrawals>,
+}
+
+impl LimitChecker {
+    /// `recent_withdrawals` is the list saved by `saved_state`
+    pub fn new(
+        config: &LimitsConfig,
+        recent_withdrawals: Vec<(ClientId, u64, Amount)>,
+    ) -> LimitChecker {
+        let mut checker = LimitChecker {
+            config: config.clone(),
+            recent_withdrawals: HashMap::default(),
+        };
+        for (client_id, time, amount) in recent_withdrawals {
+            checker
+                .recent_withdrawals
+                .entry(client_id)
+                .or_default()
+                .push(time, amount);
+        }
+        checker
+    }
+
+    /// The recent withdrawals of every client, in their order
+    pub fn saved_state(&self) -> Vec<(ClientId, u64, Amount)> {
+        self.recent_withdrawals
+            .iter()
+            .flat_map(|(client_id, recent)| {
+                recent
+                    .withdrawals
+                    .iter()
+                    .map(|(time, amount)| (*client_id, *time, *amount))
+            })
+            .collect()
+    }
+
+    /// The withdrawals of the client within the window, if it has a volume limit
+    pub fn recent_withdrawals(&self, client_id: ClientId) -> Option<&VecDeque<(u64, Amount)>> {
+        self.recent_withdrawals
+            .get(&client_id)
+            .map(|recent| &recent.withdrawals)
+    }
+
+    /// The recent withdrawals of the client, see `restore`
+    pub fn snapshot(&self, client_id: ClientId) -> LimitsSnapshot {
+        LimitsSnapshot {
+            client_id,
+            recent_withdrawals: self.recent_withdrawals.get(&client_id).cloned(),
+        }
+    }
+
+    /// Undoes the withdrawals of the client since `snapshot`
+    pub fn restore(&mut self, snapshot: LimitsSnapshot) {
+        match snapshot.recent_withdrawals {
+            Some(recent) => self.recent_withdrawals.insert(snapshot.client_id, recent),
+            None => self.recent_withdrawals.remove(&snapshot.client_id),
+        };
+    }
+
+    pub fn check_deposit(
+        &self,
+        client_id: ClientId,
+        client: &Client,
+        amount: Amount,
+    ) -> Result<(), RejectionReason> {
+        match self.config.for_client(client_id).max_balance {
+            Some(max_balance)
+                if client
+                    .total
+                    .checked_add(amount)
+                    .is_none_or(|it| it > max_balance) =>
+            {
+                Err(RejectionReason::BalanceLimitExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The withdrawals outside of the window are forgotten first, `current_time` is the latest timestamp of the input.
+    pub fn check_withdrawal(
+        &mut self,
+        client_id: ClientId,
+        amount: Amount,
+        current_time: Option<u64>,
+    ) -> Result<(), RejectionReason> {
+        let limits = self.config.for_client(client_id);
+        if limits.max_withdrawal.is_some_and(|max| amount > max) {
+            return Err(RejectionReason::WithdrawalLimitExceeded);
+        }
+        let Some(max_volume) = limits.max_withdrawal_volume else {
+            return Ok(());
+        };
+        let window = self.config.withdrawal_volume_window.as_secs();
+        let mut volume = amount;
+        if let Some(recent) = self.recent_withdrawals.get_mut(&client_id) {
+            while let (Some((time, expired_amount)), Some(current_time)) =
+                (recent.withdrawals.front().copied(), current_time)
+            {
+                if current_time.saturating_sub(time) < window {
+                    break;
+                }
+                recent.withdrawals.pop_front();
+                recent.volume -= expired_amount;
+            }
+            volume += recent.volume;
+        }
+        if volume > max_volume {
+            return Err(RejectionReason::WithdrawalVolumeLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Has to be called after a withdrawal passed `check_withdrawal` and it was applied.
+    pub fn withdrawal_applied(
+        &mut self,
+        client_id: ClientId,
+        amount: Amount,
+        current_time: Option<u64>,
+    ) {
+        if self
+            .config
+            .for_client(client_id)
+            .max_withdrawal_volume
+            .is_some()
+        {
+            self.recent_withdrawals
+                .entry(client_id)
+                .or_default()
+                .push(current_time.unwrap_or(0), amount);
+        }
+    }
+
+    /// Moves the recent withdrawals of `other` into this checker, the two must not share any clients.
+    pub fn merge(&mut self, other: LimitChecker) {
+        self.recent_withdrawals.extend(other.recent_withdrawals);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::engine::Engine;
+use crate::statistics::ALL_TYPES;
+use crate::Amount;
+use std::io::Write;
+
+/// The content type of the Prometheus text exposition format
+pub(crate) const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Writes the counters and the gauges of the engine in the Prometheus text exposition format.
+///
+/// The counters (the lines per type and per rejection reason, the volumes) are maintained by the engine
+/// as the lines are processed, so they count only the lines of the current run (e.g. not the ones before
+/// a checkpoint). The gauges of the clients are calculated from the current client states.
+pub(crate) fn write_metrics(engine: &Engine, writer: &mut dyn Write) -> std::io::Result<()> {
+    let statistics = engine.statistics();
+
+    write_header(
+        writer,
+        "transactions_lines_total",
+        "counter",
+        "The processed input lines by type",
+    )?;
+    for typ in ALL_TYPES {
+        writeln!(
+            writer,
+            "transactions_lines_total{{type=\"{}\"}} {}",
+            typ,
+            statistics.lines_by_type.get(typ)
+        )?;
+    }
+
+    write_header(
+        writer,
+        "transactions_rejected_lines_total",
+        "counter",
+        "The rejected input lines by reason",
+    )?;
+    // ordered by the reasons, so the output is stable
+    let mut rejections: Vec<(String, usize)> = statistics
+        .rejections_by_reason
+        .iter()
+        .map(|(reason, count)| (reason.to_string(), *count))
+        .collect();
+    rejections.sort();
+    for (reason, count) in rejections {
+        writeln!(
+            writer,
+            "transactions_rejected_lines_total{{reason=\"{}\"}} {}",
+            reason, count
+        )?;
+    }
+
+    write_header(
+        writer,
+        "transactions_volume_total",
+        "counter",
+        "The sum of the amounts of the applied events by type",
+    )?;
+    for typ in ALL_TYPES {
+        writeln!(
+            writer,
+            "transactions_volume_total{{type=\"{}\"}} {}",
+            typ,
+            statistics.volumes.get(typ)
+        )?;
+    }
+
+    let clients = engine.clients();
+    let mut held_total = Amount::zero();
+    for client in clients.values() {
+        held_total += client.held;
+    }
+    let gauges = [
+        (
+            "transactions_clients",
+            "The number of the clients",
+            clients.len().to_string(),
+        ),
+        (
+            "transactions_locked_clients",
+            "The number of the locked clients",
+            clients
+                .values()
+                .filter(|client| client.locked)
+                .count()
+                .to_string(),
+        ),
+        (
+            "transactions_stored_deposits",
+            "The number of the deposits stored by the current run which are not evicted yet",
+            statistics.stored_deposits.to_string(),
+        ),
+        (
+            "transactions_held_total",
+            "The sum of the held funds of the clients",
+            held_total.to_string(),
+        ),
+    ];
+    for (name, help, value) in gauges {
+        write_header(writer, name, "gauge", help)?;
+        writeln!(writer, "{} {}", name, value)?;
+    }
+    Ok(())
+}
+
+fn write_header(writer: &mut dyn Write, name: &str, typ: &str, help: &str) -> std::io::Result<()> {
+    writeln!(writer, "# HELP {} {}.", name, help)?;
+    writeln!(writer, "# TYPE {} {}", name, typ)
+}
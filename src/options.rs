@@ -0,0 +1,107 @@
+use crate::{read_limit_overrides, Amount, Config, StoreConfig, SECONDS_PER_DAY, SECONDS_PER_HOUR};
+use std::fs::File;
+use std::io::BufReader;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The cache capacity of the `store-file` option without a `store-cache` option
+const DEFAULT_STORE_CACHE_CAPACITY: usize = 1_000_000;
+
+/// The options which are enabled without a value
+const FLAG_OPTIONS: [&str; 2] = ["unsorted", "verify"];
+
+impl Config {
+    /// Sets an option by its name, it is shared by the command line (`--max-withdrawal 50`)
+    /// and the configs of the scenarios (`max_withdrawal = 50`).
+    /// The name can have the leading dashes of the command line, and underscores instead of dashes,
+    /// it is used as it is in the error messages. Returns `Ok(false)` if there is no such option.
+    ///
+    /// The flags (see `is_flag_option`) are enabled without a value, or set by `true` or `false`.
+    /// The durations are in whole days or hours, and the limits file is read right away.
+    pub fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<bool, String> {
+        let missing = || format!("Missing value for {}", name);
+        let parse_amount = |value: Option<&str>| {
+            value
+                .and_then(Amount::parse_non_negative)
+                .ok_or_else(|| invalid_value(name))
+        };
+        match normalize_option_name(name).as_str() {
+            "store-file" => {
+                let path = value.ok_or_else(missing)?;
+                let cache_capacity = match &self.store {
+                    StoreConfig::OnDisk { cache_capacity, .. } => *cache_capacity,
+                    _ => DEFAULT_STORE_CACHE_CAPACITY,
+                };
+                self.store = StoreConfig::OnDisk {
+                    path: path.into(),
+                    cache_capacity,
+                };
+            }
+            "store-cache" => {
+                let capacity = parse_value(name, value)?;
+                match &mut self.store {
+                    StoreConfig::OnDisk { cache_capacity, .. } => *cache_capacity = capacity,
+                    _ => return Err(format!("{} requires a store file", name)),
+                }
+            }
+            #[cfg(feature = "sqlite")]
+            "sqlite" => {
+                let path = value.ok_or_else(missing)?;
+                self.store = StoreConfig::Sqlite { path: path.into() };
+            }
+            #[cfg(not(feature = "sqlite"))]
+            "sqlite" => return Err(format!("{} requires the sqlite feature", name)),
+            "dispute-window-days" => {
+                let days: u64 = parse_value(name, value)?;
+                let seconds = days
+                    .checked_mul(SECONDS_PER_DAY)
+                    .ok_or_else(|| invalid_value(name))?;
+                self.dispute_window = Some(Duration::from_secs(seconds));
+            }
+            "max-withdrawal" => self.limits.default.max_withdrawal = Some(parse_amount(value)?),
+            "max-withdrawal-volume" => {
+                self.limits.default.max_withdrawal_volume = Some(parse_amount(value)?)
+            }
+            "withdrawal-volume-window-hours" => {
+                let hours: u64 = parse_value(name, value)?;
+                let seconds = hours
+                    .checked_mul(SECONDS_PER_HOUR)
+                    .ok_or_else(|| invalid_value(name))?;
+                self.limits.withdrawal_volume_window = Duration::from_secs(seconds);
+            }
+            "max-balance" => self.limits.default.max_balance = Some(parse_amount(value)?),
+            "limits" => {
+                let path = value.ok_or_else(missing)?;
+                let file = File::open(path)
+                    .map_err(|err| format!("Could not open the limits file: {}", err))?;
+                self.limits.overrides =
+                    read_limit_overrides(BufReader::new(file)).map_err(|err| err.to_string())?;
+            }
+            "fraud-rule" => self.fraud_rules.push(parse_value(name, value)?),
+            "workers" => self.workers = parse_value(name, value)?,
+            "unsorted" => self.unsorted = value.map_or(Ok(true), |_| parse_value(name, value))?,
+            "verify" => self.verify = value.map_or(Ok(true), |_| parse_value(name, value))?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Whether the option of `set_option` is enabled without a value
+    pub fn is_flag_option(name: &str) -> bool {
+        FLAG_OPTIONS.contains(&normalize_option_name(name).as_str())
+    }
+}
+
+fn parse_value<T: FromStr>(name: &str, value: Option<&str>) -> Result<T, String> {
+    value
+        .and_then(|it| it.parse().ok())
+        .ok_or_else(|| invalid_value(name))
+}
+
+fn invalid_value(name: &str) -> String {
+    format!("Invalid or missing value for {}", name)
+}
+
+fn normalize_option_name(name: &str) -> String {
+    name.trim_start_matches('-').replace('_', "-")
+}
@@ -0,0 +1,259 @@
+use crate::checkpoint::InputPosition;
+use crate::engine::{Engine, Outcome};
+use crate::{read_input_lines, AsOf, Config, InputCsvLine, Rejection};
+use roaring::RoaringBitmap;
+use std::error::Error;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+
+/// The average number of lines sent to a worker at once, so the channel overhead is paid only once per batch.
+/// The input is read in rounds of `BATCH_SIZE` lines per worker, and every worker gets a batch in every round.
+const BATCH_SIZE: usize = 1024;
+
+/// The number of batches waiting for a worker before the reader blocks,
+/// so the memory usage does not grow if the workers are slower than the reader.
+/// It is also the number of rounds whose rejections can wait for the slowest worker.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// An input line dispatched to a worker.
+struct Job {
+    line_index: usize,
+    csv_line: InputCsvLine,
+    /// The latest timestamp in the whole input so far (not only in the lines of this worker)
+    current_time: Option<u64>,
+    /// The Transaction ID of the transfer was already used in the input (maybe by another worker's client)
+    duplicate: bool,
+}
+
+/// The error returned by the reader when a worker has stopped, the real error is returned by the worker.
+#[derive(Debug)]
+struct WorkerStopped;
+
+impl std::fmt::Display for WorkerStopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "A worker has stopped unexpectedly")
+    }
+}
+
+impl Error for WorkerStopped {}
+
+/// Same as `run_transactions_until`, but the clients are distributed between `config.workers` threads
+/// by their Client IDs, each with its own `Engine`.
+///
+/// The input is read and parsed on the calling thread, and the lines of a client are always processed by the
+/// same worker in their original order, so the client states are the same as with the sequential processing.
+/// The duplicated Transaction IDs and the current time are tracked here, since they are global
+/// for the whole input, not per client.
+///
+/// A dispute which references a transaction of another worker's client is rejected as an unknown transaction,
+/// while the sequential processing would report it as a client mismatch (or not a deposit). Both of them are ignored,
+/// so it affects only the reason in the rejections output.
+///
+/// The workers send back the rejected lines of every round, and they are written in the order of the lines
+/// as soon as every worker has finished the round, so they are never collected for the whole input.
+pub(crate) fn run_transactions_in_parallel(
+    reader: impl std::io::Read,
+    as_of: Option<AsOf>,
+    config: &Config,
+    with_ledger: bool,
+    rejections: Option<&mut dyn std::io::Write>,
+) -> Result<Engine, Box<dyn Error>> {
+    let worker_count = config.workers;
+    let engines = (0..worker_count)
+        .map(|shard_index| {
+            let shard_config = Config {
+                store: config.store.for_shard(shard_index, worker_count),
+                ..config.clone()
+            };
+            if with_ledger {
+                Engine::with_ledger(&shard_config)
+            } else {
+                Engine::new(&shard_config)
+            }
+        })
+        .collect::<std::io::Result<Vec<Engine>>>()?;
+    let collect_rejections = rejections.is_some();
+
+    let (read_result, worker_results) = std::thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(worker_count);
+        let mut rejection_receivers = Vec::with_capacity(worker_count);
+        let mut workers = Vec::with_capacity(worker_count);
+        for engine in engines {
+            let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+            senders.push(sender);
+            // the number of the waiting rounds is limited by the reader, so it does not have to be bounded
+            let rejection_sender = collect_rejections.then(|| {
+                let (rejection_sender, rejection_receiver) = channel();
+                rejection_receivers.push(rejection_receiver);
+                rejection_sender
+            });
+            workers.push(scope.spawn(move || run_worker(engine, receiver, rejection_sender)));
+        }
+
+        let rejections = rejections.map(|writer| RejectionWriter {
+            writer,
+            receivers: rejection_receivers,
+            waiting_rounds: 0,
+        });
+        let read_result = dispatch_lines(reader, as_of, &senders, rejections);
+        // closing the channels stops the workers
+        drop(senders);
+        let worker_results: Vec<_> = workers
+            .into_iter()
+            .map(|worker| worker.join().expect("A worker thread has panicked"))
+            .collect();
+        (read_result, worker_results)
+    });
+
+    let mut merged_engine: Option<Engine> = None;
+    for worker_result in worker_results {
+        let engine = worker_result?;
+        match merged_engine.as_mut() {
+            Some(merged_engine) => merged_engine.merge(engine),
+            None => merged_engine = Some(engine),
+        }
+    }
+    // the workers' errors are more informative, so they are returned first
+    read_result?;
+    Ok(merged_engine.expect("There is at least 2 workers"))
+}
+
+/// Writes the rejected lines sent back by the workers, round by round.
+struct RejectionWriter<'a> {
+    writer: &'a mut dyn std::io::Write,
+    /// A receiver for every worker, in the order of the workers
+    receivers: Vec<Receiver<Vec<Rejection>>>,
+    /// The number of the dispatched rounds whose rejections have not been written yet
+    waiting_rounds: usize,
+}
+
+impl RejectionWriter<'_> {
+    /// Waits until every worker has finished the oldest waiting round, and writes its rejections
+    /// in the order of their lines.
+    fn write_round(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut rejected_lines = Vec::new();
+        for receiver in self.receivers.iter() {
+            rejected_lines.extend(receiver.recv().map_err(|_| WorkerStopped)?);
+        }
+        self.waiting_rounds -= 1;
+        // the lines of every worker are already sorted, so the sort only merges these runs
+        rejected_lines.sort_by_key(|it| it.line_index);
+        for rejection in rejected_lines.iter() {
+            rejection.write_to(self.writer)?;
+        }
+        Ok(())
+    }
+
+    /// Called after every dispatched round, it writes the oldest rounds if too many of them are waiting.
+    fn round_dispatched(&mut self) -> Result<(), Box<dyn Error>> {
+        self.waiting_rounds += 1;
+        while self.waiting_rounds > CHANNEL_CAPACITY {
+            self.write_round()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the input and sends the lines in rounds to the worker of their client.
+/// The duplicated transfers are detected here, since the IDs are global, and the workers only classify them.
+fn dispatch_lines(
+    reader: impl std::io::Read,
+    as_of: Option<AsOf>,
+    senders: &[SyncSender<Vec<Job>>],
+    mut rejections: Option<RejectionWriter>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(rejections) = rejections.as_mut() {
+        Rejection::write_header_to(rejections.writer)?;
+    }
+    let round_size = BATCH_SIZE * senders.len();
+    let mut batches: Vec<Vec<Job>> = senders
+        .iter()
+        .map(|_| Vec::with_capacity(BATCH_SIZE))
+        .collect();
+    let mut round_lines = 0;
+    let mut transaction_ids = RoaringBitmap::new();
+    let mut current_time: Option<u64> = None;
+    read_input_lines(
+        reader,
+        as_of,
+        InputPosition::default(),
+        |line_index, csv_line, _| {
+            // the time is moved even by the rejected lines, the same way as in `Engine::apply`
+            if let Some(timestamp) = csv_line.timestamp() {
+                current_time = current_time.max(Some(timestamp));
+            }
+            let duplicate = match &csv_line {
+                InputCsvLine::Transfer(tx) => !transaction_ids.insert(tx.id.0),
+                InputCsvLine::Dispute(_) => false,
+            };
+            let shard_index = csv_line.client_id().0 as usize % senders.len();
+            batches[shard_index].push(Job {
+                line_index,
+                csv_line,
+                current_time,
+                duplicate,
+            });
+            round_lines += 1;
+            if round_lines >= round_size {
+                send_round(&mut batches, senders, rejections.as_mut())?;
+                round_lines = 0;
+            }
+            Ok(())
+        },
+    )?;
+    if round_lines > 0 {
+        send_round(&mut batches, senders, rejections.as_mut())?;
+    }
+    if let Some(rejections) = rejections.as_mut() {
+        while rejections.waiting_rounds > 0 {
+            rejections.write_round()?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends a batch to every worker (even an empty one), so every worker sends back the rejections of the round.
+fn send_round(
+    batches: &mut [Vec<Job>],
+    senders: &[SyncSender<Vec<Job>>],
+    rejections: Option<&mut RejectionWriter>,
+) -> Result<(), Box<dyn Error>> {
+    for (sender, batch) in senders.iter().zip(batches.iter_mut()) {
+        let batch = std::mem::replace(batch, Vec::with_capacity(BATCH_SIZE));
+        sender.send(batch).map_err(|_| WorkerStopped)?;
+    }
+    match rejections {
+        Some(rejections) => rejections.round_dispatched(),
+        None => Ok(()),
+    }
+}
+
+/// Applies the received lines to `engine` until the channel is closed.
+/// The rejected lines of every batch are sent back by `rejections`, if they are written.
+fn run_worker(
+    mut engine: Engine,
+    receiver: Receiver<Vec<Job>>,
+    rejections: Option<Sender<Vec<Rejection>>>,
+) -> std::io::Result<Engine> {
+    for batch in receiver {
+        let mut rejected_lines = Vec::new();
+        for job in batch {
+            let rejection = Rejection::prepare(job.line_index, &job.csv_line);
+            let outcome = engine.apply_dispatched(
+                job.line_index,
+                job.csv_line,
+                job.current_time,
+                job.duplicate,
+            )?;
+            if let Outcome::Rejected(reason) = outcome {
+                if rejections.is_some() {
+                    rejected_lines.push(rejection.with_reason(reason));
+                }
+            }
+        }
+        if let Some(rejections) = rejections.as_ref() {
+            // the reader might have stopped already because of an error
+            let _ = rejections.send(rejected_lines);
+        }
+    }
+    Ok(engine)
+}
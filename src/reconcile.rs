@@ -0,0 +1,112 @@
+use crate::client::{Client, ClientId};
+use crate::{Amount, CsvParsingError, HashMap};
+use std::error::Error;
+use std::io::{Read, Write};
+
+/// Reads the expected client states from a csv in the same format as the output.
+pub(crate) fn read_expected_balances(
+    reader: impl Read,
+) -> Result<HashMap<ClientId, Client>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let expected_columns = ["client", "available", "held", "total", "locked"];
+    let headers = rdr.headers()?;
+    if headers.len() != expected_columns.len()
+        || !headers
+            .iter()
+            .zip(expected_columns)
+            .all(|(column, expected)| column.eq_ignore_ascii_case(expected))
+    {
+        return Err(Box::new(CsvParsingError::new(
+            "Expected columns in the balances file: client, available, held, total, locked",
+        )));
+    }
+
+    let mut clients = HashMap::default();
+    for (record_index, record) in rdr.records().enumerate() {
+        let record = record?;
+        let line_index = record_index + 1;
+        let invalid = |column: &str| {
+            CsvParsingError::new(format!(
+                "Invalid {} in the balances file at line {}",
+                column, line_index
+            ))
+        };
+        let client_id = ClientId(record[0].parse().map_err(|_| invalid("Client ID"))?);
+        let client = Client {
+            available: Amount::parse_non_negative(&record[1])
+                .ok_or_else(|| invalid("available"))?,
+            held: Amount::parse_non_negative(&record[2]).ok_or_else(|| invalid("held"))?,
+            total: Amount::parse_non_negative(&record[3]).ok_or_else(|| invalid("total"))?,
+            locked: record[4].parse().map_err(|_| invalid("locked"))?,
+        };
+        if clients.insert(client_id, client).is_some() {
+            return Err(Box::new(CsvParsingError::new(format!(
+                "Duplicated client {} in the balances file at line {}",
+                client_id.0, line_index
+            ))));
+        }
+    }
+    Ok(clients)
+}
+
+/// Writes every difference between the `actual` and the `expected` client states into `writer` as a csv,
+/// ordered by the Client IDs. Returns the number of the differences.
+///
+/// A client missing from one of the sides is a single difference, its fields are not compared.
+pub(crate) fn write_differences(
+    actual: &HashMap<ClientId, Client>,
+    expected: &HashMap<ClientId, Client>,
+    writer: &mut impl Write,
+) -> std::io::Result<usize> {
+    let mut client_ids: Vec<ClientId> = actual.keys().chain(expected.keys()).copied().collect();
+    client_ids.sort();
+    client_ids.dedup();
+
+    // (client, field, expected, actual)
+    let mut differences: Vec<(ClientId, &str, String, String)> = Vec::new();
+    for client_id in client_ids {
+        match (expected.get(&client_id), actual.get(&client_id)) {
+            (Some(expected), Some(actual)) => {
+                let fields = [
+                    ("available", expected.available, actual.available),
+                    ("held", expected.held, actual.held),
+                    ("total", expected.total, actual.total),
+                ];
+                for (field, expected, actual) in fields {
+                    if expected != actual {
+                        differences.push((
+                            client_id,
+                            field,
+                            expected.to_string(),
+                            actual.to_string(),
+                        ));
+                    }
+                }
+                if expected.locked != actual.locked {
+                    differences.push((
+                        client_id,
+                        "locked",
+                        expected.locked.to_string(),
+                        actual.locked.to_string(),
+                    ));
+                }
+            }
+            (Some(_), None) => {
+                differences.push((client_id, "client", "present".into(), "missing".into()))
+            }
+            (None, Some(_)) => {
+                differences.push((client_id, "client", "missing".into(), "present".into()))
+            }
+            (None, None) => unreachable!("The Client IDs are collected from the two sides"),
+        }
+    }
+
+    writeln!(writer, "client, field, expected, actual")?;
+    for (client_id, field, expected, actual) in differences.iter() {
+        writeln!(writer, "{},{},{},{}", client_id.0, field, expected, actual)?;
+    }
+    Ok(differences.len())
+}
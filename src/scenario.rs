@@ -0,0 +1,185 @@
+use crate::{process_input_then_write_outputs, Amount, Config, Outputs};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+/// A test case in a directory, so it can be written without touching the Rust code:
+/// - `input.csv`: the input of the processing
+/// - `expected.csv`: the expected client states (or the expected error)
+/// - `rejections.csv` (optional): the expected rejections report
+/// - `config` (optional): the options of the processing, see `parse_config`
+struct Scenario {
+    input: String,
+    expected: String,
+    expected_rejections: Option<String>,
+    config: Config,
+}
+
+/// Runs every scenario in the subdirectories of `dir` (in the order of their names),
+/// and writes whether they passed into `report`, with the differences of the failed ones.
+/// Returns the number of the passed and the failed scenarios.
+pub(crate) fn run_scenarios(
+    dir: &Path,
+    report: &mut impl Write,
+) -> std::io::Result<(usize, usize)> {
+    let mut scenario_dirs: Vec<_> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|it| it.path()))
+        .collect::<std::io::Result<_>>()?;
+    scenario_dirs.retain(|it| it.join("input.csv").is_file());
+    scenario_dirs.sort();
+
+    let (mut passed, mut failed) = (0, 0);
+    for scenario_dir in scenario_dirs {
+        let name = scenario_dir
+            .file_name()
+            .map(|it| it.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let differences = match read_scenario(&scenario_dir) {
+            Ok(scenario) => run_scenario(&scenario),
+            Err(err) => vec![err],
+        };
+        if differences.is_empty() {
+            passed += 1;
+            writeln!(report, "PASS {}", name)?;
+        } else {
+            failed += 1;
+            writeln!(report, "FAIL {}", name)?;
+            for difference in differences {
+                writeln!(report, "  {}", difference)?;
+            }
+        }
+    }
+    writeln!(
+        report,
+        "{} scenarios, {} passed, {} failed",
+        passed + failed,
+        passed,
+        failed
+    )?;
+    Ok((passed, failed))
+}
+
+fn read_scenario(dir: &Path) -> Result<Scenario, String> {
+    let read = |file_name: &str| {
+        std::fs::read_to_string(dir.join(file_name))
+            .map_err(|err| format!("Could not read {}: {}", file_name, err))
+    };
+    let read_optional = |file_name: &str| match std::fs::read_to_string(dir.join(file_name)) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("Could not read {}: {}", file_name, err)),
+    };
+    Ok(Scenario {
+        input: read("input.csv")?,
+        expected: read("expected.csv")?,
+        expected_rejections: read_optional("rejections.csv")?,
+        config: match read_optional("config")? {
+            Some(config) => parse_config(&config)?,
+            None => Config::default(),
+        },
+    })
+}
+
+/// The config file has a `key = value` option in every line, empty lines and lines starting with `#` are ignored.
+/// The options are the same as the ones of the command line (see `Config::set_option`),
+/// with underscores instead of dashes, e.g. `max_withdrawal = 50` or `verify = true`.
+fn parse_config(content: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || format!("Invalid option in config at line {}: {}", index + 1, line);
+        let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+        if !config
+            .set_option(key.trim(), Some(value.trim()))
+            .map_err(|_| invalid())?
+        {
+            return Err(invalid());
+        }
+    }
+    Ok(config)
+}
+
+/// Returns the differences between the expected and the actual outputs, it is empty if the scenario passed.
+fn run_scenario(scenario: &Scenario) -> Vec<String> {
+    let mut output = Vec::new();
+    let mut rejections = Vec::new();
+    process_input_then_write_outputs(
+        scenario.input.as_bytes(),
+        &mut output,
+        Outputs {
+            rejections: scenario
+                .expected_rejections
+                .as_ref()
+                .map(|_| &mut rejections as &mut dyn Write),
+            ..Outputs::default()
+        },
+        &Config {
+            unsorted: true,
+            ..scenario.config.clone()
+        },
+    );
+    let mut differences = compare_csv(
+        "expected.csv",
+        &scenario.expected,
+        &String::from_utf8_lossy(&output),
+    );
+    if let Some(expected_rejections) = &scenario.expected_rejections {
+        differences.extend(compare_csv(
+            "rejections.csv",
+            expected_rejections,
+            &String::from_utf8_lossy(&rejections),
+        ));
+    }
+    differences
+}
+
+/// Compares the csvs as multisets of rows, so only the header has to be the first line in both of them.
+/// The cells are trimmed and the numbers are normalised, so e.g. `1.0` and `1` are equal.
+fn compare_csv(file_name: &str, expected: &str, actual: &str) -> Vec<String> {
+    let (expected_header, mut expected_rows) = normalize_csv(expected);
+    let (actual_header, actual_rows) = normalize_csv(actual);
+    if expected_header != actual_header {
+        return vec![format!(
+            "{}: expected the header `{}` but got `{}`",
+            file_name, expected_header, actual_header
+        )];
+    }
+    let mut differences = Vec::new();
+    for row in actual_rows {
+        match expected_rows.iter().position(|it| *it == row) {
+            Some(index) => {
+                expected_rows.swap_remove(index);
+            }
+            None => differences.push(format!("{}: unexpected row `{}`", file_name, row)),
+        }
+    }
+    for row in expected_rows {
+        differences.push(format!("{}: missing row `{}`", file_name, row));
+    }
+    differences
+}
+
+fn normalize_csv(csv: &str) -> (String, Vec<String>) {
+    let mut rows = csv
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            line.split(',')
+                .map(normalize_cell)
+                .collect::<Vec<_>>()
+                .join(",")
+        });
+    let header = rows.next().unwrap_or_default();
+    (header, rows.collect())
+}
+
+fn normalize_cell(cell: &str) -> String {
+    let cell = cell.trim();
+    let is_number = !cell.is_empty() && cell.chars().all(|c| c.is_ascii_digit() || c == '.');
+    match Amount::parse(cell) {
+        Some(amount) if is_number => amount.to_string(),
+        _ => cell.to_owned(),
+    }
+}
@@ -0,0 +1,384 @@
+use crate::client::{Client, ClientId};
+use crate::engine::{Engine, Outcome};
+use crate::metrics;
+use crate::{parse_transaction, Amount, Config, InputCsvLine};
+use csv::ByteRecord;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Read, Take, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+/// The largest accepted request body, so a single request can not exhaust the memory
+const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+/// The largest accepted request line and headers together, the body is limited separately by `MAX_BODY_SIZE`
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// The largest accepted number of headers
+const MAX_HEADER_COUNT: usize = 100;
+
+/// A connection which does not send its request in time is closed, so it can not block the other ones
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A long-running HTTP service on top of the `Engine`, so the transactions can be submitted in real time.
+///
+/// It listens only on the loopback interface, and handles the requests one by one, so the transactions
+/// are applied sequentially in the order they arrive:
+/// - `POST /transactions`: applies the csv (with or without the header) or JSON (an object or an array of objects,
+///   if the `Content-Type` is `application/json`) rows in the body, with the same columns as the input file.
+///   If any of the rows is invalid, none of them is applied.
+///   The response contains the outcome of every row.
+/// - `GET /clients`: the state of every client
+/// - `GET /clients/{id}`: the state of a single client
+/// - `GET /metrics`: the metrics of the engine in the Prometheus text format
+pub struct Server {
+    listener: TcpListener,
+    engine: Engine,
+    sorted: bool,
+    /// The number of the rows applied so far, it is used as the line number of the next row
+    line_count: usize,
+}
+
+/// A row of a `POST /transactions` JSON body. The values can be either JSON strings or numbers.
+#[derive(Deserialize)]
+struct JsonRow {
+    #[serde(rename = "type")]
+    typ: String,
+    client: Value,
+    tx: Value,
+    #[serde(default)]
+    amount: Value,
+    #[serde(default)]
+    timestamp: Value,
+}
+
+/// The same fields as in the output csv
+#[derive(Serialize)]
+struct ClientState {
+    client: u16,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+impl ClientState {
+    fn new(client_id: ClientId, client: &Client) -> ClientState {
+        ClientState {
+            client: client_id.0,
+            available: client.available,
+            held: client.held,
+            total: client.total,
+            locked: client.locked,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RowOutcome {
+    line: usize,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+/// The request line and the headers are larger than `MAX_HEADER_SIZE`, or there are more than `MAX_HEADER_COUNT` headers
+#[derive(Debug)]
+struct HeadersTooLarge;
+
+impl Display for HeadersTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The request headers are too large")
+    }
+}
+
+impl Error for HeadersTooLarge {}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: &impl Serialize) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(body).expect("The responses can always be serialized"),
+        }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Response {
+        Response::json(status, &serde_json::json!({ "error": message.to_string() }))
+    }
+}
+
+impl Server {
+    /// Binds the server to the given port on the loopback interface, 0 means an arbitrary free port.
+    /// The `Config::workers` option is ignored, the transactions are always applied sequentially.
+    pub fn bind(port: u16, config: &Config) -> std::io::Result<Server> {
+        let engine = Engine::new(config)?;
+        Ok(Server {
+            listener: TcpListener::bind((Ipv4Addr::LOCALHOST, port))?,
+            // with a persistent store, the line numbers continue from the previous run
+            line_count: engine.last_line(),
+            engine,
+            sorted: !config.unsorted,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Handles the incoming connections one by one until an IO error happens with the listener.
+    /// Only a single request is handled per connection, the connection is closed after the response.
+    ///
+    /// If the deposit store fails, or a violation is found with `Config::verify`, only the request fails
+    /// with `500 Internal Server Error`. The failing row is rolled back (see `Engine::apply`), so it can be retried,
+    /// but the rows of the request before it stay applied.
+    pub fn run(mut self) -> std::io::Result<()> {
+        for stream in self.listener.try_clone()?.incoming() {
+            let mut stream = stream?;
+            let request = read_request(&mut stream);
+            let is_invalid = request.is_err();
+            let response = match request {
+                Ok(request) => self.handle_request(request).unwrap_or_else(|err| {
+                    log::error!(error:% = err; "request failed");
+                    Response::error(500, err)
+                }),
+                Err(err) if err.is::<HeadersTooLarge>() => Response::error(431, err),
+                Err(err) => Response::error(400, err),
+            };
+            // the errors of a single connection (e.g. the client disconnects) do not stop the server
+            let _ = write_response(&mut stream, &response);
+            if is_invalid {
+                discard_unread_input(&mut stream);
+            }
+        }
+        Ok(())
+    }
+
+    /// It can return an `Err` only when the deposit store fails, or a violation is found with `Config::verify`.
+    fn handle_request(&mut self, request: Request) -> std::io::Result<Response> {
+        let path = request.path.trim_end_matches('/');
+        Ok(match (request.method.as_str(), path) {
+            ("POST", "/transactions") => {
+                let rows = if request
+                    .content_type
+                    .is_some_and(|it| it.starts_with("application/json"))
+                {
+                    parse_json_rows(&request.body, self.line_count)
+                } else {
+                    parse_csv_rows(&request.body, self.line_count)
+                };
+                match rows {
+                    Ok(rows) => Response::json(200, &self.apply_rows(rows)?),
+                    Err(err) => Response::error(400, err),
+                }
+            }
+            ("GET", "/clients") => {
+                let mut client_ids: Vec<&ClientId> = self.engine.clients().keys().collect();
+                if self.sorted {
+                    client_ids.sort();
+                }
+                let states: Vec<ClientState> = client_ids
+                    .into_iter()
+                    .map(|id| ClientState::new(*id, &self.engine.clients()[id]))
+                    .collect();
+                Response::json(200, &states)
+            }
+            ("GET", _) if path.starts_with("/clients/") => {
+                let client = path["/clients/".len()..]
+                    .parse()
+                    .ok()
+                    .map(ClientId)
+                    .and_then(|id| Some((id, self.engine.clients().get(&id)?)));
+                match client {
+                    Some((id, client)) => Response::json(200, &ClientState::new(id, client)),
+                    None => Response::error(404, "Unknown client"),
+                }
+            }
+            ("GET", "/metrics") => {
+                let mut body = Vec::new();
+                metrics::write_metrics(&self.engine, &mut body)?;
+                Response {
+                    status: 200,
+                    content_type: metrics::CONTENT_TYPE,
+                    body: String::from_utf8(body).expect("The metrics are always valid UTF-8"),
+                }
+            }
+            (_, "/transactions" | "/clients" | "/metrics") => {
+                Response::error(405, "Method not allowed")
+            }
+            _ => Response::error(404, "Not found"),
+        })
+    }
+
+    fn apply_rows(&mut self, rows: Vec<InputCsvLine>) -> std::io::Result<Vec<RowOutcome>> {
+        let mut outcomes = Vec::with_capacity(rows.len());
+        for row in rows {
+            // a failed row is rolled back by the engine, so its line number is used by the next row
+            let line = self.line_count + 1;
+            let outcome = self.engine.apply(line, row)?;
+            self.line_count = line;
+            outcomes.push(match outcome {
+                Outcome::Applied => RowOutcome {
+                    line,
+                    outcome: "applied",
+                    reason: None,
+                },
+                Outcome::Rejected(reason) => RowOutcome {
+                    line,
+                    outcome: "rejected",
+                    reason: Some(reason.to_string()),
+                },
+            });
+        }
+        Ok(outcomes)
+    }
+}
+
+/// Parses the csv rows of the body, the header is optional.
+/// The line numbers in the error messages continue from `line_count`.
+fn parse_csv_rows(body: &[u8], line_count: usize) -> Result<Vec<InputCsvLine>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(body);
+    let mut rows = Vec::new();
+    let mut record = ByteRecord::new();
+    while rdr.read_byte_record(&mut record)? {
+        let is_header = rows.is_empty()
+            && record
+                .get(0)
+                .is_some_and(|it| it.trim_ascii().eq_ignore_ascii_case(b"type"));
+        if !is_header {
+            rows.push(parse_transaction(line_count + rows.len() + 1, &record)?);
+        }
+    }
+    Ok(rows)
+}
+
+/// Parses a JSON object or array of objects with the same fields as the columns of the input csv.
+fn parse_json_rows(body: &[u8], line_count: usize) -> Result<Vec<InputCsvLine>, Box<dyn Error>> {
+    let json_rows: Vec<JsonRow> = match serde_json::from_slice::<Value>(body)? {
+        array @ Value::Array(_) => serde_json::from_value(array)?,
+        object => vec![serde_json::from_value(object)?],
+    };
+    let to_column = |value: Value| match value {
+        Value::Null => String::new(),
+        Value::String(string) => string,
+        other => other.to_string(),
+    };
+    json_rows
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let record = ByteRecord::from(vec![
+                row.typ,
+                to_column(row.client),
+                to_column(row.tx),
+                to_column(row.amount),
+                to_column(row.timestamp),
+            ]);
+            parse_transaction(line_count + index + 1, &record).map_err(Box::<dyn Error>::from)
+        })
+        .collect()
+}
+
+fn read_request(stream: &mut TcpStream) -> Result<Request, Box<dyn Error>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream).take(MAX_HEADER_SIZE as u64);
+    let request_line = read_header_line(&mut reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err("Invalid request line".into());
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut content_length = 0;
+    let mut content_type = None;
+    let mut header_count = 0;
+    loop {
+        let header = read_header_line(&mut reader)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        header_count += 1;
+        if header_count > MAX_HEADER_COUNT {
+            return Err(HeadersTooLarge.into());
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            } else if name.eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.trim().to_ascii_lowercase());
+            }
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err("The body is too large".into());
+    }
+    let mut body = vec![0; content_length];
+    reader.set_limit(content_length as u64);
+    reader.read_exact(&mut body)?;
+    Ok(Request {
+        method,
+        path,
+        content_type,
+        body,
+    })
+}
+
+/// Reads a line of the request line or the headers, they share the `MAX_HEADER_SIZE` limit of the reader.
+fn read_header_line(
+    reader: &mut Take<BufReader<&mut TcpStream>>,
+) -> Result<String, Box<dyn Error>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.ends_with('\n') && reader.limit() == 0 {
+        return Err(HeadersTooLarge.into());
+    }
+    Ok(line)
+}
+
+/// Closing a connection with unread input resets it, so the client might not receive the response.
+/// The rest of an invalid request is read (up to `MAX_BODY_SIZE` or the `READ_TIMEOUT`) before closing it.
+fn discard_unread_input(stream: &mut TcpStream) {
+    let _ = stream.shutdown(Shutdown::Write);
+    let _ = std::io::copy(&mut stream.take(MAX_BODY_SIZE as u64), &mut std::io::sink());
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> std::io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,256 @@
+use crate::client::{Client, ClientId};
+use crate::engine::{Engine, RejectionReason};
+use crate::ledger::LedgerEventType;
+use crate::{Amount, HashMap};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The format of the summary written at the end of the processing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SummaryFormat {
+    /// One `name: value` line per counter
+    Text,
+    Json,
+}
+
+impl FromStr for SummaryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(SummaryFormat::Text),
+            "json" => Ok(SummaryFormat::Json),
+            _ => Err(format!("Invalid summary format: {}", s)),
+        }
+    }
+}
+
+/// A value for every type of input line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub(crate) struct PerType<T> {
+    pub deposit: T,
+    pub withdrawal: T,
+    pub dispute: T,
+    pub resolve: T,
+    pub chargeback: T,
+}
+
+impl<T> PerType<T> {
+    pub fn get(&self, typ: LedgerEventType) -> &T {
+        match typ {
+            LedgerEventType::Deposit => &self.deposit,
+            LedgerEventType::Withdrawal => &self.withdrawal,
+            LedgerEventType::Dispute => &self.dispute,
+            LedgerEventType::Resolve => &self.resolve,
+            LedgerEventType::Chargeback => &self.chargeback,
+        }
+    }
+
+    fn get_mut(&mut self, typ: LedgerEventType) -> &mut T {
+        match typ {
+            LedgerEventType::Deposit => &mut self.deposit,
+            LedgerEventType::Withdrawal => &mut self.withdrawal,
+            LedgerEventType::Dispute => &mut self.dispute,
+            LedgerEventType::Resolve => &mut self.resolve,
+            LedgerEventType::Chargeback => &mut self.chargeback,
+        }
+    }
+}
+
+/// The counters of the lines processed by an `Engine`.
+///
+/// Only the lines processed by the current run are counted, e.g. the lines before a checkpoint are not.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Statistics {
+    pub lines_by_type: PerType<usize>,
+    pub rejections_by_reason: HashMap<RejectionReason, usize>,
+    /// The sum of the amounts of the applied events
+    pub volumes: PerType<Amount>,
+    /// The number of the deposits in the deposit store right now
+    pub stored_deposits: usize,
+    /// The maximum of `stored_deposits` during the processing
+    pub peak_stored_deposits: usize,
+}
+
+/// The counters of a `Statistics` changed by the events of a line, see `Statistics::snapshot`.
+/// The lines themselves are counted only after they were processed successfully.
+#[derive(Clone, Copy)]
+pub(crate) struct StatisticsSnapshot {
+    volumes: PerType<Amount>,
+    stored_deposits: usize,
+    peak_stored_deposits: usize,
+}
+
+impl Statistics {
+    pub fn lines(&self) -> usize {
+        let counts = &self.lines_by_type;
+        counts.deposit + counts.withdrawal + counts.dispute + counts.resolve + counts.chargeback
+    }
+
+    pub fn rejected_lines(&self) -> usize {
+        self.rejections_by_reason.values().sum()
+    }
+
+    pub(crate) fn line_processed(
+        &mut self,
+        typ: LedgerEventType,
+        result: Result<(), RejectionReason>,
+    ) {
+        *self.lines_by_type.get_mut(typ) += 1;
+        if let Err(reason) = result {
+            *self.rejections_by_reason.entry(reason).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn event_applied(&mut self, typ: LedgerEventType, amount: Amount) {
+        *self.volumes.get_mut(typ) += amount;
+    }
+
+    pub(crate) fn deposit_stored(&mut self) {
+        self.stored_deposits += 1;
+        self.peak_stored_deposits = self.peak_stored_deposits.max(self.stored_deposits);
+    }
+
+    pub(crate) fn deposit_evicted(&mut self) {
+        self.stored_deposits = self.stored_deposits.saturating_sub(1);
+    }
+
+    pub(crate) fn snapshot(&self) -> StatisticsSnapshot {
+        StatisticsSnapshot {
+            volumes: self.volumes,
+            stored_deposits: self.stored_deposits,
+            peak_stored_deposits: self.peak_stored_deposits,
+        }
+    }
+
+    /// Undoes the events since `snapshot`
+    pub(crate) fn restore(&mut self, snapshot: StatisticsSnapshot) {
+        self.volumes = snapshot.volumes;
+        self.stored_deposits = snapshot.stored_deposits;
+        self.peak_stored_deposits = snapshot.peak_stored_deposits;
+    }
+
+    /// Adds the counters of an engine with other clients, see `Engine::merge`.
+    /// The peaks of the engines might be at different times, so their sum is only an upper bound.
+    pub(crate) fn merge(&mut self, other: Statistics) {
+        for typ in ALL_TYPES {
+            *self.lines_by_type.get_mut(typ) += *other.lines_by_type.get(typ);
+            *self.volumes.get_mut(typ) += *other.volumes.get(typ);
+        }
+        for (reason, count) in other.rejections_by_reason {
+            *self.rejections_by_reason.entry(reason).or_default() += count;
+        }
+        self.stored_deposits += other.stored_deposits;
+        self.peak_stored_deposits += other.peak_stored_deposits;
+    }
+}
+
+pub(crate) const ALL_TYPES: [LedgerEventType; 5] = [
+    LedgerEventType::Deposit,
+    LedgerEventType::Withdrawal,
+    LedgerEventType::Dispute,
+    LedgerEventType::Resolve,
+    LedgerEventType::Chargeback,
+];
+
+/// The summary of a finished processing, as it is written into the summary output
+#[derive(Serialize)]
+pub(crate) struct Summary {
+    lines: usize,
+    lines_by_type: PerType<usize>,
+    applied_lines: usize,
+    rejected_lines: usize,
+    /// Ordered by the reasons, so the output is stable
+    rejections_by_reason: BTreeMap<String, usize>,
+    replayed_transaction_ids: usize,
+    conflicting_transaction_ids: usize,
+    volumes: PerType<Amount>,
+    locked_clients: usize,
+    peak_stored_deposits: usize,
+    elapsed_seconds: f64,
+    lines_per_second: f64,
+}
+
+impl Summary {
+    pub fn new(engine: &Engine, elapsed: Duration) -> Summary {
+        let statistics = engine.statistics();
+        let lines = statistics.lines();
+        let rejected_lines = statistics.rejected_lines();
+        let elapsed_seconds = elapsed.as_secs_f64();
+        Summary {
+            lines,
+            lines_by_type: statistics.lines_by_type,
+            applied_lines: lines - rejected_lines,
+            rejected_lines,
+            rejections_by_reason: statistics
+                .rejections_by_reason
+                .iter()
+                .map(|(reason, count)| (reason.to_string(), *count))
+                .collect(),
+            replayed_transaction_ids: engine.duplicates().replays,
+            conflicting_transaction_ids: engine.duplicates().conflicts,
+            volumes: statistics.volumes,
+            locked_clients: count_locked(engine.clients()),
+            peak_stored_deposits: statistics.peak_stored_deposits,
+            elapsed_seconds,
+            lines_per_second: if elapsed_seconds > 0.0 {
+                lines as f64 / elapsed_seconds
+            } else {
+                0.0
+            },
+        }
+    }
+
+    pub fn write_to(
+        &self,
+        format: SummaryFormat,
+        writer: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        match format {
+            SummaryFormat::Text => self.write_text_to(writer),
+            SummaryFormat::Json => {
+                serde_json::to_writer_pretty(&mut *writer, self)?;
+                writeln!(writer)
+            }
+        }
+    }
+
+    fn write_text_to(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        writeln!(writer, "lines: {}", self.lines)?;
+        for typ in ALL_TYPES {
+            writeln!(writer, "{} lines: {}", typ, self.lines_by_type.get(typ))?;
+        }
+        writeln!(writer, "applied lines: {}", self.applied_lines)?;
+        writeln!(writer, "rejected lines: {}", self.rejected_lines)?;
+        for (reason, count) in self.rejections_by_reason.iter() {
+            writeln!(writer, "rejected as {}: {}", reason, count)?;
+        }
+        writeln!(
+            writer,
+            "replayed transaction ids: {}",
+            self.replayed_transaction_ids
+        )?;
+        writeln!(
+            writer,
+            "conflicting transaction ids: {}",
+            self.conflicting_transaction_ids
+        )?;
+        for typ in ALL_TYPES {
+            writeln!(writer, "{} volume: {}", typ, self.volumes.get(typ))?;
+        }
+        writeln!(writer, "locked clients: {}", self.locked_clients)?;
+        writeln!(
+            writer,
+            "peak stored deposits: {}",
+            self.peak_stored_deposits
+        )?;
+        writeln!(writer, "elapsed seconds: {:.3}", self.elapsed_seconds)?;
+        writeln!(writer, "lines per second: {:.0}", self.lines_per_second)
+    }
+}
+
+fn count_locked(clients: &HashMap<ClientId, Client>) -> usize {
+    clients.values().filter(|client| client.locked).count()
+}
@@ -0,0 +1,208 @@
+mod disk;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use crate::client::{Client, ClientId};
+use crate::fraud::{Alert, FraudDetector};
+use crate::transaction::{StoredDeposit, TransactionId};
+use crate::{Amount, HashMap};
+use roaring::RoaringBitmap;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+pub use disk::DiskStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// Stores the deposits, so later disputes can reference them.
+///
+/// Withdrawals can not be disputed, only their client and amount are stored (see `StoredDeposit::withdrawal`),
+/// to classify the reuses of their IDs. The duplicated Transaction IDs are detected without the store,
+/// so `insert` is called only once for every ID.
+///
+/// The implementation can be chosen by `StoreConfig`.
+/// Every method can fail only in case of an IO error.
+///
+/// It has to be `Send`, since the stores are moved into the worker threads in parallel mode.
+pub trait DepositStore: Send {
+    fn insert(&mut self, id: TransactionId, deposit: StoredDeposit) -> std::io::Result<()>;
+
+    fn get_mut(&mut self, id: TransactionId) -> std::io::Result<Option<&mut StoredDeposit>>;
+
+    fn remove(&mut self, id: TransactionId) -> std::io::Result<()>;
+
+    /// Calls `f` with every stored deposit (and withdrawal) in an arbitrary order, e.g. to save them into a checkpoint.
+    fn for_each_deposit(
+        &mut self,
+        _f: &mut dyn FnMut(TransactionId, StoredDeposit) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "The deposits of this store can not be listed",
+        ))
+    }
+
+    /// Called after every input line (even the rejected ones) with the state of the line's client,
+    /// so a persistent store can save every change of the line atomically.
+    /// The changes of the deposits are made by the other methods before it.
+    fn line_processed(&mut self, _line: ProcessedLine) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Undoes the changes of the deposits since the last `line_processed`, when the processing of the line failed
+    /// (even if the store itself failed). `previous` are the deposits changed by the line with their values before
+    /// the changes (`None` if they did not exist), in the order of the changes.
+    fn rollback_line(
+        &mut self,
+        previous: &[(TransactionId, Option<StoredDeposit>)],
+    ) -> std::io::Result<()> {
+        for (id, deposit) in previous.iter().rev() {
+            match deposit {
+                Some(deposit) => self.insert(*id, *deposit)?,
+                None => self.remove(*id)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The state saved by a previous run into a persistent store, it is called only once, on creation of the `Engine`.
+    fn load_state(&mut self) -> std::io::Result<Option<SavedState>> {
+        Ok(None)
+    }
+}
+
+/// The changes of an input line besides the deposits, see `DepositStore::line_processed`.
+// only the persistent stores read it
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+pub struct ProcessedLine<'a> {
+    pub line_index: usize,
+    pub client_id: ClientId,
+    /// It is missing if the line was rejected before its client was created
+    pub client: Option<&'a Client>,
+    /// The ID of the deposit or withdrawal, if it was not a duplicate
+    pub new_transaction_id: Option<TransactionId>,
+    /// The amount of the withdrawal, if it was not a duplicate
+    pub new_withdrawal_amount: Option<Amount>,
+    /// The time and the amount of the withdrawals of the client within the volume limit's window,
+    /// if the line was a withdrawal of a client with a volume limit
+    pub recent_withdrawals: Option<&'a VecDeque<(u64, Amount)>>,
+    /// The fraud rules, to save their state of the client
+    pub fraud: Option<&'a FraudDetector>,
+    /// The alerts triggered by the line
+    pub new_alerts: &'a [Alert],
+    pub current_time: Option<u64>,
+}
+
+/// The state of the `Engine` (besides the deposits themselves) saved by a persistent store.
+#[derive(Default)]
+pub struct SavedState {
+    pub clients: Vec<(ClientId, Client)>,
+    /// The IDs of every deposit and withdrawal
+    pub transaction_ids: RoaringBitmap,
+    pub evicted_deposits: RoaringBitmap,
+    /// The not evicted deposits with timestamp, in the order of their arrival
+    pub deposits_by_time: Vec<(u64, TransactionId)>,
+    /// The withdrawals within the volume limit's window, in their order, see `LimitsConfig`
+    pub recent_withdrawals: Vec<(ClientId, u64, Amount)>,
+    /// The non-empty states of the fraud rules by the client and the index of the rule, see `FraudRule::client_state`
+    pub fraud_rule_states: Vec<(ClientId, usize, Vec<u8>)>,
+    pub alerts: Vec<Alert>,
+    pub current_time: Option<u64>,
+    /// The index of the last processed input line
+    pub last_line: usize,
+}
+
+/// Determines which `DepositStore` implementation is used.
+#[derive(Debug, Clone, Default)]
+pub enum StoreConfig {
+    /// Every deposit is kept in memory, it is the fastest, but the memory usage grows with the input.
+    #[default]
+    InMemory,
+    /// The deposits are stored in the file at `path`, and only the `cache_capacity`
+    /// most recently used ones are kept in memory.
+    OnDisk {
+        path: PathBuf,
+        cache_capacity: usize,
+    },
+    /// The clients and the deposits are stored in the SQLite database at `path`, and every input line is applied
+    /// in a database transaction. If the database already exists, the processing continues from its state.
+    #[cfg(feature = "sqlite")]
+    Sqlite { path: PathBuf },
+}
+
+impl StoreConfig {
+    pub(crate) fn create_store(&self) -> std::io::Result<Box<dyn DepositStore>> {
+        Ok(match self {
+            StoreConfig::InMemory => Box::<InMemoryStore>::default(),
+            StoreConfig::OnDisk {
+                path,
+                cache_capacity,
+            } => Box::new(DiskStore::create(path, *cache_capacity)?),
+            #[cfg(feature = "sqlite")]
+            StoreConfig::Sqlite { path } => Box::new(SqliteStore::open(path)?),
+        })
+    }
+
+    /// A persistent store keeps the state between the runs, so it can not be shared by the workers in parallel mode.
+    pub(crate) fn is_persistent(&self) -> bool {
+        match self {
+            StoreConfig::InMemory | StoreConfig::OnDisk { .. } => false,
+            #[cfg(feature = "sqlite")]
+            StoreConfig::Sqlite { .. } => true,
+        }
+    }
+
+    /// The configuration of the store of the `shard_index`th worker in parallel mode.
+    /// Every worker gets its own file (with the index as suffix), and the cache capacity is divided between them.
+    pub(crate) fn for_shard(&self, shard_index: usize, shard_count: usize) -> StoreConfig {
+        match self {
+            StoreConfig::InMemory => StoreConfig::InMemory,
+            StoreConfig::OnDisk {
+                path,
+                cache_capacity,
+            } => {
+                let mut filename = path.clone().into_os_string();
+                filename.push(format!(".{}", shard_index));
+                StoreConfig::OnDisk {
+                    path: filename.into(),
+                    cache_capacity: (cache_capacity / shard_count).max(1),
+                }
+            }
+            #[cfg(feature = "sqlite")]
+            StoreConfig::Sqlite { .. } => {
+                unreachable!("A persistent store is never used in parallel mode")
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryStore {
+    deposits: HashMap<TransactionId, StoredDeposit>,
+}
+
+impl DepositStore for InMemoryStore {
+    fn insert(&mut self, id: TransactionId, deposit: StoredDeposit) -> std::io::Result<()> {
+        self.deposits.insert(id, deposit);
+        Ok(())
+    }
+
+    fn get_mut(&mut self, id: TransactionId) -> std::io::Result<Option<&mut StoredDeposit>> {
+        Ok(self.deposits.get_mut(&id))
+    }
+
+    fn remove(&mut self, id: TransactionId) -> std::io::Result<()> {
+        self.deposits.remove(&id);
+        Ok(())
+    }
+
+    fn for_each_deposit(
+        &mut self,
+        f: &mut dyn FnMut(TransactionId, StoredDeposit) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        for (id, deposit) in self.deposits.iter() {
+            f(*id, *deposit)?;
+        }
+        Ok(())
+    }
+}
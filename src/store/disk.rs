@@ -0,0 +1,234 @@
+use crate::store::DepositStore;
+use crate::transaction::{StoredDeposit, TransactionId};
+use crate::HashMap;
+use roaring::RoaringBitmap;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The size of a single deposit in the file, a marker byte followed by the `StoredDeposit`
+const RECORD_SIZE: u64 = 1 + StoredDeposit::SIZE as u64;
+
+const RECORD_EMPTY: u8 = 0;
+const RECORD_PRESENT: u8 = 1;
+
+/// A `DepositStore` which keeps only a limited number of deposits in memory.
+///
+/// The file is indexed directly by the Transaction ID, every deposit occupies `RECORD_SIZE` bytes
+/// at the offset `id * RECORD_SIZE`, so there is no need for an index in memory.
+/// The file is sparse, only the written blocks take up disk space, but its apparent size
+/// can be as large as the biggest Transaction ID times `RECORD_SIZE` (max 80 GB).
+/// Only a compressed bitmap of the stored IDs is kept, so `for_each_deposit` does not have to scan the whole file.
+///
+/// The `cache_capacity` most recently used deposits are kept in memory, and the modified ones are
+/// written back to the file only when they are evicted from the cache.
+///
+/// The file is used only as a temporary storage, so it is truncated on creation and removed on drop.
+pub struct DiskStore {
+    file: File,
+    path: PathBuf,
+    cache: HashMap<TransactionId, CacheEntry>,
+    /// The IDs of the stored deposits, in the cache or in the file
+    stored_ids: RoaringBitmap,
+    /// The cached Transaction IDs ordered by their last usage, the first is the least recently used one
+    usage_order: BTreeMap<u64, TransactionId>,
+    usage_counter: u64,
+    cache_capacity: usize,
+}
+
+struct CacheEntry {
+    deposit: StoredDeposit,
+    last_used: u64,
+    dirty: bool,
+}
+
+impl DiskStore {
+    pub fn create(path: &Path, cache_capacity: usize) -> std::io::Result<DiskStore> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(DiskStore {
+            file,
+            path: path.to_owned(),
+            cache: HashMap::default(),
+            stored_ids: RoaringBitmap::new(),
+            usage_order: BTreeMap::new(),
+            usage_counter: 0,
+            cache_capacity: cache_capacity.max(1),
+        })
+    }
+
+    /// Makes sure the referenced deposit is in the cache, if it exists.
+    /// Returns `false` if it does not exist.
+    fn load(&mut self, id: TransactionId) -> std::io::Result<bool> {
+        if let Some(entry) = self.cache.get_mut(&id) {
+            self.usage_order.remove(&entry.last_used);
+            self.usage_counter += 1;
+            entry.last_used = self.usage_counter;
+            self.usage_order.insert(self.usage_counter, id);
+            return Ok(true);
+        }
+        match self.read_record(id)? {
+            Some(deposit) => {
+                self.cache_insert(id, deposit, false)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn cache_insert(
+        &mut self,
+        id: TransactionId,
+        deposit: StoredDeposit,
+        dirty: bool,
+    ) -> std::io::Result<()> {
+        if self.cache.len() >= self.cache_capacity {
+            self.evict_least_recently_used()?;
+        }
+        self.usage_counter += 1;
+        self.usage_order.insert(self.usage_counter, id);
+        self.cache.insert(
+            id,
+            CacheEntry {
+                deposit,
+                last_used: self.usage_counter,
+                dirty,
+            },
+        );
+        Ok(())
+    }
+
+    /// The deposit is removed from the cache only after it was written, so a failed write does not lose it.
+    fn evict_least_recently_used(&mut self) -> std::io::Result<()> {
+        let Some((&last_used, &id)) = self.usage_order.first_key_value() else {
+            return Ok(());
+        };
+        if let Some(entry) = self.cache.get(&id) {
+            if entry.dirty {
+                self.write_record(id, Some(entry.deposit))?;
+            }
+        }
+        self.usage_order.remove(&last_used);
+        self.cache.remove(&id);
+        Ok(())
+    }
+
+    fn read_record(&mut self, id: TransactionId) -> std::io::Result<Option<StoredDeposit>> {
+        let mut buf = [0u8; RECORD_SIZE as usize];
+        self.file.seek(SeekFrom::Start(id.0 as u64 * RECORD_SIZE))?;
+        match self.file.read_exact(&mut buf) {
+            Ok(()) => {}
+            // the file has not been extended until this record yet
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        parse_record(id, &buf)
+    }
+
+    fn write_record(
+        &mut self,
+        id: TransactionId,
+        deposit: Option<StoredDeposit>,
+    ) -> std::io::Result<()> {
+        let mut buf = [0u8; RECORD_SIZE as usize];
+        if let Some(deposit) = deposit {
+            buf[0] = RECORD_PRESENT;
+            buf[1..].copy_from_slice(&deposit.to_bytes());
+        }
+        self.file.seek(SeekFrom::Start(id.0 as u64 * RECORD_SIZE))?;
+        self.file.write_all(&buf)
+    }
+}
+
+impl DepositStore for DiskStore {
+    /// The deposit might be in the cache already, when it is restored by `rollback_line`
+    fn insert(&mut self, id: TransactionId, deposit: StoredDeposit) -> std::io::Result<()> {
+        if let Some(entry) = self.cache.get_mut(&id) {
+            entry.deposit = deposit;
+            entry.dirty = true;
+            return Ok(());
+        }
+        self.cache_insert(id, deposit, true)?;
+        self.stored_ids.insert(id.0);
+        Ok(())
+    }
+
+    fn get_mut(&mut self, id: TransactionId) -> std::io::Result<Option<&mut StoredDeposit>> {
+        if !self.load(id)? {
+            return Ok(None);
+        }
+        // the caller might modify it, so it has to be written back on eviction
+        Ok(self.cache.get_mut(&id).map(|entry| {
+            entry.dirty = true;
+            &mut entry.deposit
+        }))
+    }
+
+    fn remove(&mut self, id: TransactionId) -> std::io::Result<()> {
+        self.stored_ids.remove(id.0);
+        if let Some(entry) = self.cache.remove(&id) {
+            self.usage_order.remove(&entry.last_used);
+        }
+        self.write_record(id, None)
+    }
+
+    /// Only the stored deposits are visited, the cached ones from the cache, and the others are read from the file
+    /// in the order of their IDs, so the cost does not depend on the size of the sparse file.
+    fn for_each_deposit(
+        &mut self,
+        f: &mut dyn FnMut(TransactionId, StoredDeposit) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut position = 0u64;
+        let mut buf = [0u8; RECORD_SIZE as usize];
+        for id in self.stored_ids.iter() {
+            let id = TransactionId(id);
+            if let Some(entry) = self.cache.get(&id) {
+                f(id, entry.deposit)?;
+                continue;
+            }
+            // the IDs are ascending, so the reader only moves forward and its buffer is reused
+            let offset = id.0 as u64 * RECORD_SIZE;
+            reader.seek_relative((offset - position) as i64)?;
+            reader.read_exact(&mut buf)?;
+            position = offset + RECORD_SIZE;
+            match parse_record(id, &buf)? {
+                Some(deposit) => f(id, deposit)?,
+                None => return Err(corrupted(id)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_record(
+    id: TransactionId,
+    buf: &[u8; RECORD_SIZE as usize],
+) -> std::io::Result<Option<StoredDeposit>> {
+    match buf[0] {
+        RECORD_EMPTY => Ok(None),
+        RECORD_PRESENT => Ok(Some(StoredDeposit::from_bytes(
+            buf[1..].try_into().unwrap(),
+        ))),
+        _ => Err(corrupted(id)),
+    }
+}
+
+fn corrupted(id: TransactionId) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Corrupted deposit store at Transaction ID {}", id.0),
+    )
+}
+
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,464 @@
+use crate::client::{Client, ClientId};
+use crate::fraud::Alert;
+use crate::store::{DepositStore, ProcessedLine, SavedState};
+use crate::transaction::{StoredDeposit, TransactionId};
+use crate::Amount;
+use roaring::RoaringBitmap;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+/// The amounts are stored as integers (in ten-thousandths), so they can be summed up exactly.
+/// The `balances` view shows them as decimals for the reporting queries.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS clients (
+        client    INTEGER PRIMARY KEY,
+        available INTEGER NOT NULL,
+        held      INTEGER NOT NULL,
+        total     INTEGER NOT NULL,
+        locked    INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS deposits (
+        tx        INTEGER PRIMARY KEY,
+        client    INTEGER NOT NULL,
+        amount    INTEGER NOT NULL,
+        timestamp INTEGER,
+        disputed  INTEGER NOT NULL,
+        rejected  INTEGER NOT NULL,
+        evicted   INTEGER NOT NULL,
+        seq       INTEGER NOT NULL
+    );
+    -- the IDs of every deposit and withdrawal, to detect the duplicates,
+    -- with the client and the amount of the withdrawals, to classify them
+    CREATE TABLE IF NOT EXISTS transactions (
+        tx                INTEGER PRIMARY KEY,
+        withdrawal_client INTEGER,
+        withdrawal_amount INTEGER
+    );
+    -- the withdrawals within the window of the volume limit, of the clients with such a limit
+    CREATE TABLE IF NOT EXISTS recent_withdrawals (
+        seq       INTEGER PRIMARY KEY,
+        client    INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        amount    INTEGER NOT NULL
+    );
+    -- the states of the fraud rules (by their index in the configuration) for the clients, see `FraudRule::client_state`
+    CREATE TABLE IF NOT EXISTS fraud_rule_states (
+        client INTEGER NOT NULL,
+        rule   INTEGER NOT NULL,
+        state  BLOB NOT NULL,
+        PRIMARY KEY (client, rule)
+    );
+    CREATE TABLE IF NOT EXISTS alerts (
+        line   INTEGER NOT NULL,
+        client INTEGER NOT NULL,
+        rule   TEXT NOT NULL,
+        action TEXT NOT NULL,
+        reason TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS progress (
+        id               INTEGER PRIMARY KEY CHECK (id = 0),
+        line             INTEGER NOT NULL,
+        latest_timestamp INTEGER
+    );
+    CREATE VIEW IF NOT EXISTS balances AS
+        SELECT client,
+               available / 10000.0 AS available,
+               held / 10000.0 AS held,
+               total / 10000.0 AS total,
+               locked
+        FROM clients;
+";
+
+/// A `DepositStore` which keeps the deposits, the clients and the progress of the processing
+/// in a SQLite database, so they can be queried with SQL and the processing can continue after a crash.
+///
+/// Every change of an input line is written in a single database transaction, which is committed
+/// in `line_processed`, so a crash never leaves a half-applied line (e.g. a disputed deposit
+/// without the held funds of its client) in the database. If any of the writes fails,
+/// the transaction is rolled back, so the next line can not commit the rest of the failed one.
+///
+/// The clients are still kept in the memory of the `Engine` as well (there are at most 65536 of them),
+/// the database is updated with their state after every line.
+pub struct SqliteStore {
+    connection: Connection,
+    /// The deposit returned by the last `get_mut` with its original disputed flag,
+    /// it is written back only if it was changed.
+    borrowed_deposit: Option<(TransactionId, bool, StoredDeposit)>,
+    /// The order of the next inserted deposit
+    next_seq: i64,
+    in_transaction: bool,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, or creates it if it does not exist.
+    pub fn open(path: &Path) -> std::io::Result<SqliteStore> {
+        let connection = Connection::open(path).map_err(std::io::Error::other)?;
+        // the WAL journal still makes every transaction atomic, but it needs much less syncing
+        connection
+            .execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .map_err(std::io::Error::other)?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(std::io::Error::other)?;
+        let next_seq = connection
+            .query_row(
+                "SELECT COALESCE(MAX(seq), 0) + 1 FROM deposits",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(std::io::Error::other)?;
+        Ok(SqliteStore {
+            connection,
+            borrowed_deposit: None,
+            next_seq,
+            in_transaction: false,
+        })
+    }
+
+    /// Starts the transaction of the current line, if it has not been started yet
+    fn begin(&mut self) -> rusqlite::Result<()> {
+        if !self.in_transaction {
+            self.connection.execute_batch("BEGIN")?;
+            self.in_transaction = true;
+        }
+        Ok(())
+    }
+
+    /// Rolls back the transaction of the current line, and forgets the borrowed deposit
+    fn rollback(&mut self) {
+        self.borrowed_deposit = None;
+        if self.in_transaction {
+            self.in_transaction = false;
+            // SQLite might have rolled back the transaction already, e.g. when the disk is full
+            let _ = self.connection.execute_batch("ROLLBACK");
+        }
+    }
+
+    /// Rolls back the current line if the `result` of a database operation is an error
+    fn rollback_on_error<T>(&mut self, result: rusqlite::Result<T>) -> std::io::Result<T> {
+        result.map_err(|err| {
+            self.rollback();
+            std::io::Error::other(err)
+        })
+    }
+
+    /// Writes back the deposit borrowed by `get_mut`, if it was changed
+    fn write_back_borrowed_deposit(&mut self) -> rusqlite::Result<()> {
+        if let Some((id, disputed, deposit)) = self.borrowed_deposit.take() {
+            if deposit.disputed() != disputed {
+                self.begin()?;
+                self.connection
+                    .prepare_cached("UPDATE deposits SET disputed = ?1 WHERE tx = ?2")?
+                    .execute(params![deposit.disputed(), id.0])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn save_line(&mut self, line: ProcessedLine) -> rusqlite::Result<()> {
+        self.write_back_borrowed_deposit()?;
+        self.begin()?;
+        if let Some(client) = line.client {
+            self.connection
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO clients (client, available, held, total, locked)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                )?
+                .execute(params![
+                    line.client_id.0,
+                    to_sql(client.available)?,
+                    to_sql(client.held)?,
+                    to_sql(client.total)?,
+                    client.locked
+                ])?;
+        }
+        if let Some(id) = line.new_transaction_id {
+            let withdrawal_client = line.new_withdrawal_amount.map(|_| line.client_id.0);
+            self.connection
+                .prepare_cached(
+                    "INSERT INTO transactions (tx, withdrawal_client, withdrawal_amount)
+                     VALUES (?1, ?2, ?3)",
+                )?
+                .execute(params![
+                    id.0,
+                    withdrawal_client,
+                    line.new_withdrawal_amount.map(to_sql).transpose()?
+                ])?;
+        }
+        if let Some(recent_withdrawals) = line.recent_withdrawals {
+            // the window of the client is replaced as a whole, the withdrawals could leave it as well
+            self.connection
+                .prepare_cached("DELETE FROM recent_withdrawals WHERE client = ?1")?
+                .execute([line.client_id.0])?;
+            for (time, amount) in recent_withdrawals {
+                self.connection
+                    .prepare_cached(
+                        "INSERT INTO recent_withdrawals (client, timestamp, amount) VALUES (?1, ?2, ?3)",
+                    )?
+                    .execute(params![line.client_id.0, *time as i64, to_sql(*amount)?])?;
+            }
+        }
+        if let Some(fraud) = line.fraud {
+            for (rule_index, state) in fraud.client_states(line.client_id) {
+                if state.is_empty() {
+                    self.connection
+                        .prepare_cached(
+                            "DELETE FROM fraud_rule_states WHERE client = ?1 AND rule = ?2",
+                        )?
+                        .execute(params![line.client_id.0, rule_index as i64])?;
+                } else {
+                    self.connection
+                        .prepare_cached(
+                            "INSERT OR REPLACE INTO fraud_rule_states (client, rule, state) VALUES (?1, ?2, ?3)",
+                        )?
+                        .execute(params![line.client_id.0, rule_index as i64, state])?;
+                }
+            }
+        }
+        for alert in line.new_alerts {
+            self.connection
+                .prepare_cached(
+                    "INSERT INTO alerts (line, client, rule, action, reason) VALUES (?1, ?2, ?3, ?4, ?5)",
+                )?
+                .execute(params![
+                    alert.line as i64,
+                    alert.client_id,
+                    alert.rule,
+                    alert.action.to_string(),
+                    alert.reason
+                ])?;
+        }
+        self.connection
+            .prepare_cached(
+                "INSERT OR REPLACE INTO progress (id, line, latest_timestamp) VALUES (0, ?1, ?2)",
+            )?
+            .execute(params![
+                line.line_index as i64,
+                line.current_time.map(|it| it as i64)
+            ])?;
+        self.connection.execute_batch("COMMIT")?;
+        self.in_transaction = false;
+        Ok(())
+    }
+
+    fn read_state(&mut self) -> rusqlite::Result<Option<SavedState>> {
+        let progress: Option<(i64, Option<i64>)> = self
+            .connection
+            .query_row("SELECT line, latest_timestamp FROM progress", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+        let Some((last_line, current_time)) = progress else {
+            return Ok(None);
+        };
+        let clients = self
+            .connection
+            .prepare("SELECT client, available, held, total, locked FROM clients")?
+            .query_map([], |row| {
+                Ok((
+                    ClientId(row.get(0)?),
+                    Client {
+                        available: from_sql(row.get(1)?),
+                        held: from_sql(row.get(2)?),
+                        total: from_sql(row.get(3)?),
+                        locked: row.get(4)?,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let transaction_ids = self.read_ids("SELECT tx FROM transactions")?;
+        let evicted_deposits = self.read_ids("SELECT tx FROM deposits WHERE evicted")?;
+        let deposits_by_time = self
+            .connection
+            .prepare(
+                "SELECT timestamp, tx FROM deposits
+                 WHERE timestamp IS NOT NULL AND NOT evicted
+                 ORDER BY seq",
+            )?
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)? as u64, TransactionId(row.get(1)?)))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let recent_withdrawals = self
+            .connection
+            .prepare("SELECT client, timestamp, amount FROM recent_withdrawals ORDER BY seq")?
+            .query_map([], |row| {
+                Ok((
+                    ClientId(row.get(0)?),
+                    row.get::<_, i64>(1)? as u64,
+                    from_sql(row.get(2)?),
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let fraud_rule_states = self
+            .connection
+            .prepare("SELECT client, rule, state FROM fraud_rule_states")?
+            .query_map([], |row| {
+                Ok((
+                    ClientId(row.get(0)?),
+                    row.get::<_, i64>(1)? as usize,
+                    row.get(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let alerts = self
+            .connection
+            .prepare("SELECT line, client, rule, action, reason FROM alerts ORDER BY rowid")?
+            .query_map([], |row| {
+                Ok(Alert {
+                    line: row.get::<_, i64>(0)? as usize,
+                    client_id: row.get(1)?,
+                    rule: row.get(2)?,
+                    action: row.get::<_, String>(3)?.parse().map_err(|err: String| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            3,
+                            rusqlite::types::Type::Text,
+                            err.into(),
+                        )
+                    })?,
+                    reason: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Some(SavedState {
+            clients,
+            transaction_ids,
+            evicted_deposits,
+            deposits_by_time,
+            recent_withdrawals,
+            fraud_rule_states,
+            alerts,
+            current_time: current_time.map(|it| it as u64),
+            last_line: last_line as usize,
+        }))
+    }
+
+    fn read_withdrawal(&self, id: TransactionId) -> rusqlite::Result<Option<StoredDeposit>> {
+        self.connection
+            .prepare_cached(
+                "SELECT withdrawal_client, withdrawal_amount FROM transactions
+                 WHERE tx = ?1 AND withdrawal_client IS NOT NULL",
+            )?
+            .query_row([id.0], |row| {
+                Ok(StoredDeposit::withdrawal(
+                    ClientId(row.get(0)?),
+                    from_sql(row.get(1)?),
+                ))
+            })
+            .optional()
+    }
+
+    fn read_ids(&self, query: &str) -> rusqlite::Result<RoaringBitmap> {
+        let mut ids = RoaringBitmap::new();
+        let mut statement = self.connection.prepare(query)?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            ids.insert(row.get(0)?);
+        }
+        Ok(ids)
+    }
+}
+
+impl DepositStore for SqliteStore {
+    /// The withdrawals are saved into the `transactions` table by `line_processed`
+    fn insert(&mut self, id: TransactionId, deposit: StoredDeposit) -> std::io::Result<()> {
+        if deposit.is_withdrawal() {
+            return Ok(());
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let result = self.begin().and_then(|_| {
+            self.connection
+                .prepare_cached(
+                    "INSERT INTO deposits (tx, client, amount, timestamp, disputed, rejected, evicted, seq)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, FALSE, ?7)",
+                )?
+                .execute(params![
+                    id.0,
+                    { deposit.client_id }.0,
+                    to_sql(deposit.amount)?,
+                    deposit.timestamp().map(|it| it as i64),
+                    deposit.disputed(),
+                    deposit.rejected(),
+                    seq
+                ])
+        });
+        self.rollback_on_error(result)?;
+        Ok(())
+    }
+
+    fn get_mut(&mut self, id: TransactionId) -> std::io::Result<Option<&mut StoredDeposit>> {
+        let result = self.write_back_borrowed_deposit().and_then(|_| {
+            self.connection
+                .prepare_cached(
+                    "SELECT client, amount, timestamp, disputed, rejected FROM deposits
+                     WHERE tx = ?1 AND NOT evicted",
+                )?
+                .query_row([id.0], |row| {
+                    let mut deposit = StoredDeposit::new(
+                        ClientId(row.get(0)?),
+                        from_sql(row.get(1)?),
+                        row.get::<_, Option<i64>>(2)?.map(|it| it as u64),
+                    );
+                    deposit.set_disputed(row.get(3)?);
+                    deposit.set_rejected(row.get(4)?);
+                    Ok(deposit)
+                })
+                .optional()?
+                .map_or_else(|| self.read_withdrawal(id), |deposit| Ok(Some(deposit)))
+        });
+        let deposit = self.rollback_on_error(result)?;
+        Ok(deposit.map(|deposit| {
+            let (_, _, deposit) = self
+                .borrowed_deposit
+                .insert((id, deposit.disputed(), deposit));
+            deposit
+        }))
+    }
+
+    /// The deposit is only marked as evicted, so disputes referencing it can be rejected with the right reason
+    /// after a restart as well.
+    fn remove(&mut self, id: TransactionId) -> std::io::Result<()> {
+        let result = self
+            .write_back_borrowed_deposit()
+            .and_then(|_| self.begin())
+            .and_then(|_| {
+                self.connection
+                    .prepare_cached("UPDATE deposits SET evicted = TRUE WHERE tx = ?1")?
+                    .execute([id.0])
+            });
+        self.rollback_on_error(result)?;
+        Ok(())
+    }
+
+    fn line_processed(&mut self, line: ProcessedLine) -> std::io::Result<()> {
+        let result = self.save_line(line);
+        self.rollback_on_error(result)
+    }
+
+    /// Every change of the line is in its database transaction, so `previous` is not needed
+    fn rollback_line(
+        &mut self,
+        _previous: &[(TransactionId, Option<StoredDeposit>)],
+    ) -> std::io::Result<()> {
+        self.rollback();
+        Ok(())
+    }
+
+    fn load_state(&mut self) -> std::io::Result<Option<SavedState>> {
+        self.read_state().map_err(std::io::Error::other)
+    }
+}
+
+/// SQLite has only signed integers, so the amounts above `i64::MAX` (in ten-thousandths) can not be stored
+fn to_sql(amount: Amount) -> rusqlite::Result<i64> {
+    i64::try_from(amount.to_raw()).map_err(|_| {
+        rusqlite::Error::ToSqlConversionFailure(
+            format!("The amount {} is too large for the SQLite store", amount).into(),
+        )
+    })
+}
+
+fn from_sql(value: i64) -> Amount {
+    Amount::from_raw(value as u64)
+}
@@ -0,0 +1,168 @@
+use crate::engine::{Engine, Outcome};
+use crate::{
+    parse_transaction, validate_header_record, write_client_states_to, Config, Transaction,
+};
+use csv::ByteRecord;
+use csv_core::ReadRecordResult;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::error::Error;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// The error type of the async API, it can be sent between tasks
+pub type AsyncError = Box<dyn Error + Send + Sync>;
+
+/// The `Engine` with an async API, so the transactions can be applied as they arrive from another async source
+/// (e.g. a network connection or a message queue) without blocking a runtime thread on the reading.
+///
+/// The transactions are applied one by one in the order they are polled from the input stream,
+/// and the outcome of each one is yielded right after it is applied, so the input does not have to be
+/// read into memory as a whole.
+///
+/// The engine itself is still synchronous, so with `StoreConfig::OnDisk` the store IO blocks the polling task.
+pub struct AsyncEngine {
+    engine: Engine,
+    sorted: bool,
+    /// The number of the transactions applied so far, it is used as the line number of the next one
+    line_count: usize,
+}
+
+/// The state of `AsyncEngine::process_csv` between two lines
+struct CsvState<'a, R> {
+    engine: &'a mut AsyncEngine,
+    reader: BufReader<R>,
+    /// Splits the bytes of `reader` into records as they arrive, so a record can span multiple reads
+    /// (and multiple lines, in a quoted field)
+    csv_reader: csv_core::Reader,
+    /// The fields of the current record one after another, and their ends, see `csv_core::Reader::read_record`.
+    /// They are reused by every record, so there is no allocation per line.
+    fields: Vec<u8>,
+    field_ends: Vec<usize>,
+    record: ByteRecord,
+    header_validated: bool,
+    /// Set after the first error, so nothing is read after it
+    stopped: bool,
+}
+
+impl AsyncEngine {
+    /// The `Config::workers` option is ignored, the transactions are always applied sequentially.
+    pub fn new(config: &Config) -> std::io::Result<AsyncEngine> {
+        let engine = Engine::new(config)?;
+        Ok(AsyncEngine {
+            // with a persistent store, the line numbers continue from the previous run
+            line_count: engine.last_line(),
+            engine,
+            sorted: !config.unsorted,
+        })
+    }
+
+    /// Applies the transactions of `transactions` and yields their outcomes.
+    /// An `Err` is yielded only if the deposit store fails, the failed transaction leaves no trace in the state.
+    pub fn process<'a>(
+        &'a mut self,
+        transactions: impl Stream<Item = Transaction> + 'a,
+    ) -> impl Stream<Item = std::io::Result<Outcome>> + 'a {
+        transactions.map(move |transaction| self.apply(transaction))
+    }
+
+    /// Reads the lines of an input csv (with the same format as the input file, including the header)
+    /// from `reader`, applies them and yields their outcomes.
+    ///
+    /// The stream ends after the first error (an invalid line or an IO error), the same way as the processing
+    /// of an input file stops. The returned stream is not `Unpin`, it has to be pinned (e.g. by `std::pin::pin!`)
+    /// before calling `StreamExt::next` on it.
+    pub fn process_csv<'a>(
+        &'a mut self,
+        reader: impl AsyncRead + Unpin + 'a,
+    ) -> impl Stream<Item = Result<Outcome, AsyncError>> + 'a {
+        let state = CsvState {
+            engine: self,
+            reader: BufReader::new(reader),
+            csv_reader: csv_core::Reader::new(),
+            fields: vec![0; 1024],
+            field_ends: vec![0; 8],
+            record: ByteRecord::new(),
+            header_validated: false,
+            stopped: false,
+        };
+        stream::unfold(state, |mut state| async move {
+            if state.stopped {
+                return None;
+            }
+            let result = state.next_outcome().await.transpose()?;
+            state.stopped = result.is_err();
+            Some((result, state))
+        })
+    }
+
+    /// Writes the state of the clients in the same format as the output of the processing
+    pub fn write_client_states_to(
+        &self,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), Box<dyn Error>> {
+        write_client_states_to(self.engine.clients(), None, self.sorted, writer)
+    }
+
+    /// A failed transaction is rolled back by the engine, so its line number is used by the next one
+    fn apply(&mut self, transaction: Transaction) -> std::io::Result<Outcome> {
+        let line = self.line_count + 1;
+        let outcome = self.engine.apply(line, transaction.0)?;
+        self.line_count = line;
+        Ok(outcome)
+    }
+}
+
+impl<R: AsyncRead + Unpin> CsvState<'_, R> {
+    /// Reads and applies the next line, skipping the header and the blank lines.
+    /// Returns `None` at the end of the input.
+    async fn next_outcome(&mut self) -> Result<Option<Outcome>, AsyncError> {
+        while self.read_record().await? {
+            if self.record.len() == 1 && self.record[0].trim_ascii().is_empty() {
+                continue;
+            }
+            if !self.header_validated {
+                // the error of `validate_header_record` can not be sent between threads, only its message is kept
+                validate_header_record(&self.record).map_err(|err| err.to_string())?;
+                self.header_validated = true;
+                continue;
+            }
+            let csv_line = parse_transaction(self.engine.line_count + 1, &self.record)?;
+            return Ok(Some(self.engine.apply(Transaction(csv_line))?));
+        }
+        Ok(None)
+    }
+
+    /// Reads the next record into `record`, returns `false` at the end of the input.
+    /// The empty lines are skipped by `csv_core`.
+    async fn read_record(&mut self) -> std::io::Result<bool> {
+        let (mut fields_len, mut field_ends_len) = (0, 0);
+        loop {
+            // an empty input means the end of the input for `csv_core` as well
+            let input = self.reader.fill_buf().await?;
+            let (result, read, written, ends_written) = self.csv_reader.read_record(
+                input,
+                &mut self.fields[fields_len..],
+                &mut self.field_ends[field_ends_len..],
+            );
+            self.reader.consume(read);
+            fields_len += written;
+            field_ends_len += ends_written;
+            match result {
+                ReadRecordResult::InputEmpty => {}
+                ReadRecordResult::OutputFull => self.fields.resize(self.fields.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => {
+                    self.field_ends.resize(self.field_ends.len() * 2, 0)
+                }
+                ReadRecordResult::Record => {
+                    self.record.clear();
+                    let mut start = 0;
+                    for &end in &self.field_ends[..field_ends_len] {
+                        self.record.push_field(&self.fields[start..end]);
+                        start = end;
+                    }
+                    return Ok(true);
+                }
+                ReadRecordResult::End => return Ok(false),
+            }
+        }
+    }
+}
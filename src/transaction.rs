@@ -11,17 +11,19 @@ pub struct TransactionId(pub u32);
 /// deposit    ,1      ,3  , 100.0
 /// withdrawal ,1      ,4  , 50.0
 /// ```
+/// An optional `timestamp` column (in seconds since the Unix epoch) can follow the `amount`.
 
 #[derive(Debug)]
 pub struct Transfer {
     pub id: TransactionId,
     pub client_id: ClientId,
     pub typ: TransferType,
+    pub timestamp: Option<u64>,
 }
 
 #[derive(Debug)]
 pub enum TransferType {
-    Deposit(DepositedTransaction),
+    Deposit { amount: Amount },
     Withdrawal { amount: Amount },
 }
 
@@ -38,6 +40,7 @@ pub struct Dispute {
     pub disputed_tx_id: TransactionId,
     pub client_id: ClientId,
     pub state: DisputeState,
+    pub timestamp: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -47,8 +50,109 @@ pub enum DisputeState {
     Chargeback,
 }
 
-#[derive(Debug)]
-pub struct DepositedTransaction {
+const FLAG_DISPUTED: u8 = 0b01;
+const FLAG_HAS_TIMESTAMP: u8 = 0b10;
+const FLAG_REJECTED: u8 = 0b100;
+const FLAG_WITHDRAWAL: u8 = 0b1000;
+
+/// A deposit as it is stored for the later disputes.
+/// The withdrawals are stored in the same layout (only their client and amount), to classify the later reuses
+/// of their Transaction IDs.
+///
+/// Millions of them might be stored, so it uses a packed layout, 19 bytes instead of the 32 bytes
+/// the same fields would take with the default alignment.
+/// Being packed, references to its fields can not be taken, they have to be copied.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct StoredDeposit {
     pub amount: Amount,
-    pub disputed: bool,
+    timestamp: u64,
+    pub client_id: ClientId,
+    flags: u8,
+}
+
+impl StoredDeposit {
+    /// The size of `to_bytes`
+    pub const SIZE: usize = 19;
+
+    pub fn new(client_id: ClientId, amount: Amount, timestamp: Option<u64>) -> StoredDeposit {
+        StoredDeposit {
+            amount,
+            timestamp: timestamp.unwrap_or(0),
+            client_id,
+            flags: if timestamp.is_some() {
+                FLAG_HAS_TIMESTAMP
+            } else {
+                0
+            },
+        }
+    }
+
+    /// A withdrawal can not be disputed, so its timestamp is not stored
+    pub fn withdrawal(client_id: ClientId, amount: Amount) -> StoredDeposit {
+        StoredDeposit {
+            amount,
+            timestamp: 0,
+            client_id,
+            flags: FLAG_WITHDRAWAL,
+        }
+    }
+
+    pub fn is_withdrawal(&self) -> bool {
+        self.flags & FLAG_WITHDRAWAL != 0
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        if self.flags & FLAG_HAS_TIMESTAMP != 0 {
+            Some(self.timestamp)
+        } else {
+            None
+        }
+    }
+
+    pub fn disputed(&self) -> bool {
+        self.flags & FLAG_DISPUTED != 0
+    }
+
+    pub fn set_disputed(&mut self, disputed: bool) {
+        if disputed {
+            self.flags |= FLAG_DISPUTED;
+        } else {
+            self.flags &= !FLAG_DISPUTED;
+        }
+    }
+
+    /// A rejected deposit is only stored to classify the later reuses of its Transaction ID,
+    /// it can not be disputed.
+    pub fn rejected(&self) -> bool {
+        self.flags & FLAG_REJECTED != 0
+    }
+
+    pub fn set_rejected(&mut self, rejected: bool) {
+        if rejected {
+            self.flags |= FLAG_REJECTED;
+        } else {
+            self.flags &= !FLAG_REJECTED;
+        }
+    }
+
+    /// Serializes the deposit into a platform independent binary format
+    pub fn to_bytes(self) -> [u8; StoredDeposit::SIZE] {
+        let mut buf = [0u8; StoredDeposit::SIZE];
+        buf[0..8].copy_from_slice(&self.amount.to_raw().to_le_bytes());
+        buf[8..16].copy_from_slice(&{ self.timestamp }.to_le_bytes());
+        buf[16..18].copy_from_slice(&self.client_id.0.to_le_bytes());
+        buf[18] = self.flags;
+        buf
+    }
+
+    /// The inverse of `to_bytes`
+    pub fn from_bytes(buf: &[u8; StoredDeposit::SIZE]) -> StoredDeposit {
+        StoredDeposit {
+            amount: Amount::from_raw(u64::from_le_bytes(buf[0..8].try_into().unwrap())),
+            timestamp: u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+            client_id: ClientId(u16::from_le_bytes([buf[16], buf[17]])),
+            flags: buf[18],
+        }
+    }
 }
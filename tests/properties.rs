@@ -0,0 +1,353 @@
+//! Property-based tests: random transaction streams are processed by the engine, and the state of the changed
+//! client is checked after every applied line (via the changes output), and compared to a simple reference model.
+
+use proptest::prelude::*;
+use std::collections::HashMap;
+use std::io::BufWriter;
+use transactions_lib::amount::Amount;
+use transactions_lib::{
+    process_input_then_write_outputs, ChangesOutput, Config, Outputs, StoreConfig,
+};
+
+/// The Client IDs and Transaction IDs are drawn from small ranges,
+/// so the disputes often reference existing transactions and the IDs are often duplicated.
+const CLIENT_COUNT: u16 = 4;
+const TRANSACTION_ID_COUNT: u32 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    typ: Type,
+    client: u16,
+    tx: u32,
+    /// In ten-thousandths, only used by the deposits and withdrawals
+    amount: i64,
+}
+
+impl Line {
+    fn to_csv(&self) -> String {
+        let typ = match self.typ {
+            Type::Deposit => "deposit",
+            Type::Withdrawal => "withdrawal",
+            Type::Dispute => "dispute",
+            Type::Resolve => "resolve",
+            Type::Chargeback => "chargeback",
+        };
+        match self.typ {
+            Type::Deposit | Type::Withdrawal => format!(
+                "{},{},{},{}",
+                typ,
+                self.client,
+                self.tx,
+                format_amount(self.amount)
+            ),
+            _ => format!("{},{},{},", typ, self.client, self.tx),
+        }
+    }
+}
+
+fn format_amount(amount: i64) -> String {
+    format!("{}.{:04}", amount / 10_000, amount % 10_000)
+}
+
+fn line_strategy() -> impl Strategy<Value = Line> {
+    let typ = prop_oneof![
+        4 => Just(Type::Deposit),
+        3 => Just(Type::Withdrawal),
+        2 => Just(Type::Dispute),
+        1 => Just(Type::Resolve),
+        1 => Just(Type::Chargeback),
+    ];
+    (
+        typ,
+        1..=CLIENT_COUNT,
+        1..=TRANSACTION_ID_COUNT,
+        // a few amounts are repeated often, so the same deposit can be replayed
+        prop_oneof![Just(10_000i64), Just(25_000i64), 0i64..=1_000_000],
+    )
+        .prop_map(|(typ, client, tx, amount)| Line {
+            typ,
+            client,
+            tx,
+            amount,
+        })
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ModelClient {
+    available: i64,
+    held: i64,
+    total: i64,
+    locked: bool,
+}
+
+struct ModelTransfer {
+    typ: Type,
+    client: u16,
+    amount: i64,
+    disputed: bool,
+}
+
+/// A straightforward implementation of the documented rules, without the optimizations of the engine
+/// (no bitmaps, no deposit stores, no sharding), with signed amounts so an underflow is visible.
+#[derive(Default)]
+struct Model {
+    clients: HashMap<u16, ModelClient>,
+    transfers: HashMap<u32, ModelTransfer>,
+}
+
+impl Model {
+    /// Returns the rejection reason, or `None` if the line is applied
+    fn apply(&mut self, line: &Line) -> Option<&'static str> {
+        match line.typ {
+            Type::Deposit | Type::Withdrawal => self.apply_transfer(line),
+            Type::Dispute | Type::Resolve | Type::Chargeback => self.apply_dispute(line),
+        }
+    }
+
+    fn apply_transfer(&mut self, line: &Line) -> Option<&'static str> {
+        if let Some(original) = self.transfers.get(&line.tx) {
+            let is_replay = original.typ == line.typ
+                && original.client == line.client
+                && original.amount == line.amount;
+            return Some(if is_replay {
+                "duplicate transaction id"
+            } else {
+                "conflicting transaction id"
+            });
+        }
+        // the rejected transfers are stored as well, so their IDs can not be reused
+        self.transfers.insert(
+            line.tx,
+            ModelTransfer {
+                typ: line.typ,
+                client: line.client,
+                amount: line.amount,
+                disputed: false,
+            },
+        );
+        let client = self.clients.entry(line.client).or_default();
+        if client.locked {
+            return Some("client locked");
+        }
+        if line.typ == Type::Deposit {
+            client.available += line.amount;
+            client.total += line.amount;
+        } else if line.amount > client.available {
+            return Some("insufficient funds");
+        } else {
+            client.available -= line.amount;
+            client.total -= line.amount;
+        }
+        None
+    }
+
+    fn apply_dispute(&mut self, line: &Line) -> Option<&'static str> {
+        let Some(transfer) = self.transfers.get_mut(&line.tx) else {
+            return Some("unknown transaction");
+        };
+        if transfer.typ != Type::Deposit {
+            return Some("not a deposit");
+        }
+        if transfer.client != line.client {
+            return Some("client mismatch");
+        }
+        let client = self.clients.entry(line.client).or_default();
+        match line.typ {
+            Type::Dispute => {
+                if transfer.disputed {
+                    return Some("already disputed");
+                }
+                if client.locked {
+                    return Some("client locked");
+                }
+                if client.available < transfer.amount {
+                    return Some("insufficient funds");
+                }
+                client.available -= transfer.amount;
+                client.held += transfer.amount;
+                transfer.disputed = true;
+            }
+            Type::Resolve => {
+                if !transfer.disputed {
+                    return Some("not disputed");
+                }
+                client.held -= transfer.amount;
+                client.available += transfer.amount;
+                transfer.disputed = false;
+            }
+            _ => {
+                if !transfer.disputed {
+                    return Some("not disputed");
+                }
+                if client.locked {
+                    return Some("client locked");
+                }
+                client.held -= transfer.amount;
+                client.total -= transfer.amount;
+                client.locked = true;
+                transfer.disputed = false;
+            }
+        }
+        None
+    }
+}
+
+/// A row of the changes output: the state of a client right after an applied line
+#[derive(Debug)]
+struct Change {
+    line_index: usize,
+    client: u16,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+/// Processes the lines with the real engine, returns the changes and the rejections by line index
+fn run_engine(lines: &[Line], config: &Config) -> (Vec<Change>, HashMap<usize, String>) {
+    let mut input = String::from("type,client,tx,amount\n");
+    for line in lines {
+        input.push_str(&line.to_csv());
+        input.push('\n');
+    }
+    let mut output = Vec::new();
+    let mut changes = BufWriter::new(Vec::new());
+    let mut rejections = BufWriter::new(Vec::new());
+    process_input_then_write_outputs(
+        input.as_bytes(),
+        &mut output,
+        Outputs {
+            rejections: Some(&mut rejections),
+            changes: Some(ChangesOutput {
+                writer: &mut changes,
+                interval: None,
+            }),
+            ..Outputs::default()
+        },
+        config,
+    );
+    let changes = String::from_utf8(changes.into_inner().unwrap()).unwrap();
+    let rejections = String::from_utf8(rejections.into_inner().unwrap()).unwrap();
+    let changes = changes
+        .lines()
+        .skip(1)
+        .map(|row| {
+            let cells: Vec<&str> = row.split(',').map(str::trim).collect();
+            Change {
+                line_index: cells[0].parse().unwrap(),
+                client: cells[1].parse().unwrap(),
+                available: Amount::parse(cells[2]).unwrap(),
+                held: Amount::parse(cells[3]).unwrap(),
+                total: Amount::parse(cells[4]).unwrap(),
+                locked: cells[5].parse().unwrap(),
+            }
+        })
+        .collect();
+    let rejections = rejections
+        .lines()
+        .skip(1)
+        .map(|row| {
+            let cells: Vec<&str> = row.split(',').collect();
+            (cells[0].parse().unwrap(), cells[4].to_owned())
+        })
+        .collect();
+    (changes, rejections)
+}
+
+fn to_amount(model_amount: i64) -> Amount {
+    Amount::parse(&format_amount(model_amount)).unwrap()
+}
+
+fn check_against_model(lines: &[Line], config: &Config) -> Result<(), TestCaseError> {
+    let (changes, rejections) = run_engine(lines, config);
+    let mut changes = changes.into_iter().peekable();
+    let mut model = Model::default();
+    let mut locked_clients = std::collections::HashSet::new();
+    for (i, line) in lines.iter().enumerate() {
+        let line_index = i + 1;
+        let expected_rejection = model.apply(line);
+        prop_assert_eq!(
+            rejections.get(&line_index).map(String::as_str),
+            expected_rejection,
+            "line {}: {:?}",
+            line_index,
+            line
+        );
+        if expected_rejection.is_some() {
+            continue;
+        }
+        let change = changes.next();
+        prop_assert!(change.is_some(), "line {} has no change", line_index);
+        let change = change.unwrap();
+        prop_assert_eq!(change.line_index, line_index);
+        prop_assert_eq!(change.client, line.client);
+
+        // the invariants of the engine itself
+        let mut sum = change.available;
+        sum += change.held;
+        prop_assert_eq!(
+            sum,
+            change.total,
+            "line {}: available + held != total",
+            line_index
+        );
+        prop_assert!(
+            change.held <= change.total,
+            "line {}: negative held",
+            line_index
+        );
+        prop_assert!(
+            !(locked_clients.contains(&line.client)
+                && matches!(line.typ, Type::Deposit | Type::Withdrawal)),
+            "line {}: a locked client was changed by a {:?}",
+            line_index,
+            line.typ
+        );
+        if change.locked {
+            locked_clients.insert(line.client);
+        }
+
+        // the model
+        let expected = &model.clients[&line.client];
+        prop_assert!(expected.held >= 0 && expected.available >= 0);
+        prop_assert_eq!(change.available, to_amount(expected.available));
+        prop_assert_eq!(change.held, to_amount(expected.held));
+        prop_assert_eq!(change.total, to_amount(expected.total));
+        prop_assert_eq!(change.locked, expected.locked);
+    }
+    prop_assert!(changes.next().is_none(), "a rejected line has a change");
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn engine_matches_the_reference_model(lines in prop::collection::vec(line_strategy(), 1..200)) {
+        check_against_model(&lines, &Config::default())?;
+    }
+
+    #[test]
+    fn engine_with_disk_store_matches_the_reference_model(
+        lines in prop::collection::vec(line_strategy(), 1..100)
+    ) {
+        let path = std::env::temp_dir().join(format!("properties_test_{}.bin", std::process::id()));
+        let config = Config {
+            store: StoreConfig::OnDisk {
+                path: path.clone(),
+                cache_capacity: 4,
+            },
+            ..Config::default()
+        };
+        let result = check_against_model(&lines, &config);
+        let _ = std::fs::remove_file(&path);
+        result?;
+    }
+}
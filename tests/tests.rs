@@ -1,5 +1,19 @@
-use std::io::BufWriter;
-use transactions_lib::process_input_then_write_output;
+use std::io::{BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use transactions_lib::amount::Amount;
+use transactions_lib::generator::{GeneratorConfig, InputGenerator};
+use transactions_lib::{
+    parse_input_with, process_input_then_write_output, process_input_then_write_outputs,
+    process_input_with_config_then_write_output, query_input_as_of_then_write_output,
+    read_limit_overrides, reconcile_input_then_write_report, resume_input_then_write_outputs,
+    run_scenarios_then_write_report, AsOf, ChangesOutput, CheckpointConfig, Config,
+    CustomFraudRule, FraudAction, FraudEvent, FraudRule, FraudRuleConfig, FraudRuleKind,
+    InputParser, LedgerEventType, LedgerFormat, LedgerOutput, Limits, LimitsConfig, Outputs,
+    Server, StoreConfig, SummaryFormat, SummaryOutput,
+};
 
 #[test]
 fn simple_simple_deposit_test() {
@@ -99,6 +113,23 @@ fn test_precision_loss() {
     );
 }
 
+// Parsing through f64 resulted in 2.9998, since 2.9999 * 10 000 = 29998.999999999996
+#[test]
+fn amounts_are_parsed_without_floating_point() {
+    assert_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,3  , 2.9999
+         deposit    ,2      ,4  , +1.5
+         deposit    ,3      ,5  , 7.",
+        // OUTPUT CSV
+        "client ,available ,held ,total  , locked
+         1      ,2.9999    ,0    ,2.9999 , false
+         2      ,1.5       ,0    ,1.5    , false
+         3      ,7         ,0    ,7      , false",
+    );
+}
+
 #[test]
 fn invalid_input_stops_processing() {
     assert_csv_eq(
@@ -174,6 +205,85 @@ fn invalid_input_stops_processing() {
     );
 }
 
+// Found by the `parse` fuzz target: the short lines of the flexible reader were indexed out of bounds
+#[test]
+fn short_lines_are_reported_as_errors() {
+    assert_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx      , amount
+         deposit    ,111    ,222",
+        // OUTPUT CSV
+        "Error: Invalid amount at line 1",
+    );
+    assert_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx      , amount
+         deposit",
+        // OUTPUT CSV
+        "Error: Invalid Client ID at line 1",
+    );
+    for input in [
+        "type,client,tx,amount,timestamp\n\"",
+        "type,client,tx,amount\ndeposit,1,1",
+        "type,client,tx,amount\ndeposit",
+    ] {
+        for parser in [InputParser::ByteRecord, InputParser::StringRecord] {
+            assert!(
+                parse_input_with(input.as_bytes(), parser).is_err(),
+                "{:?} {:?}",
+                parser,
+                input
+            );
+        }
+    }
+}
+
+// Found by the `process` fuzz target: the sum of the deposits overflowed the balance
+#[test]
+fn deposit_overflowing_the_balance_is_rejected() {
+    assert_rejections_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 1844674407370955
+         deposit    ,1      ,2  , 0.1615
+         deposit    ,1      ,3  , 0.0001
+         dispute    ,1      ,2
+         deposit    ,1      ,4  , 0.0001",
+        // OUTPUT CSV
+        "client ,available        ,held   ,total                 , locked
+         1      ,1844674407370955 ,0.1615 ,1844674407370955.1615 , false",
+        // REJECTIONS CSV
+        "line, type, client, tx, reason
+         3,deposit,1,3,balance overflow
+         5,deposit,1,4,balance overflow",
+        &Config::default(),
+    );
+}
+
+#[test]
+fn deposit_rejected_by_a_balance_overflow_can_not_be_disputed() {
+    assert_rejections_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 1844674407370955
+         deposit    ,1      ,2  , 0.1616
+         withdrawal ,1      ,3  , 1000
+         dispute    ,1      ,2
+         chargeback ,1      ,2
+         deposit    ,1      ,2  , 0.1616",
+        // OUTPUT CSV
+        "client ,available        ,held ,total            , locked
+         1      ,1844674407369955 ,0    ,1844674407369955 , false",
+        // REJECTIONS CSV
+        "line, type, client, tx, reason
+         2,deposit,1,2,balance overflow
+         4,dispute,1,2,unknown transaction
+         5,chargeback,1,2,not disputed
+         6,deposit,1,2,duplicate transaction id",
+        &Config::default(),
+    );
+}
+
 #[test]
 fn test_dispute_simple() {
     assert_csv_eq(
@@ -641,10 +751,2144 @@ fn client_has_no_available_amount_for_dispute() {
     );
 }
 
+#[test]
+fn query_as_of_line_ignores_the_later_lines() {
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 100.0
+                 deposit    ,2      ,2  , 20.0
+                 dispute    ,1      ,1
+                 chargeback ,1      ,1";
+    assert_query_csv_eq(
+        input,
+        AsOf::Line(1),
+        None,
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,100       ,0    ,100   , false",
+    );
+    assert_query_csv_eq(
+        input,
+        AsOf::Line(3),
+        None,
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,0         ,100  ,100   , false
+         2      ,20        ,0    ,20    , false",
+    );
+    assert_query_csv_eq(
+        input,
+        AsOf::Line(4),
+        Some(1),
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,0         ,0    ,0     , true",
+    );
+}
+
+#[test]
+fn query_as_of_line_does_not_parse_the_later_lines() {
+    assert_query_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 1.0
+         deposit    ,aaa    ,2  , 1.0",
+        AsOf::Line(1),
+        None,
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,1         ,0    ,1     , false",
+    );
+}
+
+#[test]
+fn query_for_a_non_existing_client_returns_only_the_header() {
+    assert_query_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 1.0",
+        AsOf::Line(1),
+        Some(42),
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked",
+    );
+}
+
+#[test]
+fn reconciliation_passes_when_the_balances_match() {
+    assert_reconciliation_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 10.0
+         deposit    ,2      ,2  , 2.5
+         dispute    ,2      ,2",
+        // EXPECTED BALANCES CSV
+        "client ,available ,held ,total , locked
+         2      ,0         ,2.5  ,2.50  , false
+         1      ,10.0000   ,0    ,10    , false",
+        // REPORT CSV
+        "client, field, expected, actual",
+        true,
+    );
+}
+
+#[test]
+fn reconciliation_reports_the_mismatched_fields_and_the_missing_clients() {
+    assert_reconciliation_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 10.0
+         deposit    ,2      ,2  , 2.5
+         dispute    ,2      ,2
+         chargeback ,2      ,2
+         deposit    ,4      ,3  , 1.0",
+        // EXPECTED BALANCES CSV
+        "client ,available ,held ,total , locked
+         1      ,9         ,0    ,9     , false
+         2      ,0         ,0    ,0     , false
+         3      ,1         ,0    ,1     , false",
+        // REPORT CSV
+        "client, field, expected, actual
+         1,available,9,10
+         1,total,9,10
+         2,locked,false,true
+         3,client,present,missing
+         4,client,missing,present",
+        false,
+    );
+}
+
+#[test]
+fn reconciliation_fails_on_an_invalid_balances_file() {
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 10.0";
+    assert_reconciliation_eq(
+        input,
+        "client ,available ,held",
+        "Error: Expected columns in the balances file: client, available, held, total, locked",
+        false,
+    );
+    assert_reconciliation_eq(
+        input,
+        "client ,available ,held ,total , locked
+         1      ,-10       ,0    ,10    , false",
+        "Error: Invalid available in the balances file at line 1",
+        false,
+    );
+    assert_reconciliation_eq(
+        input,
+        "client ,available ,held ,total , locked
+         1      ,10        ,0    ,10    , false
+         1      ,10        ,0    ,10    , false",
+        "Error: Duplicated client 1 in the balances file at line 2",
+        false,
+    );
+}
+
+/// Runs the scenarios in `tests/scenarios`, each of them is a directory with csv files,
+/// so they can be written without touching the Rust code.
+#[test]
+fn scenarios_pass() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let mut report = Vec::new();
+    let passed = run_scenarios_then_write_report(&dir, &mut report);
+    let report = String::from_utf8(report).unwrap();
+    assert!(passed, "{}", report);
+    assert!(
+        report.ends_with("9 scenarios, 9 passed, 0 failed\n"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn failed_scenarios_are_reported_with_their_differences() {
+    let dir = std::env::temp_dir().join("transactions_scenarios_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    let write = |scenario: &str, file_name: &str, content: &str| {
+        std::fs::create_dir_all(dir.join(scenario)).unwrap();
+        std::fs::write(dir.join(scenario).join(file_name), content).unwrap();
+    };
+    let input = "type, client, tx, amount
+                 deposit, 1, 1, 1.0
+                 withdrawal, 1, 2, 5.0";
+    write("a_passing", "input.csv", input);
+    write(
+        "a_passing",
+        "expected.csv",
+        "client, available, held, total, locked
+         1, 1.0000, 0, 1, false",
+    );
+    write("b_wrong_balance", "input.csv", input);
+    write(
+        "b_wrong_balance",
+        "expected.csv",
+        "client, available, held, total, locked
+         1, 2, 0, 2, false",
+    );
+    write(
+        "b_wrong_balance",
+        "rejections.csv",
+        "line, type, client, tx, reason",
+    );
+    write("c_invalid_config", "input.csv", input);
+    write("c_invalid_config", "expected.csv", "");
+    write("c_invalid_config", "config", "workers = many");
+
+    let mut report = BufWriter::new(Vec::new());
+    assert!(!run_scenarios_then_write_report(&dir, &mut report));
+    assert_output_eq(
+        report,
+        "PASS a_passing
+         FAIL b_wrong_balance
+           expected.csv: unexpected row `1,1,0,1,false`
+           expected.csv: missing row `1,2,0,2,false`
+           rejections.csv: unexpected row `2,withdrawal,1,2,insufficient funds`
+         FAIL c_invalid_config
+           Invalid option in config at line 1: workers = many
+         3 scenarios, 1 passed, 2 failed",
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn config_options_are_shared_by_the_command_line_and_the_scenarios() {
+    let mut from_args = Config::default();
+    assert_eq!(
+        Ok(true),
+        from_args.set_option("--max-withdrawal", Some("50"))
+    );
+    assert_eq!(Ok(true), from_args.set_option("--verify", None));
+    let mut from_scenario = Config::default();
+    assert_eq!(
+        Ok(true),
+        from_scenario.set_option("max_withdrawal", Some("50"))
+    );
+    assert_eq!(Ok(true), from_scenario.set_option("verify", Some("true")));
+    assert_eq!(from_args.limits, from_scenario.limits);
+    assert!(from_args.verify && from_scenario.verify);
+
+    assert!(Config::is_flag_option("--verify"));
+    assert!(!Config::is_flag_option("--max-withdrawal"));
+    assert_eq!(
+        Ok(false),
+        from_args.set_option("--ledger", Some("ledger.csv"))
+    );
+    assert_eq!(
+        Err("Invalid or missing value for --max-balance".to_owned()),
+        from_args.set_option("--max-balance", Some("-5"))
+    );
+    assert_eq!(
+        Err("Invalid or missing value for --dispute-window-days".to_owned()),
+        from_args.set_option("--dispute-window-days", Some(&u64::MAX.to_string()))
+    );
+    assert_eq!(
+        Err("Invalid or missing value for --withdrawal-volume-window-hours".to_owned()),
+        from_args.set_option(
+            "--withdrawal-volume-window-hours",
+            Some(&u64::MAX.to_string())
+        )
+    );
+}
+
+#[test]
+fn ledger_contains_only_the_balance_affecting_events() {
+    assert_ledger_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 100.0
+         deposit    ,2      ,2  , 20.0
+         withdrawal ,1      ,3  , 500.0
+         withdrawal ,1      ,4  , 30.0
+         dispute    ,1      ,1
+         dispute    ,1      ,2
+         deposit    ,1      ,5  , 50.0
+         dispute    ,1      ,5
+         resolve    ,1      ,5
+         dispute    ,1      ,5
+         chargeback ,1      ,5
+         deposit    ,1      ,6  , 50.0",
+        None,
+        // LEDGER CSV
+        "client ,line ,tx ,type       ,amount ,available ,held ,total ,locked
+         1      ,1    ,1  ,deposit    ,100    ,100       ,0    ,100   ,false
+         1      ,4    ,4  ,withdrawal ,30     ,70        ,0    ,70    ,false
+         1      ,7    ,5  ,deposit    ,50     ,120       ,0    ,120   ,false
+         1      ,8    ,5  ,dispute    ,50     ,70        ,50   ,120   ,false
+         1      ,9    ,5  ,resolve    ,50     ,120       ,0    ,120   ,false
+         1      ,10   ,5  ,dispute    ,50     ,70        ,50   ,120   ,false
+         1      ,11   ,5  ,chargeback ,50     ,70        ,0    ,70    ,true
+         2      ,2    ,2  ,deposit    ,20     ,20        ,0    ,20    ,false",
+    );
+}
+
+#[test]
+fn ledger_can_be_exported_for_a_single_client() {
+    assert_ledger_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 100.0
+         deposit    ,2      ,2  , 20.0",
+        Some(2),
+        // LEDGER CSV
+        "client ,line ,tx ,type       ,amount ,available ,held ,total ,locked
+         2      ,2    ,2  ,deposit    ,20     ,20        ,0    ,20    ,false",
+    );
+}
+
+#[test]
+fn ledger_can_be_exported_as_json() {
+    let mut output = Vec::new();
+    let mut ledger_output = Vec::new();
+    process_input_then_write_outputs(
+        "type, client, tx, amount
+         deposit, 1, 1, 0.5"
+            .as_bytes(),
+        &mut output,
+        Outputs {
+            ledger: Some(LedgerOutput {
+                writer: &mut ledger_output,
+                format: LedgerFormat::Json,
+                client_id: None,
+            }),
+            ..Outputs::default()
+        },
+        &Config::default(),
+    );
+    let actual: String = String::from_utf8(ledger_output)
+        .unwrap()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    assert_eq!(
+        actual,
+        r#"[{"client":1,"entries":[{"line":1,"tx":1,"type":"deposit","amount":"0.5","available":"0.5","held":"0","total":"0.5","locked":false}]}]"#
+    );
+}
+
+#[test]
+fn disk_store_works_when_the_transactions_are_evicted_from_the_cache() {
+    let path = std::env::temp_dir().join("transactions_disk_store_eviction_test.bin");
+    let config = Config {
+        store: StoreConfig::OnDisk {
+            path: path.clone(),
+            cache_capacity: 1,
+        },
+        ..Config::default()
+    };
+    assert_csv_eq_with_config(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 100.0
+         deposit    ,2      ,2  , 20.0
+         withdrawal ,2      ,3  , 5.0
+         deposit    ,1      ,4  , 10.0
+         dispute    ,1      ,1
+         deposit    ,2      ,5  , 1.0
+         deposit    ,1      ,1  , 999.0
+         dispute    ,2      ,3
+         chargeback ,1      ,1
+         deposit    ,2      ,2  , 999.0",
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,10        ,0    ,10    , true
+         2      ,16        ,0    ,16    , false",
+        &config,
+    );
+    assert!(!path.exists(), "The store file should be removed");
+}
+
+#[test]
+fn query_as_of_timestamp_ignores_the_later_lines() {
+    assert_query_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount , timestamp
+         deposit    ,1      ,1  , 100.0  , 1000
+         dispute    ,1      ,1  ,        ,
+         deposit    ,1      ,2  , 20.0   , 2000
+         deposit    ,1      ,3  , 20.0   , 1500",
+        AsOf::Timestamp(1999),
+        None,
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,0         ,100  ,100   , false",
+    );
+}
+
+#[test]
+fn timestamp_column_is_optional() {
+    assert_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount , timestamp
+         deposit    ,1      ,1  , 100.0  , 1000
+         deposit    ,1      ,2  , 20.0",
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,120       ,0    ,120   , false",
+    );
+    assert_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount , timestamp
+         deposit    ,1      ,1  , 100.0  , yesterday",
+        // OUTPUT CSV
+        "Error: Invalid timestamp at line 1",
+    );
+    assert_csv_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount , time
+         deposit    ,1      ,1  , 100.0  , 1000",
+        // OUTPUT CSV
+        "Error: Expected columns: type,client,tx,amount",
+    );
+}
+
+#[test]
+fn disputing_a_deposit_older_than_the_dispute_window_is_rejected() {
+    let config = Config {
+        dispute_window: Some(Duration::from_secs(100)),
+        ..Config::default()
+    };
+    assert_rejections_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount , timestamp
+         deposit    ,1      ,1  , 100.0  , 1000
+         deposit    ,1      ,2  , 10.0   , 1050
+         deposit    ,1      ,3  , 10.0
+         dispute    ,1      ,2  ,        , 1150
+         dispute    ,1      ,1  ,        , 1101
+         dispute    ,1      ,3  ,        , 99999",
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,100       ,20   ,120   , false",
+        // REJECTIONS CSV
+        "line ,type    ,client ,tx ,reason
+         5    ,dispute ,1      ,1  ,dispute window expired",
+        &config,
+    );
+}
+
+#[test]
+fn limits_can_be_overridden_per_client() {
+    let overrides = read_limit_overrides(
+        "client, max_withdrawal, max_withdrawal_volume, max_balance
+         2,      ,               ,                      1000.0
+         3,      5.0,            ,"
+            .as_bytes(),
+    )
+    .unwrap();
+    let config = Config {
+        limits: LimitsConfig {
+            default: Limits {
+                max_withdrawal: Some(Amount::from_f64(50.0)),
+                max_withdrawal_volume: Some(Amount::from_f64(70.0)),
+                max_balance: Some(Amount::from_f64(100.0)),
+            },
+            overrides,
+            withdrawal_volume_window: Duration::from_secs(100),
+        },
+        ..Config::default()
+    };
+    assert_rejections_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount , timestamp
+         deposit    ,1      ,1  , 100.0  , 0
+         deposit    ,1      ,2  , 0.0001 , 0
+         withdrawal ,1      ,3  , 40.0   , 10
+         withdrawal ,1      ,4  , 40.0   , 20
+         withdrawal ,1      ,5  , 40.0   , 110
+         deposit    ,2      ,6  , 500.0  , 110
+         withdrawal ,2      ,7  , 400.0  , 110
+         deposit    ,3      ,8  , 500.0  , 110
+         withdrawal ,3      ,9  , 6.0    , 110",
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,20        ,0    ,20    , false
+         2      ,100       ,0    ,100   , false
+         3      ,500       ,0    ,500   , false",
+        // REJECTIONS CSV
+        "line ,type       ,client ,tx ,reason
+         2    ,deposit    ,1      ,2  ,balance limit exceeded
+         4    ,withdrawal ,1      ,4  ,withdrawal volume limit exceeded
+         9    ,withdrawal ,3      ,9  ,withdrawal limit exceeded",
+        &config,
+    );
+
+    let invalid_limits = read_limit_overrides(
+        "client, max_withdrawal, max_withdrawal_volume, max_balance
+                                               1,      -5,             ,"
+            .as_bytes(),
+    );
+    assert_eq!(
+        "Invalid max_withdrawal in the limits file at line 1",
+        invalid_limits.unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn withdrawals_without_timestamp_are_in_the_window_from_the_latest_timestamp() {
+    let config = Config {
+        limits: LimitsConfig {
+            default: Limits {
+                max_withdrawal_volume: Some(Amount::from_f64(100.0)),
+                ..Limits::default()
+            },
+            withdrawal_volume_window: Duration::from_secs(1000),
+            ..LimitsConfig::default()
+        },
+        ..Config::default()
+    };
+    assert_rejections_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount , timestamp
+         deposit    ,1      ,1  , 500.0  ,
+         withdrawal ,1      ,2  , 80.0   ,
+         withdrawal ,1      ,3  , 80.0   , 500
+         withdrawal ,1      ,4  , 80.0   , 1000
+         withdrawal ,1      ,5  , 10.0   ,
+         withdrawal ,1      ,6  , 20.0   , 1999",
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,330       ,0    ,330   , false",
+        // REJECTIONS CSV
+        "line ,type       ,client ,tx ,reason
+         3    ,withdrawal ,1      ,3  ,withdrawal volume limit exceeded
+         6    ,withdrawal ,1      ,6  ,withdrawal volume limit exceeded",
+        &config,
+    );
+}
+
+#[test]
+fn withdrawal_volume_window_continues_after_resuming() {
+    let input = "type       ,client ,tx , amount , timestamp
+                 deposit    ,1      ,1  , 500.0  , 0
+                 withdrawal ,1      ,2  , 60.0   , 10
+                 withdrawal ,1      ,3  , 30.0   , 20
+                 withdrawal ,1      ,4  , 20.0   , 30
+                 withdrawal ,1      ,5  , 10.0   , 30
+                 withdrawal ,1      ,6  , 50.0   , 1015";
+    let expected_output = "client,available,held,total,locked\n1,350,0,350,false\n";
+    let expected_rejections =
+        "line, type, client, tx, reason\n4,withdrawal,1,4,withdrawal volume limit exceeded\n";
+    let limits = LimitsConfig {
+        default: Limits {
+            max_withdrawal_volume: Some(Amount::from_f64(100.0)),
+            ..Limits::default()
+        },
+        withdrawal_volume_window: Duration::from_secs(1000),
+        ..LimitsConfig::default()
+    };
+    let checkpoint_path = std::env::temp_dir().join("transactions_limits_checkpoint_test.bin");
+    let _ = std::fs::remove_file(&checkpoint_path);
+    let config = Config {
+        limits: limits.clone(),
+        checkpoint: Some(CheckpointConfig {
+            path: checkpoint_path.clone(),
+            interval: 3,
+        }),
+        ..Config::default()
+    };
+    // the first run stops at an invalid line after the checkpoint of the 3rd line
+    let interrupted_input = input.replace("withdrawal ,1      ,4", "withdrawal ,1      ,x");
+    let (output, _, _) = process_with_every_output(&interrupted_input, &config);
+    assert_eq!("Error: Invalid Transaction ID at line 4\n", output);
+    let (output, rejections) = resume_with_every_output(input, &config);
+    assert_eq!(expected_output, output.replace(' ', ""));
+    assert_eq!(expected_rejections, rejections);
+    std::fs::remove_file(&checkpoint_path).unwrap();
+
+    #[cfg(feature = "sqlite")]
+    {
+        let path = std::env::temp_dir().join("transactions_sqlite_limits_test.db");
+        remove_sqlite_database(&path);
+        let config = Config {
+            limits,
+            store: StoreConfig::Sqlite { path: path.clone() },
+            ..Config::default()
+        };
+        let first_part: String = input
+            .lines()
+            .take(4)
+            .map(|it| format!("{}\n", it))
+            .collect();
+        process_with_every_output(&first_part, &config);
+        let (output, _, rejections) = process_with_every_output(input, &config);
+        assert_eq!(expected_output, output.replace(' ', ""));
+        assert_eq!(expected_rejections, rejections);
+        remove_sqlite_database(&path);
+    }
+}
+
+#[test]
+fn deposit_rejected_by_the_balance_limit_can_not_be_disputed() {
+    let config = Config {
+        limits: LimitsConfig {
+            default: Limits {
+                max_balance: Some(Amount::from_f64(150.0)),
+                ..Limits::default()
+            },
+            ..LimitsConfig::default()
+        },
+        ..Config::default()
+    };
+    assert_rejections_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 100
+         deposit    ,1      ,2  , 100
+         dispute    ,1      ,2
+         chargeback ,1      ,2",
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,100       ,0    ,100   , false",
+        // REJECTIONS CSV
+        "line ,type       ,client ,tx ,reason
+         2    ,deposit    ,1      ,2  ,balance limit exceeded
+         3    ,dispute    ,1      ,2  ,unknown transaction
+         4    ,chargeback ,1      ,2  ,not disputed",
+        &config,
+    );
+}
+
+#[test]
+fn expired_deposits_are_evicted_but_their_ids_are_still_unique() {
+    let config = Config {
+        dispute_window: Some(Duration::from_secs(100)),
+        ..Config::default()
+    };
+    assert_rejections_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount , timestamp
+         deposit    ,1      ,1  , 100.0  , 1000
+         deposit    ,1      ,2  , 10.0   , 1000
+         dispute    ,1      ,2  ,        , 1001
+         deposit    ,1      ,3  , 10.0   , 2000
+         deposit    ,1      ,1  , 10.0   , 2000
+         dispute    ,1      ,1  ,        , 2000
+         chargeback ,1      ,2  ,        , 2000",
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,110       ,0    ,110   , true",
+        // REJECTIONS CSV
+        "line ,type    ,client ,tx ,reason
+         5    ,deposit ,1      ,1  ,conflicting transaction id
+         6    ,dispute ,1      ,1  ,dispute window expired",
+        &config,
+    );
+}
+
+#[test]
+fn deposits_disputed_when_they_expire_are_evicted_by_their_resolve_or_chargeback() {
+    let config = Config {
+        dispute_window: Some(Duration::from_secs(100)),
+        ..Config::default()
+    };
+    let input = "type       ,client ,tx , amount , timestamp
+                 deposit    ,1      ,1  , 100.0  , 1000
+                 deposit    ,1      ,2  , 10.0   , 1000
+                 deposit    ,2      ,3  , 10.0   , 1000
+                 dispute    ,1      ,1  ,        , 1001
+                 dispute    ,2      ,3  ,        , 1001
+                 deposit    ,1      ,4  , 10.0   , 2000
+                 resolve    ,1      ,1  ,        , 2000
+                 chargeback ,2      ,3  ,        , 2000
+                 dispute    ,1      ,1  ,        , 2000
+                 deposit    ,2      ,3  , 10.0   , 2000";
+    assert_rejections_eq(
+        input,
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,120       ,0    ,120   , false
+         2      ,0         ,0    ,0     , true",
+        // REJECTIONS CSV
+        "line ,type    ,client ,tx ,reason
+         9    ,dispute ,1      ,1  ,dispute window expired
+         10   ,deposit ,2      ,3  ,conflicting transaction id",
+        &config,
+    );
+    let mut metrics = Vec::new();
+    process_input_then_write_outputs(
+        input.as_bytes(),
+        std::io::sink(),
+        Outputs {
+            metrics: Some(&mut metrics),
+            ..Outputs::default()
+        },
+        &config,
+    );
+    // only the deposit 4 is left in the store
+    let metrics = String::from_utf8(metrics).unwrap();
+    assert!(metrics.contains("\ntransactions_stored_deposits 1\n"));
+}
+
+#[test]
+fn rejections_contain_the_reason_of_every_ignored_line() {
+    assert_rejections_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 100.0
+         deposit    ,1      ,1  , 100.0
+         withdrawal ,1      ,2  , 500.0
+         dispute    ,1      ,3
+         dispute    ,2      ,1
+         dispute    ,1      ,2
+         resolve    ,1      ,1
+         chargeback ,1      ,1
+         dispute    ,1      ,1
+         dispute    ,1      ,1
+         chargeback ,1      ,1
+         deposit    ,1      ,4  , 100.0
+         withdrawal ,1      ,5  , 1.0",
+        // OUTPUT CSV
+        "client ,available ,held ,total , locked
+         1      ,0         ,0    ,0     , true",
+        // REJECTIONS CSV
+        "line ,type       ,client ,tx ,reason
+         2    ,deposit    ,1      ,1  ,duplicate transaction id
+         3    ,withdrawal ,1      ,2  ,insufficient funds
+         4    ,dispute    ,1      ,3  ,unknown transaction
+         5    ,dispute    ,2      ,1  ,client mismatch
+         6    ,dispute    ,1      ,2  ,not a deposit
+         7    ,resolve    ,1      ,1  ,not disputed
+         8    ,chargeback ,1      ,1  ,not disputed
+         10   ,dispute    ,1      ,1  ,already disputed
+         12   ,deposit    ,1      ,4  ,client locked
+         13   ,withdrawal ,1      ,5  ,client locked",
+        &Config::default(),
+    );
+}
+
+#[test]
+fn rejected_lines_are_logged_with_their_fields() {
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 100.0
+                 withdrawal ,1      ,2  , 500.0
+                 deposit    ,2      ,1  , 100.0
+                 dispute    ,1      ,3";
+    let records = capture_logs(|| {
+        process_input_then_write_output(input.as_bytes(), std::io::sink());
+    });
+    assert_eq!(
+        records,
+        [
+            "INFO line=2 type=withdrawal tx=2 client=1 reason=insufficient funds",
+            "WARN line=3 type=deposit tx=1 client=2 reason=conflicting transaction id",
+            "INFO line=4 type=dispute tx=3 client=1 reason=unknown transaction",
+        ]
+    );
+}
+
+#[test]
+fn metrics_are_written_at_the_end_of_the_processing() {
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 10.0
+                 deposit    ,2      ,2  , 5.0
+                 withdrawal ,1      ,3  , 30.0
+                 dispute    ,1      ,1  ,
+                 dispute    ,2      ,2  ,
+                 chargeback ,2      ,2  ,";
+    let expected_samples = r#"transactions_lines_total{type="deposit"} 2
+                              transactions_lines_total{type="withdrawal"} 1
+                              transactions_lines_total{type="dispute"} 2
+                              transactions_lines_total{type="resolve"} 0
+                              transactions_lines_total{type="chargeback"} 1
+                              transactions_rejected_lines_total{reason="insufficient funds"} 1
+                              transactions_volume_total{type="deposit"} 15
+                              transactions_volume_total{type="withdrawal"} 0
+                              transactions_volume_total{type="dispute"} 15
+                              transactions_volume_total{type="resolve"} 0
+                              transactions_volume_total{type="chargeback"} 5
+                              transactions_clients 2
+                              transactions_locked_clients 1
+                              transactions_stored_deposits 2
+                              transactions_held_total 10"#;
+    let mut metrics = Vec::new();
+    process_input_then_write_outputs(
+        input.as_bytes(),
+        std::io::sink(),
+        Outputs {
+            metrics: Some(&mut metrics),
+            ..Outputs::default()
+        },
+        &Config::default(),
+    );
+    let metrics = String::from_utf8(metrics).unwrap();
+    // every metric has a HELP and a TYPE comment
+    assert_eq!(14, metrics.lines().filter(|it| it.starts_with('#')).count());
+    let samples: String = metrics
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    assert_output_eq(to_writer(&samples), expected_samples);
+}
+
+#[test]
+fn fraud_rules_record_alerts_and_lock_the_clients() {
+    /// Flags every deposit larger than 1000
+    struct LargeDeposit;
+
+    impl FraudRule for LargeDeposit {
+        fn name(&self) -> &str {
+            "large_deposit"
+        }
+
+        fn evaluate(&mut self, event: &FraudEvent) -> Option<String> {
+            let amount = event
+                .amount
+                .filter(|_| event.typ == LedgerEventType::Deposit)?;
+            (amount > Amount::from_f64(1000.0)).then(|| format!("deposit of {}", amount))
+        }
+    }
+
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 10.0
+                 withdrawal ,1      ,2  , 10.0
+                 deposit    ,2      ,3  , 2000.0
+                 dispute    ,2      ,3  ,
+                 resolve    ,2      ,3  ,
+                 dispute    ,2      ,3  ,
+                 withdrawal ,2      ,4  , 1.0
+                 deposit    ,3      ,5  , 10.0";
+    let expected_output = "client ,available ,held ,total , locked
+                           1      ,0         ,0    ,0     , false
+                           2      ,0         ,2000 ,2000  , true
+                           3      ,10        ,0    ,10    , false";
+    let expected_alerts = "line ,client ,rule                         ,action ,reason
+                           2    ,1      ,deposit_then_full_withdrawal ,flag   ,withdrawal of 10 emptied the account right after a deposit of 10
+                           3    ,2      ,large_deposit                ,flag   ,deposit of 2000
+                           6    ,2      ,frequent_disputes            ,lock   ,2 disputes in the last 3 events";
+    for workers in [1, 2] {
+        let config = Config {
+            workers,
+            fraud_rules: vec![
+                "deposit_then_full_withdrawal".parse().unwrap(),
+                FraudRuleConfig {
+                    kind: FraudRuleKind::Custom(CustomFraudRule(Arc::new(|| {
+                        Box::new(LargeDeposit)
+                    }))),
+                    action: FraudAction::Flag,
+                },
+                "frequent_disputes:max=1,events=3,action=lock"
+                    .parse()
+                    .unwrap(),
+            ],
+            ..Config::default()
+        };
+        let mut output = BufWriter::new(Vec::new());
+        let mut alerts = BufWriter::new(Vec::new());
+        process_input_then_write_outputs(
+            input.as_bytes(),
+            &mut output,
+            Outputs {
+                alerts: Some(&mut alerts),
+                ..Outputs::default()
+            },
+            &config,
+        );
+        assert_output_eq(output, expected_output);
+        assert_output_eq(alerts, expected_alerts);
+    }
+}
+
+#[test]
+fn fraud_rules_continue_after_resuming() {
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 100.0
+                 dispute    ,1      ,1  ,
+                 deposit    ,3      ,4  , 5.0
+                 withdrawal ,3      ,5  , 5.0
+                 deposit    ,2      ,2  , 50.0
+                 withdrawal ,2      ,3  , 50.0
+                 resolve    ,1      ,1  ,
+                 dispute    ,1      ,1  ,";
+    let expected_output = "client ,available ,held ,total , locked
+                           1      ,0         ,100  ,100   , true
+                           2      ,0         ,0    ,0     , false
+                           3      ,0         ,0    ,0     , false";
+    let expected_alerts = "line ,client ,rule                         ,action ,reason
+                           4    ,3      ,deposit_then_full_withdrawal ,flag   ,withdrawal of 5 emptied the account right after a deposit of 5
+                           6    ,2      ,deposit_then_full_withdrawal ,flag   ,withdrawal of 50 emptied the account right after a deposit of 50
+                           8    ,1      ,frequent_disputes            ,lock   ,2 disputes in the last 3 events";
+    let fraud_rules: Vec<FraudRuleConfig> = vec![
+        "deposit_then_full_withdrawal".parse().unwrap(),
+        "frequent_disputes:max=1,events=3,action=lock"
+            .parse()
+            .unwrap(),
+    ];
+    let process = |input: &str, config: &Config, resume: bool| {
+        let mut output = BufWriter::new(Vec::new());
+        let mut alerts = BufWriter::new(Vec::new());
+        let outputs = Outputs {
+            alerts: Some(&mut alerts),
+            ..Outputs::default()
+        };
+        if resume {
+            resume_input_then_write_outputs(
+                std::io::Cursor::new(input.as_bytes()),
+                &mut output,
+                outputs,
+                config,
+            );
+        } else {
+            process_input_then_write_outputs(input.as_bytes(), &mut output, outputs, config);
+        }
+        (output, alerts)
+    };
+
+    let checkpoint_path = std::env::temp_dir().join("transactions_fraud_checkpoint_test.bin");
+    let _ = std::fs::remove_file(&checkpoint_path);
+    let config = Config {
+        fraud_rules: fraud_rules.clone(),
+        checkpoint: Some(CheckpointConfig {
+            path: checkpoint_path.clone(),
+            interval: 5,
+        }),
+        ..Config::default()
+    };
+    // the first run stops at an invalid line after the checkpoint of the 5th line
+    let interrupted_input = input.replace("withdrawal ,2      ,3", "withdrawal ,2      ,x");
+    process(&interrupted_input, &config, false);
+    let (output, alerts) = process(input, &config, true);
+    assert_output_eq(output, expected_output);
+    assert_output_eq(alerts, expected_alerts);
+    std::fs::remove_file(&checkpoint_path).unwrap();
+
+    #[cfg(feature = "sqlite")]
+    {
+        let path = std::env::temp_dir().join("transactions_sqlite_fraud_test.db");
+        remove_sqlite_database(&path);
+        let config = Config {
+            fraud_rules,
+            store: StoreConfig::Sqlite { path: path.clone() },
+            ..Config::default()
+        };
+        let first_part: String = input
+            .lines()
+            .take(6)
+            .map(|it| format!("{}\n", it))
+            .collect();
+        process(&first_part, &config, false);
+        let (output, alerts) = process(input, &config, false);
+        assert_output_eq(output, expected_output);
+        assert_output_eq(alerts, expected_alerts);
+        remove_sqlite_database(&path);
+    }
+}
+
+#[test]
+fn reused_transaction_ids_are_classified_as_replays_or_conflicts() {
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 10.0
+                 withdrawal ,1      ,2  , 5.0
+                 deposit    ,1      ,1  , 10.0
+                 withdrawal ,1      ,2  , 5.0
+                 deposit    ,1      ,1  , 11.0
+                 deposit    ,2      ,1  , 10.0
+                 withdrawal ,1      ,1  , 10.0
+                 deposit    ,1      ,2  , 5.0
+                 withdrawal ,2      ,2  , 5.0";
+    let expected_output = "client ,available ,held ,total , locked
+                           1      ,5         ,0    ,5     , false";
+    let expected_rejections = "line ,type       ,client ,tx ,reason
+                               3    ,deposit    ,1      ,1  ,duplicate transaction id
+                               4    ,withdrawal ,1      ,2  ,duplicate transaction id
+                               5    ,deposit    ,1      ,1  ,conflicting transaction id
+                               6    ,deposit    ,2      ,1  ,conflicting transaction id
+                               7    ,withdrawal ,1      ,1  ,conflicting transaction id
+                               8    ,deposit    ,1      ,2  ,conflicting transaction id
+                               9    ,withdrawal ,2      ,2  ,conflicting transaction id";
+    let expected_summary = "replayed transaction ids: 2
+                            conflicting transaction ids: 5";
+    let store_path = std::env::temp_dir().join("transactions_duplicates_test.bin");
+    let configs = [
+        Config::default(),
+        // the original transfers of the conflicting lines of client 2 are processed by another worker
+        Config {
+            workers: 2,
+            ..Config::default()
+        },
+        // the withdrawals are compared with the deposit store as well, even after they are evicted from its cache
+        Config {
+            store: StoreConfig::OnDisk {
+                path: store_path,
+                cache_capacity: 1,
+            },
+            ..Config::default()
+        },
+    ];
+    for config in configs {
+        assert_rejections_eq(input, expected_output, expected_rejections, &config);
+        let summary = write_summary(input, SummaryFormat::Text, &config);
+        let duplicate_counters: String = summary
+            .lines()
+            .filter(|line| line.contains("transaction ids"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_output_eq(to_writer(&duplicate_counters), expected_summary);
+    }
+    #[cfg(feature = "sqlite")]
+    {
+        let path = std::env::temp_dir().join("transactions_sqlite_duplicates_test.db");
+        remove_sqlite_database(&path);
+        let config = Config {
+            store: StoreConfig::Sqlite { path: path.clone() },
+            ..Config::default()
+        };
+        assert_rejections_eq(input, expected_output, expected_rejections, &config);
+        remove_sqlite_database(&path);
+    }
+}
+
+#[test]
+fn summary_counts_the_lines_per_type_and_per_rejection_reason() {
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 10.0
+                 deposit    ,2      ,2  , 5.0
+                 withdrawal ,1      ,3  , 3.0
+                 withdrawal ,2      ,4  , 10.0
+                 dispute    ,2      ,2  ,
+                 chargeback ,2      ,2  ,
+                 deposit    ,2      ,5  , 1.0
+                 resolve    ,1      ,1  ,";
+    let expected_summary = "lines: 8
+                            deposit lines: 3
+                            withdrawal lines: 2
+                            dispute lines: 1
+                            resolve lines: 1
+                            chargeback lines: 1
+                            applied lines: 5
+                            rejected lines: 3
+                            rejected as client locked: 1
+                            rejected as insufficient funds: 1
+                            rejected as not disputed: 1
+                            replayed transaction ids: 0
+                            conflicting transaction ids: 0
+                            deposit volume: 15
+                            withdrawal volume: 3
+                            dispute volume: 5
+                            resolve volume: 0
+                            chargeback volume: 5
+                            locked clients: 1
+                            peak stored deposits: 3";
+    let summary = write_summary(input, SummaryFormat::Text, &Config::default());
+    // the timing depends on the machine, only its presence is checked
+    let (counters, timing) = summary.split_at(summary.find("elapsed seconds: ").unwrap());
+    assert_output_eq(to_writer(counters), expected_summary);
+    assert!(timing.contains("lines per second: "));
+
+    let json: serde_json::Value = serde_json::from_str(&write_summary(
+        input,
+        SummaryFormat::Json,
+        &Config::default(),
+    ))
+    .unwrap();
+    assert_eq!(json["lines"], 8);
+    assert_eq!(json["lines_by_type"]["deposit"], 3);
+    assert_eq!(json["rejections_by_reason"]["insufficient funds"], 1);
+    assert_eq!(json["volumes"]["deposit"], "15");
+    assert_eq!(json["locked_clients"], 1);
+    assert_eq!(json["peak_stored_deposits"], 3);
+}
+
+#[test]
+fn parallel_processing_gives_the_same_result_as_the_sequential() {
+    // with 2 workers, the rejections of more rounds are waiting than the capacity of the channels
+    let input = generate_input(40_000);
+    let sequential_config = Config {
+        dispute_window: Some(Duration::from_secs(500)),
+        ..Config::default()
+    };
+    let sequential = process_with_every_output(&input, &sequential_config);
+    // the client states, the ledger and the rejections
+    assert!(sequential.0.lines().count() > 2);
+    assert!(sequential.2.lines().count() > 2);
+    for workers in [2, 4] {
+        let parallel_config = Config {
+            workers,
+            ..sequential_config.clone()
+        };
+        let parallel = process_with_every_output(&input, &parallel_config);
+        assert_eq!(sequential, parallel);
+    }
+}
+
+#[test]
+fn verify_mode_does_not_change_the_results() {
+    let input = generate_input(20_000);
+    let config = Config {
+        dispute_window: Some(Duration::from_secs(500)),
+        ..Config::default()
+    };
+    let expected = process_with_every_output(&input, &config);
+    assert!(!expected.0.contains("Error"));
+    for workers in [1, 4] {
+        let verified = process_with_every_output(
+            &input,
+            &Config {
+                verify: true,
+                workers,
+                ..config.clone()
+            },
+        );
+        assert_eq!(expected, verified);
+    }
+}
+
+#[test]
+fn parallel_processing_stops_at_the_first_invalid_line() {
+    assert_csv_eq_with_config(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 1.0
+         deposit    ,2      ,x  , 2.0",
+        // OUTPUT CSV
+        "Error: Invalid Transaction ID at line 2",
+        &Config {
+            workers: 4,
+            ..Config::default()
+        },
+    );
+}
+
+#[test]
+fn generated_input_is_reproducible_and_only_the_requested_ratio_is_ignored() {
+    let generator_config = GeneratorConfig {
+        transactions: 20_000,
+        clients: 100,
+        error_rate: 0.05,
+        seed: 7,
+        ..GeneratorConfig::default()
+    };
+    let input = generate(generator_config.clone());
+    assert_eq!(input, generate(generator_config.clone()));
+    assert_ne!(
+        input,
+        generate(GeneratorConfig {
+            seed: 8,
+            ..generator_config
+        })
+    );
+
+    let (output, _, rejections) = process_with_every_output(&input, &Config::default());
+    assert!(output.starts_with("client, available, held, total, locked"));
+    // the header is not counted
+    let ignored_lines = rejections.lines().count() - 1;
+    assert!(
+        (800..1200).contains(&ignored_lines),
+        "{} lines were ignored instead of ~1000",
+        ignored_lines
+    );
+    for typ in ["deposit", "withdrawal", "dispute", "resolve", "chargeback"] {
+        assert!(input.contains(&format!("\n{},", typ)), "No {} lines", typ);
+    }
+}
+
+#[test]
+fn changes_are_written_after_every_applied_line() {
+    assert_changes_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 10.0
+         deposit    ,2      ,2  , 5.0
+         withdrawal ,1      ,3  , 50.0
+         dispute    ,1      ,1
+         chargeback ,1      ,1",
+        None,
+        // CHANGES CSV
+        "line ,client ,available ,held ,total , locked
+         1    ,1      ,10        ,0    ,10    , false
+         2    ,2      ,5         ,0    ,5     , false
+         4    ,1      ,0         ,10   ,10    , false
+         5    ,1      ,0         ,0    ,0     , true",
+    );
+}
+
+#[test]
+fn changes_can_be_written_periodically() {
+    assert_changes_eq(
+        // INPUT CSV
+        "type       ,client ,tx , amount
+         deposit    ,2      ,1  , 10.0
+         deposit    ,1      ,2  , 5.0
+         deposit    ,2      ,3  , 1.0
+         withdrawal ,1      ,4  , 50.0
+         deposit    ,3      ,5  , 1.0",
+        Some(3),
+        // CHANGES CSV
+        "line ,client ,available ,held ,total , locked
+         2    ,1      ,5         ,0    ,5     , false
+         3    ,2      ,11        ,0    ,11    , false
+         5    ,3      ,1         ,0    ,1     , false",
+    );
+}
+
+#[test]
+fn output_is_sorted_by_client_id_by_default() {
+    let mut actual_output = BufWriter::new(Vec::new());
+    process_input_then_write_output(
+        "type    ,client ,tx , amount
+         deposit ,3      ,1  , 1.0
+         deposit ,1      ,2  , 2.0
+         deposit ,2      ,3  , 3.0"
+            .as_bytes(),
+        &mut actual_output,
+    );
+    assert_output_eq(
+        actual_output,
+        "client ,available ,held ,total , locked
+         1      ,2         ,0    ,2     , false
+         2      ,3         ,0    ,3     , false
+         3      ,1         ,0    ,1     , false",
+    );
+}
+
+#[test]
+fn server_applies_the_posted_transactions_and_returns_the_client_states() {
+    let server = Server::bind(0, &Config::default()).unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    let (status, body) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "text/csv",
+        "type, client, tx, amount
+         deposit, 1, 1, 10.0
+         withdrawal, 1, 2, 50.0",
+    );
+    assert_eq!(200, status);
+    assert_eq!(
+        r#"[{"line":1,"outcome":"applied"},{"line":2,"outcome":"rejected","reason":"insufficient funds"}]"#,
+        body
+    );
+    let (status, body) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "application/json",
+        r#"[{"type": "deposit", "client": 2, "tx": 3, "amount": "2.5"},
+            {"type": "dispute", "client": 1, "tx": 1}]"#,
+    );
+    assert_eq!(200, status);
+    assert_eq!(
+        r#"[{"line":3,"outcome":"applied"},{"line":4,"outcome":"applied"}]"#,
+        body
+    );
+
+    let (status, body) = http_request(addr, "GET", "/clients", "", "");
+    assert_eq!(200, status);
+    assert_eq!(
+        r#"[{"client":1,"available":"0","held":"10","total":"10","locked":false},"#.to_owned()
+            + r#"{"client":2,"available":"2.5","held":"0","total":"2.5","locked":false}]"#,
+        body
+    );
+    let (status, body) = http_request(addr, "GET", "/clients/2", "", "");
+    assert_eq!(200, status);
+    assert_eq!(
+        r#"{"client":2,"available":"2.5","held":"0","total":"2.5","locked":false}"#,
+        body
+    );
+    let (status, _) = http_request(addr, "GET", "/clients/3", "", "");
+    assert_eq!(404, status);
+
+    let (status, body) = http_request(addr, "GET", "/metrics", "", "");
+    assert_eq!(200, status);
+    for sample in [
+        "transactions_lines_total{type=\"deposit\"} 2",
+        "transactions_rejected_lines_total{reason=\"insufficient funds\"} 1",
+        "transactions_clients 2",
+        "transactions_held_total 10",
+    ] {
+        assert!(body.lines().any(|line| line == sample), "{}", body);
+    }
+}
+
+#[test]
+fn server_applies_nothing_from_a_request_with_an_invalid_row() {
+    let server = Server::bind(0, &Config::default()).unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    let (status, body) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "text/csv",
+        "deposit, 1, 1, 10.0
+         deposit, 1, x, 10.0",
+    );
+    assert_eq!(400, status);
+    assert_eq!(r#"{"error":"Invalid Transaction ID at line 2"}"#, body);
+    let (_, body) = http_request(addr, "GET", "/clients", "", "");
+    assert_eq!("[]", body);
+}
+
+#[test]
+fn server_rejects_too_large_or_too_many_headers() {
+    let server = Server::bind(0, &Config::default()).unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    let long_header = format!("X-Long: {}\r\n", "a".repeat(100_000));
+    let many_headers = "X-Header: a\r\n".repeat(1000);
+    for headers in [long_header, many_headers] {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        // the server might close the connection before the whole request is sent
+        let _ = write!(stream, "GET /clients HTTP/1.1\r\n{}\r\n", headers);
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+            "{}",
+            response
+        );
+    }
+    let (status, _) = http_request(addr, "GET", "/clients", "", "");
+    assert_eq!(200, status);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn server_fails_only_the_request_when_the_store_fails() {
+    let path = std::env::temp_dir().join("transactions_server_store_failure_test.db");
+    remove_sqlite_database(&path);
+    // the audit would find a violation at the retry, if the failed row was not rolled back
+    let config = Config {
+        store: StoreConfig::Sqlite { path: path.clone() },
+        verify: true,
+        ..Config::default()
+    };
+    let server = Server::bind(0, &config).unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    let (status, _) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "text/csv",
+        "deposit, 1, 1, 1.0",
+    );
+    assert_eq!(200, status);
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute_batch("DROP TABLE transactions")
+        .unwrap();
+    let (status, body) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "text/csv",
+        "deposit, 1, 2, 1.0",
+    );
+    assert_eq!(500, status);
+    assert!(body.contains("no such table"), "{}", body);
+    // the failed row is rolled back, so it can be retried when the store works again
+    let (status, body) = http_request(addr, "GET", "/clients/1", "", "");
+    assert_eq!(200, status);
+    assert_eq!(
+        r#"{"client":1,"available":"1","held":"0","total":"1","locked":false}"#,
+        body
+    );
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE transactions (
+                 tx INTEGER PRIMARY KEY, withdrawal_client INTEGER, withdrawal_amount INTEGER
+             )",
+        )
+        .unwrap();
+    let (status, body) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "text/csv",
+        "deposit, 1, 2, 1.0",
+    );
+    assert_eq!(200, status);
+    assert_eq!(r#"[{"line":2,"outcome":"applied"}]"#, body);
+    let (_, body) = http_request(addr, "GET", "/clients/1", "", "");
+    assert_eq!(
+        r#"{"client":1,"available":"2","held":"0","total":"2","locked":false}"#,
+        body
+    );
+    remove_sqlite_database(&path);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_store_rejects_amounts_out_of_its_range() {
+    let path = std::env::temp_dir().join("transactions_sqlite_range_test.db");
+    remove_sqlite_database(&path);
+    let config = Config {
+        store: StoreConfig::Sqlite { path: path.clone() },
+        ..Config::default()
+    };
+    let (output, _, _) = process_with_every_output(
+        "type    ,client ,tx , amount
+         deposit ,1      ,1  , 1.0
+         deposit ,1      ,2  , 1000000000000000.0",
+        &config,
+    );
+    assert_eq!(
+        "Error: The amount 1000000000000000 is too large for the SQLite store\n",
+        output
+    );
+    let totals: Vec<f64> = rusqlite::Connection::open(&path)
+        .unwrap()
+        .prepare("SELECT total FROM balances")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(vec![1.0], totals);
+    remove_sqlite_database(&path);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_store_commits_nothing_of_a_failed_line() {
+    let path = std::env::temp_dir().join("transactions_sqlite_failed_line_test.db");
+    remove_sqlite_database(&path);
+    let config = Config {
+        store: StoreConfig::Sqlite { path: path.clone() },
+        ..Config::default()
+    };
+    let server = Server::bind(0, &config).unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+    let connection = rusqlite::Connection::open(&path).unwrap();
+
+    let (status, _) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "text/csv",
+        "deposit, 1, 1, 1.0",
+    );
+    assert_eq!(200, status);
+    // the deposit and the client of the next line are written before the Transaction ID fails
+    connection.execute_batch("DROP TABLE transactions").unwrap();
+    let (status, _) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "text/csv",
+        "deposit, 1, 2, 1.0",
+    );
+    assert_eq!(500, status);
+    connection
+        .execute_batch(
+            "CREATE TABLE transactions (
+                 tx INTEGER PRIMARY KEY, withdrawal_client INTEGER, withdrawal_amount INTEGER
+             )",
+        )
+        .unwrap();
+    let (status, _) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        "text/csv",
+        "deposit, 2, 3, 5.0",
+    );
+    assert_eq!(200, status);
+
+    let deposits: Vec<u32> = connection
+        .prepare("SELECT tx FROM deposits ORDER BY tx")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(vec![1, 3], deposits);
+    let totals: Vec<(u16, f64)> = connection
+        .prepare("SELECT client, total FROM balances ORDER BY client")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(vec![(1, 1.0), (2, 5.0)], totals);
+    remove_sqlite_database(&path);
+}
+
+#[test]
+fn resumed_processing_applies_every_line_exactly_once() {
+    let input = generate_input(4000);
+    let expected_config = Config {
+        dispute_window: Some(Duration::from_secs(500)),
+        ..Config::default()
+    };
+    let (expected_output, _, expected_rejections) =
+        process_with_every_output(&input, &expected_config);
+    // the rejections of the lines after the last checkpoint
+    let expected_rejections: String = expected_rejections
+        .lines()
+        .enumerate()
+        .filter(|(index, line)| {
+            *index == 0 || line.split(',').next().unwrap().parse::<usize>().unwrap() > 2000
+        })
+        .map(|(_, line)| format!("{}\n", line))
+        .collect();
+    // the first run stops at an invalid line after the checkpoint of the 2000th line
+    let mut interrupted_input: Vec<&str> = input.lines().collect();
+    interrupted_input[2500] = "deposit,1,x,1";
+    let interrupted_input = interrupted_input.join("\n");
+
+    let disk_store_path = std::env::temp_dir().join("transactions_checkpoint_disk_store_test.bin");
+    let stores = [
+        StoreConfig::InMemory,
+        StoreConfig::OnDisk {
+            path: disk_store_path,
+            cache_capacity: 10,
+        },
+    ];
+    for (index, store) in stores.into_iter().enumerate() {
+        let checkpoint_path =
+            std::env::temp_dir().join(format!("transactions_checkpoint_test_{}.bin", index));
+        let _ = std::fs::remove_file(&checkpoint_path);
+        let config = Config {
+            store,
+            checkpoint: Some(CheckpointConfig {
+                path: checkpoint_path.clone(),
+                interval: 1000,
+            }),
+            ..expected_config.clone()
+        };
+        let (output, _, _) = process_with_every_output(&interrupted_input, &config);
+        assert_eq!("Error: Invalid Transaction ID at line 2500\n", output);
+
+        let (output, rejections) = resume_with_every_output(&input, &config);
+        assert_eq!(expected_output, output);
+        assert_eq!(expected_rejections, rejections);
+        // the final checkpoint is at the end of the input, so nothing is applied again
+        let (output, rejections) = resume_with_every_output(&input, &config);
+        assert_eq!(expected_output, output);
+        assert_eq!("line, type, client, tx, reason\n", rejections);
+        std::fs::remove_file(&checkpoint_path).unwrap();
+    }
+}
+
+#[test]
+fn resuming_from_a_corrupted_checkpoint_fails() {
+    let checkpoint_path = std::env::temp_dir().join("transactions_corrupted_checkpoint_test.bin");
+    // the position, no current time, then a client count which could not be allocated
+    let mut checkpoint = b"TXCKPT04".to_vec();
+    checkpoint.extend_from_slice(&[0; 17]);
+    checkpoint.extend_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(&checkpoint_path, checkpoint).unwrap();
+    let config = Config {
+        checkpoint: Some(CheckpointConfig {
+            path: checkpoint_path.clone(),
+            interval: 1,
+        }),
+        ..Config::default()
+    };
+    let (output, _) = resume_with_every_output(
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 1.0",
+        &config,
+    );
+    assert_eq!(
+        "Error: Could not resume from the checkpoint: failed to fill whole buffer\n",
+        output
+    );
+    std::fs::remove_file(&checkpoint_path).unwrap();
+}
+
+#[test]
+fn resuming_without_checkpoint_processes_the_whole_input() {
+    let checkpoint_path = std::env::temp_dir().join("transactions_missing_checkpoint_test.bin");
+    let _ = std::fs::remove_file(&checkpoint_path);
+    let config = Config {
+        checkpoint: Some(CheckpointConfig {
+            path: checkpoint_path.clone(),
+            interval: 1,
+        }),
+        ..Config::default()
+    };
+    let (output, _) = resume_with_every_output(
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 1.0
+         deposit    ,2      ,2  , 2.0",
+        &config,
+    );
+    assert_csv_rows_eq(
+        BufWriter::new(output.into_bytes()),
+        "client ,available ,held ,total , locked
+         1      ,1         ,0    ,1     , false
+         2      ,2         ,0    ,2     , false",
+    );
+    assert!(checkpoint_path.exists());
+    std::fs::remove_file(&checkpoint_path).unwrap();
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "current_thread")]
+async fn async_engine_yields_the_outcome_of_every_transaction() {
+    use futures_util::stream::{self, StreamExt};
+    use transactions_lib::{AsyncEngine, Outcome, RejectionReason, Transaction};
+
+    let mut engine = AsyncEngine::new(&Config::default()).unwrap();
+    let outcomes: Vec<Outcome> = engine
+        .process(stream::iter([
+            Transaction::deposit(1, 1, Amount::parse("10.0").unwrap()),
+            Transaction::withdrawal(1, 2, Amount::parse("20.0").unwrap()),
+            Transaction::dispute(1, 1).with_timestamp(100),
+            Transaction::chargeback(1, 1),
+        ]))
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(
+        vec![
+            Outcome::Applied,
+            Outcome::Rejected(RejectionReason::InsufficientFunds),
+            Outcome::Applied,
+            Outcome::Applied,
+        ],
+        outcomes
+    );
+
+    let mut output = BufWriter::new(Vec::new());
+    engine.write_client_states_to(&mut output).unwrap();
+    assert_csv_rows_eq(
+        output,
+        "client, available, held, total, locked
+         1     , 0        , 0   , 0    , true",
+    );
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "current_thread")]
+async fn async_engine_processes_csv_until_the_first_invalid_line() {
+    use futures_util::StreamExt;
+    use transactions_lib::{AsyncEngine, Outcome, RejectionReason};
+
+    let mut engine = AsyncEngine::new(&Config::default()).unwrap();
+    let input = "type       ,client ,tx , amount
+                 deposit    ,1      ,1  , 10.0
+
+                 deposit    ,1      ,1  , 10.0
+                 deposit    ,1      ,x  , 10.0
+                 deposit    ,1      ,3  , 10.0";
+    let outcomes: Vec<Result<Outcome, String>> = engine
+        .process_csv(input.as_bytes())
+        .map(|result| result.map_err(|err| err.to_string()))
+        .collect()
+        .await;
+    assert_eq!(
+        vec![
+            Ok(Outcome::Applied),
+            Ok(Outcome::Rejected(RejectionReason::DuplicateTransactionId)),
+            Err("Invalid Transaction ID at line 3".to_owned()),
+        ],
+        outcomes
+    );
+
+    let mut engine = AsyncEngine::new(&Config::default()).unwrap();
+    let mut outcomes = std::pin::pin!(engine.process_csv("type, client\ndeposit, 1".as_bytes()));
+    let error = outcomes.next().await.unwrap().unwrap_err();
+    assert_eq!(
+        "Expected columns: type, client, tx, amount",
+        error.to_string()
+    );
+    assert!(outcomes.next().await.is_none());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "current_thread")]
+async fn async_engine_reads_csv_records_instead_of_lines() {
+    use futures_util::StreamExt;
+    use transactions_lib::{AsyncEngine, Outcome};
+
+    let mut engine = AsyncEngine::new(&Config::default()).unwrap();
+    let mut input = b"type, client, tx, amount\n".to_vec();
+    // a quoted field with a line break
+    input.extend_from_slice(b"deposit, 1, 1,\"10.0\n\"\n");
+    // the columns after the timestamp are ignored, even if they are not valid UTF-8
+    input.extend_from_slice(b"withdrawal, 1, 2, 3.0, , \xff\xfe\n");
+    // a record larger than the buffers of the reader
+    let long_row = format!("withdrawal, 1, 3, 1.0, , {}\n", "x,".repeat(2000));
+    input.extend_from_slice(long_row.as_bytes());
+    let outcomes: Vec<Outcome> = engine
+        .process_csv(input.as_slice())
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(vec![Outcome::Applied; 3], outcomes);
+
+    let mut output = BufWriter::new(Vec::new());
+    engine.write_client_states_to(&mut output).unwrap();
+    assert_csv_rows_eq(
+        output,
+        "client, available, held, total, locked
+         1     , 6        , 0   , 6    , false",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_store_continues_from_the_state_of_the_previous_run() {
+    let path = std::env::temp_dir().join("transactions_sqlite_resume_test.db");
+    remove_sqlite_database(&path);
+    let in_memory_config = Config {
+        dispute_window: Some(Duration::from_secs(500)),
+        ..Config::default()
+    };
+    let sqlite_config = Config {
+        store: StoreConfig::Sqlite { path: path.clone() },
+        ..in_memory_config.clone()
+    };
+    let input = generate_input(4000);
+    let (expected_output, _, expected_rejections) =
+        process_with_every_output(&input, &in_memory_config);
+
+    // the first run stops after the 2000th line, e.g. because of a crash
+    let first_part: String = input
+        .lines()
+        .take(2001)
+        .map(|it| format!("{}\n", it))
+        .collect();
+    process_with_every_output(&first_part, &sqlite_config);
+    let (output, _, rejections) = process_with_every_output(&input, &sqlite_config);
+    assert_eq!(expected_output, output);
+    // the lines of the first run are not applied again
+    let expected_rejections: Vec<&str> = expected_rejections
+        .lines()
+        .enumerate()
+        .filter(|(index, line)| {
+            *index == 0 || line.split(',').next().unwrap().parse::<usize>().unwrap() > 2000
+        })
+        .map(|(_, line)| line)
+        .collect();
+    assert_eq!(
+        expected_rejections,
+        rejections.lines().collect::<Vec<&str>>()
+    );
+    remove_sqlite_database(&path);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_store_can_be_queried_with_sql() {
+    let path = std::env::temp_dir().join("transactions_sqlite_query_test.db");
+    remove_sqlite_database(&path);
+    let config = Config {
+        store: StoreConfig::Sqlite { path: path.clone() },
+        ..Config::default()
+    };
+    process_with_every_output(
+        "type       ,client ,tx , amount
+         deposit    ,1      ,1  , 10.5
+         deposit    ,2      ,2  , 2.0
+         dispute    ,1      ,1
+         withdrawal ,2      ,3  , 0.5",
+        &config,
+    );
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let balances: Vec<(u16, f64, f64, f64, bool)> = connection
+        .prepare("SELECT client, available, held, total, locked FROM balances ORDER BY client")
+        .unwrap()
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        vec![(1, 0.0, 10.5, 10.5, false), (2, 1.5, 0.0, 1.5, false)],
+        balances
+    );
+    let disputed: Vec<u32> = connection
+        .prepare("SELECT tx FROM deposits WHERE disputed")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(vec![1], disputed);
+    drop(connection);
+    remove_sqlite_database(&path);
+}
+
+/// Generates a valid input with deposits, withdrawals, duplicated Transaction IDs, timestamps and disputes.
+/// The disputes always reference the transactions of the same client, since the rejection reason of the others
+/// might be different in parallel mode.
+fn generate_input(line_count: u32) -> String {
+    // a simple linear congruential generator, so the input is the same in every run
+    let mut seed: u64 = 42;
+    let mut random = move |max: u32| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((seed >> 33) % max as u64) as u32
+    };
+    let mut input = String::from("type,client,tx,amount,timestamp\n");
+    for tx in 1..=line_count {
+        let timestamp = tx / 10;
+        let line = match random(10) {
+            0..=4 => format!("deposit,{},{},{}.5,{}", tx % 16, tx, random(100), timestamp),
+            5..=6 => format!(
+                "withdrawal,{},{},{},{}",
+                tx % 16,
+                tx,
+                random(200),
+                timestamp
+            ),
+            7 => {
+                let duplicated_tx = random(tx) + 1;
+                format!(
+                    "deposit,{},{},1,{}",
+                    duplicated_tx % 16,
+                    duplicated_tx,
+                    timestamp
+                )
+            }
+            typ => {
+                let typ =
+                    ["dispute", "resolve", "chargeback"][typ as usize - 8 + random(2) as usize];
+                let disputed_tx = random(tx + 10) + 1;
+                format!(
+                    "{},{},{},,{}",
+                    typ,
+                    disputed_tx % 16,
+                    disputed_tx,
+                    timestamp
+                )
+            }
+        };
+        input.push_str(&line);
+        input.push('\n');
+    }
+    input
+}
+
+/// Removes the database together with its journal files
+#[cfg(feature = "sqlite")]
+fn remove_sqlite_database(path: &std::path::Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut filename = path.as_os_str().to_owned();
+        filename.push(suffix);
+        let _ = std::fs::remove_file(filename);
+    }
+}
+
+fn generate(config: GeneratorConfig) -> String {
+    let mut input = String::new();
+    InputGenerator::new(config)
+        .read_to_string(&mut input)
+        .unwrap();
+    input
+}
+
+/// Returns the client states, the ledger and the rejections
+fn process_with_every_output(input: &str, config: &Config) -> (String, String, String) {
+    let mut output = Vec::new();
+    let mut ledger = Vec::new();
+    let mut rejections = Vec::new();
+    process_input_then_write_outputs(
+        input.as_bytes(),
+        &mut output,
+        Outputs {
+            ledger: Some(LedgerOutput {
+                writer: &mut ledger,
+                format: LedgerFormat::Csv,
+                client_id: None,
+            }),
+            rejections: Some(&mut rejections),
+            ..Outputs::default()
+        },
+        config,
+    );
+    (
+        String::from_utf8(output).unwrap(),
+        String::from_utf8(ledger).unwrap(),
+        String::from_utf8(rejections).unwrap(),
+    )
+}
+
+/// Same as `process_with_every_output` without the ledger, but the processing is resumed from the checkpoint.
+fn resume_with_every_output(input: &str, config: &Config) -> (String, String) {
+    let mut output = Vec::new();
+    let mut rejections = Vec::new();
+    resume_input_then_write_outputs(
+        std::io::Cursor::new(input.as_bytes()),
+        &mut output,
+        Outputs {
+            rejections: Some(&mut rejections),
+            ..Outputs::default()
+        },
+        config,
+    );
+    (
+        String::from_utf8(output).unwrap(),
+        String::from_utf8(rejections).unwrap(),
+    )
+}
+
+/// Runs `f` and returns the log records emitted by it on the current thread,
+/// formatted as the level and the `key=value` pairs.
+fn capture_logs(f: impl FnOnce()) -> Vec<String> {
+    thread_local! {
+        static CAPTURED: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+    }
+
+    struct CapturingLogger;
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED.with_borrow_mut(|captured| {
+                if let Some(captured) = captured {
+                    let mut line = record.level().to_string();
+                    let _ = record.key_values().visit(&mut KeyValues(&mut line));
+                    captured.push(line);
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    struct KeyValues<'a>(&'a mut String);
+
+    impl<'kvs> log::kv::VisitSource<'kvs> for KeyValues<'_> {
+        fn visit_pair(
+            &mut self,
+            key: log::kv::Key<'kvs>,
+            value: log::kv::Value<'kvs>,
+        ) -> Result<(), log::kv::Error> {
+            self.0.push_str(&format!(" {}={}", key, value));
+            Ok(())
+        }
+    }
+
+    // the logger is global, so it is installed only once, the other tests just do not capture
+    let _ = log::set_logger(&CapturingLogger);
+    log::set_max_level(log::LevelFilter::Info);
+    CAPTURED.with_borrow_mut(|captured| *captured = Some(Vec::new()));
+    f();
+    CAPTURED.with_borrow_mut(|captured| captured.take().unwrap_or_default())
+}
+
+/// Processes the input, and returns only the summary in the given format
+fn write_summary(input: &str, format: SummaryFormat, config: &Config) -> String {
+    let mut summary = Vec::new();
+    process_input_then_write_outputs(
+        input.as_bytes(),
+        std::io::sink(),
+        Outputs {
+            summary: Some(SummaryOutput {
+                writer: &mut summary,
+                format,
+            }),
+            ..Outputs::default()
+        },
+        config,
+    );
+    String::from_utf8(summary).unwrap()
+}
+
+/// A writer with the content already written into it, in the form `assert_output_eq` expects
+fn to_writer(content: &str) -> BufWriter<Vec<u8>> {
+    let mut writer = BufWriter::new(Vec::new());
+    writer.write_all(content.as_bytes()).unwrap();
+    writer
+}
+
+/// Sends a single HTTP request, and returns the status code and the body of the response.
+fn http_request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &str,
+) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        content_type,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_owned())
+}
+
 fn assert_csv_eq(input: &str, expected: &str) {
+    assert_csv_eq_with_config(input, expected, &Config::default());
+}
+
+/// The client states are written unsorted, and compared independently of their order.
+fn assert_csv_eq_with_config(input: &str, expected: &str, config: &Config) {
+    let mut actual_output = BufWriter::new(Vec::new());
+    process_input_with_config_then_write_output(
+        input.as_bytes(),
+        &mut actual_output,
+        &Config {
+            unsorted: true,
+            ..config.clone()
+        },
+    );
+    assert_csv_rows_eq(actual_output, expected);
+}
+
+fn assert_query_csv_eq(input: &str, as_of: AsOf, client_id: Option<u16>, expected: &str) {
+    let mut actual_output = BufWriter::new(Vec::new());
+    query_input_as_of_then_write_output(
+        input.as_bytes(),
+        as_of,
+        client_id,
+        &mut actual_output,
+        &Config {
+            unsorted: true,
+            ..Config::default()
+        },
+    );
+    assert_csv_rows_eq(actual_output, expected);
+}
+
+fn assert_reconciliation_eq(
+    input: &str,
+    expected_balances: &str,
+    expected_report: &str,
+    expected_result: bool,
+) {
+    let mut actual_report = BufWriter::new(Vec::new());
+    let reconciled = reconcile_input_then_write_report(
+        input.as_bytes(),
+        expected_balances.as_bytes(),
+        &mut actual_report,
+        &Config::default(),
+    );
+    assert_output_eq(actual_report, expected_report);
+    assert_eq!(expected_result, reconciled);
+}
+
+fn assert_ledger_eq(input: &str, client_id: Option<u16>, expected: &str) {
+    let mut output = Vec::new();
+    let mut actual_ledger = BufWriter::new(Vec::new());
+    process_input_then_write_outputs(
+        input.as_bytes(),
+        &mut output,
+        Outputs {
+            ledger: Some(LedgerOutput {
+                writer: &mut actual_ledger,
+                format: LedgerFormat::Csv,
+                client_id,
+            }),
+            ..Outputs::default()
+        },
+        &Config::default(),
+    );
+    assert_output_eq(actual_ledger, expected);
+}
+
+fn assert_rejections_eq(input: &str, expected: &str, expected_rejections: &str, config: &Config) {
     let mut actual_output = BufWriter::new(Vec::new());
-    process_input_then_write_output(input.as_bytes(), &mut actual_output);
+    let mut actual_rejections = BufWriter::new(Vec::new());
+    process_input_then_write_outputs(
+        input.as_bytes(),
+        &mut actual_output,
+        Outputs {
+            rejections: Some(&mut actual_rejections),
+            ..Outputs::default()
+        },
+        &Config {
+            unsorted: true,
+            ..config.clone()
+        },
+    );
+    assert_csv_rows_eq(actual_output, expected);
+    assert_output_eq(actual_rejections, expected_rejections);
+}
+
+fn assert_changes_eq(input: &str, interval: Option<usize>, expected_changes: &str) {
+    let mut output = Vec::new();
+    let mut actual_changes = BufWriter::new(Vec::new());
+    process_input_then_write_outputs(
+        input.as_bytes(),
+        &mut output,
+        Outputs {
+            changes: Some(ChangesOutput {
+                writer: &mut actual_changes,
+                interval,
+            }),
+            ..Outputs::default()
+        },
+        &Config::default(),
+    );
+    assert_output_eq(actual_changes, expected_changes);
+}
+
+/// Compares the csv outputs as multisets of rows, so only the header has to be the first line in both of them,
+/// the order of the other rows does not matter.
+/// The whitespaces are removed and the numbers are normalised, so e.g. `1.0` and `1` are equal.
+fn assert_csv_rows_eq(actual_output: BufWriter<Vec<u8>>, expected: &str) {
+    fn normalize_cell(cell: &str) -> String {
+        let cell: String = cell.chars().filter(|c| !c.is_whitespace()).collect();
+        let is_number = !cell.is_empty() && cell.chars().all(|c| c.is_ascii_digit() || c == '.');
+        match Amount::parse(&cell) {
+            Some(amount) if is_number => amount.to_string(),
+            _ => cell,
+        }
+    }
+    fn parse_rows(csv: &str) -> (Vec<String>, Vec<Vec<String>>) {
+        let mut rows = csv
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split(',').map(normalize_cell).collect::<Vec<String>>());
+        let header = rows.next().unwrap_or_default();
+        let mut rows: Vec<Vec<String>> = rows.collect();
+        rows.sort();
+        (header, rows)
+    }
+    let actual_output = String::from_utf8(actual_output.into_inner().unwrap()).unwrap();
+    let (expected_header, expected_rows) = parse_rows(expected);
+    let (actual_header, actual_rows) = parse_rows(&actual_output);
+    assert_eq!(
+        expected_header, actual_header,
+        "Mismatch in the header\nActual:\n{}",
+        actual_output
+    );
+    if expected_rows != actual_rows {
+        let mut unexpected_rows = actual_rows.clone();
+        let mut missing_rows = Vec::new();
+        for row in expected_rows {
+            match unexpected_rows.iter().position(|it| *it == row) {
+                Some(index) => {
+                    unexpected_rows.remove(index);
+                }
+                None => missing_rows.push(row),
+            }
+        }
+        panic!(
+            "Missing rows: {:?}\nUnexpected rows: {:?}\nActual:\n{}",
+            missing_rows, unexpected_rows, actual_output
+        );
+    }
+}
 
+fn assert_output_eq(actual_output: BufWriter<Vec<u8>>, expected: &str) {
     fn remove_whitespace(s: &str) -> String {
         s.chars().filter(|c| !c.is_whitespace()).collect()
     }